@@ -0,0 +1,84 @@
+//! Compares drawing lots of sprites through `SpriteRenderer` and `InstancedSpriteRenderer`.
+//! Renders with the headless renderer, so it needs a Vulkan device but no window.
+//!
+//! `cargo run --release --example sprite_bench [sprite counts...]`
+
+use std::env;
+use std::time::{Duration, Instant};
+
+use vulkan_test::render::camera::{Camera, DEFAULT_RESOLUTION};
+use vulkan_test::render::display::FrameBuilder;
+use vulkan_test::render::headless::HeadlessRenderer;
+
+const WARMUP_FRAMES: u32 = 10;
+const MEASURED_FRAMES: u32 = 100;
+const DEFAULT_COUNTS: [usize; 4] = [1_000, 10_000, 50_000, 100_000];
+
+#[derive(Debug, Clone, Copy)]
+enum Path {
+	Sprites,
+	Instanced,
+}
+
+/// Bullet-sized squares scattered over the screen. Always the same ones, so both paths draw the same thing.
+fn build_frame(path: Path, count: usize) -> FrameBuilder {
+	let mut frame = FrameBuilder::new(0.0);
+	// Simple LCG, since all this needs is something that isn't a straight line
+	let mut seed = 12345_u32;
+	let mut next = |max: u32| {
+		seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+		(seed >> 8) % max
+	};
+	for _ in 0..count {
+		let x = next(DEFAULT_RESOLUTION[0]) as i32;
+		let y = next(DEFAULT_RESOLUTION[1]) as i32;
+		let color = [next(256) as u8, next(256) as u8, 255, 255];
+		match path {
+			Path::Sprites => frame.get_sprite_renderer().draw_rect(x, y, 4, 4, color),
+			Path::Instanced => frame.get_instanced_renderer().draw_rect(x, y, 4, 4, color),
+		}
+	}
+	frame
+}
+
+/// Average time to build a frame, and to build and render it (waiting for the GPU to finish).
+fn measure(renderer: &mut HeadlessRenderer, camera: &Camera, path: Path, count: usize) -> (Duration, Duration) {
+	for _ in 0..WARMUP_FRAMES {
+		renderer.render(build_frame(path, count), camera).expect("Failed to render frame");
+	}
+	let mut build_time = Duration::default();
+	let mut total_time = Duration::default();
+	for _ in 0..MEASURED_FRAMES {
+		let start = Instant::now();
+		let frame = build_frame(path, count);
+		build_time += start.elapsed();
+		renderer.render(frame, camera).expect("Failed to render frame");
+		total_time += start.elapsed();
+	}
+	(build_time / MEASURED_FRAMES, total_time / MEASURED_FRAMES)
+}
+
+fn main() {
+	let counts: Vec<usize> = env::args().skip(1)
+		.map(|arg| arg.parse().expect("Sprite counts must be whole numbers"))
+		.collect();
+	let counts = if counts.is_empty() { DEFAULT_COUNTS.to_vec() } else { counts };
+
+	let mut renderer = match HeadlessRenderer::init() {
+		Ok(renderer) => renderer,
+		Err(e) => {
+			eprintln!("{}", e);
+			std::process::exit(1);
+		},
+	};
+	let camera = Camera::new();
+
+	println!("{:>10} {:>10} {:>12} {:>12}", "sprites", "path", "build (ms)", "frame (ms)");
+	for &count in &counts {
+		for &path in &[Path::Sprites, Path::Instanced] {
+			let (build_time, total_time) = measure(&mut renderer, &camera, path, count);
+			println!("{:>10} {:>10} {:>12.3} {:>12.3}",
+				count, format!("{:?}", path), build_time.as_secs_f64() * 1000.0, total_time.as_secs_f64() * 1000.0);
+		}
+	}
+}
@@ -0,0 +1,31 @@
+#version 450
+layout(location = 0) out vec4 f_color;
+
+layout(push_constant) uniform PushConstants {
+	float threshold;
+	float intensity;
+	int radius;
+} pushConstants;
+
+layout(binding = 0) uniform sampler2D source;
+
+// Single pass 2D gaussian over the bright parts of the image.
+// Brute force, but at our resolution that's fine.
+void main() {
+	ivec2 pixel = ivec2(gl_FragCoord.xy);
+	ivec2 maxPixel = textureSize(source, 0) - 1;
+	int radius = pushConstants.radius;
+	float sigma = max(float(radius) / 2.0, 0.5);
+	vec3 sum = vec3(0.0);
+	float weightSum = 0.0;
+	for (int dy = -radius; dy <= radius; dy++) {
+		for (int dx = -radius; dx <= radius; dx++) {
+			float weight = exp(-float(dx*dx + dy*dy) / (2.0 * sigma * sigma));
+			vec3 c = texelFetch(source, clamp(pixel + ivec2(dx, dy), ivec2(0), maxPixel), 0).rgb;
+			sum += max(c - vec3(pushConstants.threshold), vec3(0.0)) * weight;
+			weightSum += weight;
+		}
+	}
+	vec4 color = texelFetch(source, pixel, 0);
+	f_color = vec4(color.rgb + sum / weightSum * pushConstants.intensity, color.a);
+}
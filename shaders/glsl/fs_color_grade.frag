@@ -0,0 +1,36 @@
+#version 450
+layout(location = 0) out vec4 f_color;
+
+layout(push_constant) uniform PushConstants {
+	float intensity;
+} pushConstants;
+
+layout(binding = 0) uniform sampler2D source;
+// N*N x N strip of N blue slices, each with red along X and green along Y.
+// Stored and indexed in sRGB, which is how LUTs are normally authored.
+layout(binding = 1) uniform sampler2D lut;
+
+vec3 toSrgb(vec3 c) {
+	return mix(c * 12.92, 1.055 * pow(c, vec3(1.0/2.4)) - 0.055, step(vec3(0.0031308), c));
+}
+
+vec3 toLinear(vec3 c) {
+	return mix(c / 12.92, pow((c + 0.055) / 1.055, vec3(2.4)), step(vec3(0.04045), c));
+}
+
+void main() {
+	vec4 color = texelFetch(source, ivec2(gl_FragCoord.xy), 0);
+	vec3 srgb = toSrgb(clamp(color.rgb, 0.0, 1.0));
+
+	float size = float(textureSize(lut, 0).y);
+	float slice = srgb.b * (size - 1.0);
+	float slice0 = floor(slice);
+	float slice1 = min(slice0 + 1.0, size - 1.0);
+	// Half texel offsets so the hardware's bilinear filtering doesn't bleed between slices
+	vec2 inSlice = (srgb.rg * (size - 1.0) + 0.5) / vec2(size * size, size);
+	vec3 graded0 = texture(lut, inSlice + vec2(slice0 / size, 0.0)).rgb;
+	vec3 graded1 = texture(lut, inSlice + vec2(slice1 / size, 0.0)).rgb;
+	vec3 graded = toLinear(mix(graded0, graded1, slice - slice0));
+
+	f_color = vec4(mix(color.rgb, graded, pushConstants.intensity), color.a);
+}
@@ -0,0 +1,10 @@
+#version 450
+layout(location = 0) in vec4 fragColor;
+
+layout(location = 0) out vec4 f_color;
+
+void main() {
+	// Colors are given in sRGB, but the overlay image is linear
+	vec3 linear = pow(fragColor.rgb, vec3(2.2));
+	f_color = vec4(linear * fragColor.a, fragColor.a);
+}
@@ -0,0 +1,83 @@
+#version 450
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D albedo;
+// r = emissive, g = unlit
+layout(set = 0, binding = 1) uniform sampler2D emissive;
+layout(set = 0, binding = 2) uniform sampler2D occlusion;
+
+// Must match MAX_LIGHTS in lighting.rs
+#define MAX_LIGHTS 32
+// Longest shadow ray we'll march, in pixels
+#define MAX_SHADOW_STEPS 512
+
+layout(set = 1, binding = 0) uniform LightData {
+	// w unused
+	vec4 ambient;
+	// xy = position in pixels (Y+ down), z = radius, w = intensity
+	vec4 light_pos[MAX_LIGHTS];
+	// w unused
+	vec4 light_color[MAX_LIGHTS];
+	// xy = direction (Y+ down), z = cos of half the cone angle, w = 1 for cone lights, 0 for point lights
+	vec4 light_cone[MAX_LIGHTS];
+	int light_count;
+} lights;
+
+bool occluded(ivec2 p) {
+	return texelFetch(occlusion, p, 0).r > 0.5;
+}
+
+// Marches one pixel at a time towards the light, giving hard pixel-aligned shadows.
+// Occluders are lit on the side facing the light: occluded pixels at the start of the ray are skipped.
+float shadow(vec2 from, vec2 to) {
+	vec2 delta = to - from;
+	float len = length(delta);
+	int steps = min(int(ceil(len)), MAX_SHADOW_STEPS);
+	vec2 stepDir = delta / max(len, 1.0);
+	ivec2 size = textureSize(occlusion, 0);
+	bool leaving = true;
+	for (int i = 0; i < steps; i++) {
+		ivec2 p = ivec2(floor(from + stepDir * float(i)));
+		if (p.x < 0 || p.y < 0 || p.x >= size.x || p.y >= size.y) {
+			// Nothing offscreen casts shadows
+			break;
+		}
+		bool occ = occluded(p);
+		if (leaving) {
+			leaving = occ;
+		} else if (occ) {
+			return 0.0;
+		}
+	}
+	return 1.0;
+}
+
+void main() {
+	ivec2 pixel = ivec2(gl_FragCoord.xy);
+	vec4 color = texelFetch(albedo, pixel, 0);
+	vec2 emissive_unlit = texelFetch(emissive, pixel, 0).rg;
+	// Unlit pixels (e.g. UI) pass through unchanged
+	if (emissive_unlit.g >= 1.0) {
+		f_color = color;
+		return;
+	}
+	vec3 light = lights.ambient.rgb + vec3(emissive_unlit.r);
+
+	for (int i = 0; i < lights.light_count; i++) {
+		vec4 pos = lights.light_pos[i];
+		vec2 toPixel = gl_FragCoord.xy - pos.xy;
+		float dist = length(toPixel);
+		if (dist >= pos.z) {
+			continue;
+		}
+		vec4 cone = lights.light_cone[i];
+		if (cone.w > 0.5 && dist > 0.0 && dot(toPixel / dist, cone.xy) < cone.z) {
+			continue;
+		}
+		float attenuation = 1.0 - dist / pos.z;
+		attenuation *= attenuation;
+		light += lights.light_color[i].rgb * pos.w * attenuation * shadow(gl_FragCoord.xy, pos.xy);
+	}
+
+	f_color = vec4(color.rgb * mix(light, vec3(1.0), emissive_unlit.g), color.a);
+}
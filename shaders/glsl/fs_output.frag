@@ -0,0 +1,43 @@
+#version 450
+layout(location = 0) in vec2 fragTexCoord;
+
+layout(location = 0) out vec4 f_color;
+
+layout(push_constant) uniform PushConstants {
+	// Which part of the intermediate image is visible, in texture coordinates
+	vec2 uv_offset;
+	vec2 uv_scale;
+	float time;
+	// CRT effect; has to happen here rather than in the post-processing chain,
+	// since scanlines need to know where each output pixel is within a source pixel.
+	// 0 turns each part off.
+	float scanline_intensity;
+	float curvature;
+} pushConstants;
+
+layout(binding = 0) uniform sampler2D texSampler;
+// Debug shapes, premultiplied. Composited after the CRT effect so they stay easy to read.
+layout(binding = 1) uniform sampler2D overlay;
+
+void main() {
+	// f_color = vec4(sin(pushConstants.time/4.0), 0.25, 1.0, 1.0);
+	vec2 uv = fragTexCoord;
+	if (pushConstants.curvature > 0.0) {
+		vec2 centered = uv * 2.0 - 1.0;
+		centered *= 1.0 + pushConstants.curvature * dot(centered.yx, centered.yx);
+		uv = centered * 0.5 + 0.5;
+		if (uv.x < 0.0 || uv.y < 0.0 || uv.x > 1.0 || uv.y > 1.0) {
+			f_color = vec4(0.0, 0.0, 0.0, 1.0);
+			return;
+		}
+	}
+	uv = uv * pushConstants.uv_scale + pushConstants.uv_offset;
+	vec4 color = texture(texSampler, uv);
+	if (pushConstants.scanline_intensity > 0.0) {
+		float row = fract(uv.y * float(textureSize(texSampler, 0).y));
+		color.rgb *= mix(1.0, sin(row * 3.14159265), pushConstants.scanline_intensity);
+	}
+	vec4 debug = texture(overlay, uv);
+	color.rgb = color.rgb * (1.0 - debug.a) + debug.rgb;
+	f_color = color;
+}
@@ -0,0 +1,69 @@
+#version 450
+layout(location = 0) in vec2 fragTexCoord;
+layout(location = 1) in vec4 fragTint;
+layout(location = 2) flat in uint fragParams;
+
+layout(location = 0) out vec4 f_color;
+layout(location = 1) out vec4 f_emissive;
+layout(location = 2) out vec4 f_occlusion;
+
+layout(set = 0, binding = 0) uniform sampler2D atlas;
+
+// One palette per row, one color per texel
+layout(set = 1, binding = 0) uniform sampler2D palettes;
+
+// Must match MAX_PALETTE_CYCLES in palette.rs
+#define MAX_PALETTE_CYCLES 16
+
+layout(set = 1, binding = 1) uniform PaletteCycles {
+	// x = palette, y = first index, z = length, w = how far the colors have moved along
+	ivec4 cycles[MAX_PALETTE_CYCLES];
+	int cycle_count;
+} palette_cycles;
+
+// Must match the flags in display.rs
+const uint FLAG_OCCLUDER = 1u;
+const uint FLAG_INVISIBLE = 2u;
+const uint FLAG_INDEXED = 4u;
+const uint FLAG_UNLIT = 8u;
+
+// Indexed sprites keep the palette index in alpha; see IndexedImage
+vec4 palette_color(float index_alpha, int palette) {
+	int index = int(round(index_alpha * 255.0));
+	for (int i = 0; i < palette_cycles.cycle_count; i++) {
+		ivec4 cycle = palette_cycles.cycles[i];
+		if (cycle.x == palette && index >= cycle.y && index < cycle.y + cycle.z) {
+			index = cycle.y + (index - cycle.y + cycle.z - cycle.w) % cycle.z;
+		}
+	}
+	if (index == 0) {
+		return vec4(0.0);
+	}
+	int row = min(palette, textureSize(palettes, 0).y - 1);
+	return texelFetch(palettes, ivec2(index, row), 0);
+}
+
+void main() {
+	vec4 texel = texture(atlas, fragTexCoord);
+	float emissive = float(fragParams & 0xFFu) / 255.0;
+	uint flags = (fragParams >> 8) & 0xFFu;
+	int palette = int((fragParams >> 16) & 0xFFu);
+	float flash = float(fragParams >> 24) / 255.0;
+	if ((flags & FLAG_INDEXED) != 0u) {
+		texel = palette_color(texel.a, palette);
+	}
+	vec4 color = texel * fragTint;
+	color.rgb = mix(color.rgb, vec3(1.0), flash);
+	float occlusion = (flags & FLAG_OCCLUDER) != 0u && color.a > 0.5 ? 1.0 : 0.0;
+	float unlit = (flags & FLAG_UNLIT) != 0u ? 1.0 : 0.0;
+	if ((flags & FLAG_INVISIBLE) != 0u) {
+		color = vec4(0.0);
+		emissive = 0.0;
+	}
+	// Premultiplied alpha; see RenderData::attachment_blend
+	f_color = vec4(color.rgb * color.a, color.a);
+	// Unlit goes in green, always alpha blended so that translucent unlit sprites are only partly unlit
+	f_emissive = vec4(emissive, unlit, 0.0, 1.0) * color.a;
+	// Blended with max whatever the blend mode, so occlusion only ever accumulates, regardless of draw order
+	f_occlusion = vec4(occlusion);
+}
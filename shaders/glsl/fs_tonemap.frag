@@ -0,0 +1,21 @@
+#version 450
+layout(location = 0) out vec4 f_color;
+
+layout(push_constant) uniform PushConstants {
+	float exposure;
+	// 0 = Reinhard, 1 = ACES (Narkowicz's fit)
+	int tonemap_operator;
+} pushConstants;
+
+layout(binding = 0) uniform sampler2D source;
+
+void main() {
+	vec4 color = texelFetch(source, ivec2(gl_FragCoord.xy), 0);
+	vec3 c = color.rgb * pushConstants.exposure;
+	if (pushConstants.tonemap_operator == 0) {
+		c = c / (c + vec3(1.0));
+	} else {
+		c = clamp((c * (2.51 * c + 0.03)) / (c * (2.43 * c + 0.59) + 0.14), 0.0, 1.0);
+	}
+	f_color = vec4(c, color.a);
+}
@@ -0,0 +1,22 @@
+#version 450
+layout(location = 0) in vec2 fragTexCoord;
+
+layout(location = 0) out vec4 f_color;
+
+layout(push_constant) uniform PushConstants {
+	vec4 color;
+	float intensity;
+	// Distance from the center (0 to 1 at the corners) where darkening starts
+	float radius;
+	float softness;
+} pushConstants;
+
+layout(binding = 0) uniform sampler2D source;
+
+void main() {
+	vec4 color = texelFetch(source, ivec2(gl_FragCoord.xy), 0);
+	// sqrt(2) so the corners are at distance 1
+	float dist = length(fragTexCoord - vec2(0.5)) * 1.41421356;
+	float amount = smoothstep(pushConstants.radius, pushConstants.radius + pushConstants.softness, dist);
+	f_color = vec4(mix(color.rgb, pushConstants.color.rgb, amount * pushConstants.intensity), color.a);
+}
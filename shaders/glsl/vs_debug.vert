@@ -0,0 +1,12 @@
+#version 450
+// Already in normalized device coordinates
+layout(location = 0) in vec2 position;
+// Packed RGBA8, like sprite tints
+layout(location = 1) in uint color;
+
+layout(location = 0) out vec4 fragColor;
+
+void main() {
+	gl_Position = vec4(position, 0.0, 1.0);
+	fragColor = unpackUnorm4x8(color);
+}
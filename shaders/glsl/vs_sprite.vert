@@ -0,0 +1,25 @@
+#version 450
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec2 uv;
+// Packed RGBA8, since vertex attribute formats have to match the shader's input type
+layout(location = 2) in uint tint;
+// Packed bytes: emissive, flags, palette, flash
+layout(location = 3) in uint params;
+
+layout(location = 0) out vec2 fragTexCoord;
+layout(location = 1) out vec4 fragTint;
+layout(location = 2) flat out uint fragParams;
+
+layout(push_constant) uniform PushConstants {
+	// not needed but I left it here because I didn't feel like fiddling with offsets
+	float time;
+	mat4 transform;
+} pushConstants;
+
+void main() {
+	vec4 pos4 = vec4(position.xyz, 1.0);
+	gl_Position = pushConstants.transform*pos4;
+	fragTexCoord = uv;
+	fragTint = unpackUnorm4x8(tint);
+	fragParams = params;
+}
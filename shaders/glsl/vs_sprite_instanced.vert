@@ -0,0 +1,28 @@
+#version 450
+// Corner of the quad, from (0, 0) to (1, 1)
+layout(location = 0) in vec2 position;
+// The rest are per instance; see InstanceSprite
+layout(location = 1) in vec2 origin;
+layout(location = 2) in vec2 size;
+layout(location = 3) in vec2 uv_min;
+layout(location = 4) in vec2 uv_max;
+layout(location = 5) in uint tint;
+layout(location = 6) in uint params;
+
+layout(location = 0) out vec2 fragTexCoord;
+layout(location = 1) out vec4 fragTint;
+layout(location = 2) flat out uint fragParams;
+
+// Must match vs_sprite, since they're drawn with the same push constants
+layout(push_constant) uniform PushConstants {
+	float time;
+	mat4 transform;
+} pushConstants;
+
+void main() {
+	gl_Position = pushConstants.transform*vec4(origin + position*size, 0.0, 1.0);
+	// Y+ is up in world space, but V+ is down in the texture, hence the V flip
+	fragTexCoord = mix(vec2(uv_min.x, uv_max.y), vec2(uv_max.x, uv_min.y), position);
+	fragTint = unpackUnorm4x8(tint);
+	fragParams = params;
+}
@@ -1,84 +1,154 @@
-pub mod vs {
+use std::fs;
+use std::path::Path;
+
+use shaderc::{CompileOptions, Compiler, ShaderKind, TargetEnv};
+
+/// What every shader's `SOURCE_PATH` is relative to: this crate's directory on the machine that built it.
+/// Hot reloading reads sources from here, so it's only useful during development.
+pub const SOURCE_ROOT: &str = env!("CARGO_MANIFEST_DIR");
+
+/// Vulkan 1.0, same as vulkano_shaders uses
+const ENV_VULKAN_VERSION: u32 = (1 << 22) | (1 << 12);
+
+/// Adds what hot reloading needs to a shader module. `$path` has to be the same as the `shader!` path.
+macro_rules! reloadable {
+	($path:literal) => {
+		/// Where this shader's source is, relative to `SOURCE_ROOT`
+		pub const SOURCE_PATH: &str = $path;
+
+		// vulkano_shaders doesn't tell cargo about the file, so edits wouldn't trigger a rebuild without this
+		const _: &str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/", $path));
+
+		impl Shader {
+			/// Like `load`, but with SPIR-V compiled at runtime by `compile`.
+			///
+			/// # Safety
+			///
+			/// The SPIR-V has to have the same interface as the built-in version (inputs, outputs, descriptors and
+			/// push constants), since pipelines are still built with the layout that was generated at compile time.
+			pub unsafe fn from_words(
+				device: ::std::sync::Arc<::vulkano::device::Device>,
+				words: &[u32],
+			) -> Result<Shader, ::vulkano::OomError> {
+				Ok(Shader {
+					shader: ::vulkano::pipeline::shader::ShaderModule::from_words(device, words)?,
+				})
+			}
+		}
+	};
+}
+
+/// Compiles a shader source file to SPIR-V, picking the stage from its extension (`.vert` or `.frag`).
+/// Errors are compiler output, ready to be printed.
+pub fn compile(path: &Path) -> Result<Vec<u32>, String> {
+	let kind = match path.extension().and_then(|extension| extension.to_str()) {
+		Some("vert") => ShaderKind::Vertex,
+		Some("frag") => ShaderKind::Fragment,
+		_ => return Err(format!("{:?}: unknown shader stage", path)),
+	};
+	let source = fs::read_to_string(path).map_err(|e| format!("{:?}: {}", path, e))?;
+
+	let mut compiler = Compiler::new().ok_or("Failed to create GLSL compiler")?;
+	let mut options = CompileOptions::new().ok_or("Failed to create compile options")?;
+	options.set_target_env(TargetEnv::Vulkan, ENV_VULKAN_VERSION);
+	let name = path.to_string_lossy();
+	let artifact = compiler.compile_into_spirv(&source, kind, &name, "main", Some(&options))
+		.map_err(|e| e.to_string())?;
+	Ok(artifact.as_binary().to_vec())
+}
+
+pub mod vs_sprite {
 	vulkano_shaders::shader! {
 		ty: "vertex",
-		src: "\
-#version 450
-layout(location = 0) in vec3 position;
-
-layout(location = 0) out vec2 fragTexCoord;
-layout(location = 1) out vec3 color;
-
-layout(push_constant) uniform PushConstants {
-	// not needed but I left it here because I didn't feel like fiddling with offsets
-	float time;
-	mat4 transform;
-} pushConstants;
-
-void main() {
-	vec4 pos4 = vec4(position.xyz, 1.0);
-	vec4 transformed_pos = pushConstants.transform*pos4;
-	gl_Position = transformed_pos;
-	fragTexCoord = position.xy;
-	color = vec3(transformed_pos.xy, 1.0-(transformed_pos.x + transformed_pos.y)/2.0);
-}"
+		path: "glsl/vs_sprite.vert"
 	}
+	reloadable!("glsl/vs_sprite.vert");
+}
+
+pub mod vs_sprite_instanced {
+	vulkano_shaders::shader! {
+		ty: "vertex",
+		path: "glsl/vs_sprite_instanced.vert"
+	}
+	reloadable!("glsl/vs_sprite_instanced.vert");
 }
 
 pub mod vs_output {
 	vulkano_shaders::shader! {
 		ty: "vertex",
-		src: "\
-#version 450
-layout(location = 0) in vec2 position;
+		path: "glsl/vs_output.vert"
+	}
+	reloadable!("glsl/vs_output.vert");
+}
 
-layout(location = 0) out vec2 fragTexCoord;
+pub mod fs_sprite {
+	vulkano_shaders::shader! {
+		ty: "fragment",
+		path: "glsl/fs_sprite.frag"
+	}
+	reloadable!("glsl/fs_sprite.frag");
+}
 
-void main() {
-	gl_Position = vec4(position, 0.0, 1.0);
-	fragTexCoord = (position+vec2(1.0, 1.0))/2.0;
-}"
+pub mod fs_lighting {
+	vulkano_shaders::shader! {
+		ty: "fragment",
+		path: "glsl/fs_lighting.frag"
 	}
+	reloadable!("glsl/fs_lighting.frag");
 }
 
-pub mod fs_triangle {
+pub mod fs_bloom {
 	vulkano_shaders::shader! {
 		ty: "fragment",
-		src: "\
-#version 450
-layout(location = 0) in vec2 fragTexCoord;
-layout(location = 1) in vec3 color;
+		path: "glsl/fs_bloom.frag"
+	}
+	reloadable!("glsl/fs_bloom.frag");
+}
 
-layout(location = 0) out vec4 f_color;
+pub mod fs_tonemap {
+	vulkano_shaders::shader! {
+		ty: "fragment",
+		path: "glsl/fs_tonemap.frag"
+	}
+	reloadable!("glsl/fs_tonemap.frag");
+}
 
-layout(push_constant) uniform PushConstants {
-	float time;
-} pushConstants;
+pub mod fs_color_grade {
+	vulkano_shaders::shader! {
+		ty: "fragment",
+		path: "glsl/fs_color_grade.frag"
+	}
+	reloadable!("glsl/fs_color_grade.frag");
+}
 
-void main() {
-	// f_color = vec4(sin(pushConstants.time/4.0), 0.25, 1.0, 1.0);
-	f_color = vec4(color, 1.0);
-}"
+pub mod fs_vignette {
+	vulkano_shaders::shader! {
+		ty: "fragment",
+		path: "glsl/fs_vignette.frag"
 	}
+	reloadable!("glsl/fs_vignette.frag");
 }
 
 pub mod fs_output {
 	vulkano_shaders::shader! {
 		ty: "fragment",
-		src: "\
-#version 450
-layout(location = 0) in vec2 fragTexCoord;
-
-layout(location = 0) out vec4 f_color;
-
-layout(push_constant) uniform PushConstants {
-	float time;
-} pushConstants;
+		path: "glsl/fs_output.frag"
+	}
+	reloadable!("glsl/fs_output.frag");
+}
 
-layout(binding = 0) uniform sampler2D texSampler;
+pub mod vs_debug {
+	vulkano_shaders::shader! {
+		ty: "vertex",
+		path: "glsl/vs_debug.vert"
+	}
+	reloadable!("glsl/vs_debug.vert");
+}
 
-void main() {
-	// f_color = vec4(sin(pushConstants.time/4.0), 0.25, 1.0, 1.0);
-	f_color = texture(texSampler, fragTexCoord);
-}"
+pub mod fs_debug {
+	vulkano_shaders::shader! {
+		ty: "fragment",
+		path: "glsl/fs_debug.frag"
 	}
+	reloadable!("glsl/fs_debug.frag");
 }
@@ -1,25 +1,59 @@
+use std::path::Path;
+
 use spin_sleep::LoopHelper;
-use winit::event::{Event, WindowEvent, ElementState};
+use winit::event::{Event, WindowEvent, ElementState, ModifiersState, VirtualKeyCode};
 use winit::event_loop::{ControlFlow, EventLoop};
 
+use vulkan_test::render::capture::{self, CaptureTarget, FrameRecorder, SequenceFormat, SCREENSHOT_DIRECTORY};
 use vulkan_test::render::renderer::Renderer;
-use vulkan_test::util::timing::TickTiming;
+use vulkan_test::render::texture::ImageData;
+use vulkan_test::render::video::{PresentModePreference, VideoSettings, VIDEO_SETTINGS_PATH};
+use vulkan_test::util::timing::{TickTiming, TICKS_PER_SECOND};
 use vulkan_test::game::Game;
 
 fn main() {
 	let events_loop = EventLoop::new();
-	let mut renderer = Renderer::init(&events_loop);
+	let video_settings_path = Path::new(VIDEO_SETTINGS_PATH);
+	let video_settings = VideoSettings::load(video_settings_path).unwrap_or_else(|e| {
+		println!("Using default video settings ({})", e);
+		VideoSettings::default()
+	});
+	let mut renderer = match Renderer::init(&events_loop, video_settings) {
+		Ok(renderer) => renderer,
+		Err(e) => {
+			eprintln!("{}", e);
+			std::process::exit(1);
+		}
+	};
+	renderer.set_title("Vulkan Test");
+	if let Ok(icon) = ImageData::load_png(Path::new("icon.png")) {
+		renderer.set_icon(Some(&icon));
+	}
 	let mut game = Game::new();
+	if let Err(e) = renderer.upload_atlas(game.get_atlas()).and_then(|_| renderer.upload_palettes(game.get_palettes())) {
+		eprintln!("{}", e);
+		std::process::exit(1);
+	}
+	// Edit a shader in shaders/glsl and it's picked up straight away
+	if cfg!(debug_assertions) {
+		renderer.watch_shaders(Path::new(shaders::SOURCE_ROOT));
+	}
 
-	// TODO why does CPU usage get maxed when using FIFO present mode and target rate is above monitor FPS?
 	let mut timer = LoopHelper::builder()
 		.report_interval_s(0.5)
-		.build_with_target_rate(120.0);
-	let mut tick_timer = TickTiming::new(1.0/60.0);
+		.build_with_target_rate(renderer.get_video_settings().target_rate());
+	let mut tick_timer = TickTiming::new(1.0/TICKS_PER_SECOND as f64);
 
 	let mut tick_count = 0_u32;
 	let mut time = 0.0;
 
+	// Alt+Enter toggles fullscreen, F9 cycles the present mode, F10 cycles the FPS cap, F3 toggles debug shapes
+	// F12 takes a screenshot, F11 records the next few seconds to a GIF
+	// The targets F12 asked for, which are kept apart from the recorder's so only they get saved as screenshots
+	let mut screenshot_targets: Vec<CaptureTarget> = Vec::new();
+	let mut recorder: Option<FrameRecorder> = None;
+	let mut modifiers = ModifiersState::empty();
+
 	events_loop.run(move |event, _, control_flow| {
 		// Not used since it means framerate is kept low unless events are occurring
 		// We might want to use it when window is minimized, etc.?
@@ -29,15 +63,74 @@ fn main() {
 		// println!("event {:?}", event);
 		match event {
 			Event::WindowEvent { event: WindowEvent::CloseRequested, .. } => {
+				if let Err(e) = renderer.get_video_settings().save(video_settings_path) {
+					println!("Failed to save video settings: {}", e);
+				}
 				*control_flow = ControlFlow::Exit;
 			},
 			Event::WindowEvent { event: WindowEvent::Resized(size), .. } => {
 				println!("resized {:?}", size);
 				renderer.recreate_swapchain = true;
+				renderer.remember_window_placement();
+			},
+			Event::WindowEvent { event: WindowEvent::Moved(_), .. } => {
+				renderer.remember_window_placement();
+			},
+			Event::WindowEvent { event: WindowEvent::ModifiersChanged(state), .. } => {
+				modifiers = state;
 			},
 			Event::WindowEvent { event: WindowEvent::KeyboardInput {input, .. }, .. } => {
 				println!("{:?}", input);
 				if let Some(key) = input.virtual_keycode {
+					if input.state == ElementState::Pressed {
+						match key {
+							VirtualKeyCode::Return if modifiers.alt() => {
+								let mut settings = renderer.get_video_settings().clone();
+								settings.window.mode = settings.window.mode.toggled();
+								println!("Window mode: {:?}", settings.window.mode);
+								renderer.set_video_settings(settings);
+							},
+							VirtualKeyCode::F3 => {
+								let debug_settings = renderer.get_debug_settings();
+								debug_settings.set_enabled(!debug_settings.is_enabled());
+							},
+							VirtualKeyCode::F9 => {
+								let mut settings = renderer.get_video_settings().clone();
+								settings.present_mode = match settings.present_mode {
+									PresentModePreference::Vsync => PresentModePreference::Mailbox,
+									PresentModePreference::Mailbox => PresentModePreference::Immediate,
+									PresentModePreference::Immediate => PresentModePreference::Vsync,
+								};
+								println!("Present mode: {:?}", settings.present_mode);
+								renderer.set_video_settings(settings);
+							},
+							VirtualKeyCode::F10 => {
+								let mut settings = renderer.get_video_settings().clone();
+								settings.target_fps = match settings.target_fps {
+									None => Some(30.0),
+									Some(fps) if fps < 60.0 => Some(60.0),
+									Some(fps) if fps < 120.0 => Some(120.0),
+									Some(_) => None,
+								};
+								println!("Target FPS: {:?}", settings.target_fps);
+								timer.set_target_rate(settings.target_rate());
+								renderer.set_video_settings(settings);
+							},
+							VirtualKeyCode::F12 => {
+								screenshot_targets = vec![CaptureTarget::Intermediate, CaptureTarget::Output];
+								for &target in &screenshot_targets {
+									renderer.request_capture(target);
+								}
+							},
+							VirtualKeyCode::F11 if recorder.is_none() => {
+								let path = Path::new(SCREENSHOT_DIRECTORY).join(format!("recording-{}.gif", tick_count));
+								println!("Recording to {:?}", path);
+								let ticks = tick_count..tick_count + 5 * TICKS_PER_SECOND;
+								recorder = Some(FrameRecorder::new(&path, CaptureTarget::Intermediate, ticks, SequenceFormat::Gif));
+							},
+							_ => {},
+						}
+					}
 					match input.state {
 						ElementState::Pressed => {
 							game.input.buffer_keydown(key);
@@ -61,7 +154,7 @@ fn main() {
 				time += delta.as_secs_f64();
 
 				if let Some(fps) = timer.report_rate() {
-					println!("FPS: {}", fps);
+					game.set_fps(fps);
 				}
 
 				while tick_timer.try_consume_tick() {
@@ -72,7 +165,46 @@ fn main() {
 					tick_count += 1;
 				}
 
-				game.draw_frame(&mut renderer, tick_count, tick_timer.get_partial_ticks() as f32, time as f32);
+				if let Some(recorder) = &recorder {
+					if recorder.wants_frame(tick_count) {
+						renderer.request_capture(recorder.get_target());
+					}
+				}
+
+				if let Err(e) = game.draw_frame(&mut renderer, tick_count, tick_timer.get_partial_ticks() as f32, time as f32) {
+					eprintln!("{}", e);
+					*control_flow = ControlFlow::Exit;
+					return;
+				}
+
+				let captures = renderer.take_captures();
+				for (target, image) in &captures {
+					if let Some(recorder) = &mut recorder {
+						if recorder.wants_frame(tick_count) && recorder.get_target() == *target {
+							if let Err(e) = recorder.add_frame(image.clone(), time as f32) {
+								println!("Failed to record frame: {}", e);
+							}
+						}
+					}
+					if let Some(index) = screenshot_targets.iter().position(|t| t == target) {
+						screenshot_targets.swap_remove(index);
+						match capture::save_screenshot(image, *target) {
+							Ok(path) => println!("Saved screenshot {:?}", path),
+							Err(e) => println!("Failed to save screenshot: {}", e),
+						}
+					}
+				}
+				// Anything still missing isn't supported, e.g. output captures on some platforms
+				if !captures.is_empty() {
+					screenshot_targets.clear();
+				}
+
+				if recorder.as_ref().is_some_and(|recorder| recorder.is_done(tick_count)) {
+					match recorder.take().unwrap().finish() {
+						Ok(frames) => println!("Finished recording {} frames", frames),
+						Err(e) => println!("Failed to finish recording: {}", e),
+					}
+				}
 			},
 			_ => ()
 		}
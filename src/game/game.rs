@@ -1,9 +1,27 @@
-use hecs::World;
-use crate::render::display::{DisplayElementSquare, FrameBuilder, DisplayElement, DisplayElementComponent};
+use std::sync::Arc;
+
+use hecs::{Entity, World};
+use crate::render::display::{DisplayElementSprite, FrameBuilder, DisplayElement, DisplayElementComponent, DrawOrder, RenderLayer};
+use crate::render::atlas::{Atlas, AtlasBuilder};
+use crate::render::texture::ImageData;
+use crate::render::sprite_sheet::{SpriteSheet, FrameTag, AnimationDirection};
+use crate::render::animation::{AnimatedSprite, AnimationEvent, AnimationSet};
+use crate::render::lighting::{Light, SpriteLighting};
+use crate::render::font::{Font, FontSheet, TextOptions};
+use crate::render::debug_draw::DebugDraw;
+use crate::render::tilemap::{TileDef, Tilemap, Tileset};
+use crate::render::particles::{Curve, ParticleEffect, ParticleEmitter, ParticlePreset};
+use crate::render::display::BlendMode;
+use crate::render::parallax::{ParallaxBackground, ParallaxLayer};
 use crate::render::renderer::Renderer;
-use crate::render::camera::Camera;
+use crate::render::error::RendererError;
+use crate::render::camera::{Camera, DEFAULT_RESOLUTION};
+use crate::render::camera_rig::{Bounds, CameraRig, Follow, LookAhead, Shake};
+use crate::render::view::{LayerMask, RenderTexture, ScreenRect, View};
+use crate::render::palette::{IndexedImage, Palette, PaletteSet, SpriteEffects};
 use crate::util::input::InputMap;
 use winit::event::VirtualKeyCode;
+use cgmath::Vector2;
 
 pub struct Pos {
 	pub x: i32,
@@ -17,6 +35,26 @@ struct Vel {
 
 pub struct Game {
 	level: World,
+	atlas: Atlas,
+	palettes: PaletteSet,
+	/// Animations that looped or finished during the last tick
+	animation_events: Vec<(Entity, AnimationEvent)>,
+	ambient_light: [f32; 3],
+	debug_font: Font,
+	/// Shown in the corner of the screen
+	fps: f64,
+	background: ParallaxBackground,
+	tilemap: Tilemap,
+	/// Debug shapes drawn during ticks, which can stick around for more than one frame
+	debug_draw: DebugDraw,
+	/// Stand-in for a player until there is one, moved around with WASD
+	camera_target: Vector2<f64>,
+	camera_rig: CameraRig,
+	/// What the views are laid out on. The same as the camera's resolution, unless it's split screen
+	screen_resolution: [u32; 2],
+	split_screen: bool,
+	/// Shows the area around the light, on a monitor in the world
+	monitor: RenderTexture,
 	pub camera: Camera, // TODO make this one non-public once we're doing inputs in a non-jank way
 	pub input: InputMap, // TODO probably same for this and add methods on Game to pass through inputs?
 }
@@ -26,35 +64,193 @@ impl Game {
 		let mut camera = Camera::new();
 		let mut input = InputMap::new();
 		let mut level = World::new();
+
+		// No real art yet, so just generate something
+		let mut atlas_builder = AtlasBuilder::new();
+		atlas_builder.add_image("test_checker", ImageData::from_fn(8, 8, |x, y|
+			if (x/2 + y/2) % 2 == 0 { [255, 64, 192, 255] } else { [32, 32, 32, 255] }));
+		let mut pulse_sheet = SpriteSheet::from_grid(
+			ImageData::from_fn(32, 8, |x, y| {
+				let frame = x / 8;
+				let (x, y) = (x % 8, y);
+				let inset = 3 - frame;
+				if x >= inset && x < 8 - inset && y >= inset && y < 8 - inset {
+					[255, 255, 255, 255]
+				} else {
+					[0, 0, 0, 0]
+				}
+			}), 8, 8).expect("Invalid pulse sheet");
+		pulse_sheet.tags.push(FrameTag {
+			name: "pulse".to_string(),
+			from: 0,
+			to: 3,
+			direction: AnimationDirection::PingPong,
+			repeat: 0,
+		});
+		atlas_builder.add_sprite_sheet("test_pulse", &pulse_sheet);
+		// Rolling hills, as a height per column
+		let hills = |width: u32, height: u32, color: [u8; 4]| ImageData::from_fn(width, height, move |x, y| {
+			let angle = x as f32 / width as f32 * std::f32::consts::PI * 2.0;
+			let top = height as f32 * (0.6 + 0.2 * angle.sin() + 0.1 * (angle * 3.0).cos());
+			// Images are Y+ down
+			if (height - y) as f32 <= top { color } else { [0, 0, 0, 0] }
+		});
+		atlas_builder.add_image("test_hills_far", hills(96, 48, [40, 48, 80, 255]));
+		atlas_builder.add_image("test_hills_near", hills(64, 32, [56, 72, 64, 255]));
+		atlas_builder.add_image("test_cloud", ImageData::from_fn(24, 6, |x, y|
+			if (x as i32 - 12).pow(2) / 16 + (y as i32 - 3).pow(2) <= 9 { [200, 200, 220, 160] } else { [0, 0, 0, 0] }));
+		atlas_builder.add_image("test_ground", ImageData::from_fn(8, 8, |x, y|
+			if y < 2 { [64, 160, 64, 255] } else if (x + y) % 3 == 0 { [96, 64, 40, 255] } else { [120, 80, 48, 255] }));
+		// A slime with a shiny stripe, for palette swaps. 1 is the outline, 2 the body, 3-5 the shine and 6 the eyes
+		atlas_builder.add_indexed_image("test_slime", &IndexedImage::from_fn(12, 8, |x, y| {
+			let (dx, dy) = ((x as f32 + 0.5 - 6.0) / 6.0, (y as f32 + 0.5 - 8.0) / 8.0);
+			let distance = dx * dx + dy * dy;
+			if distance > 1.0 {
+				0
+			} else if distance > 0.6 {
+				1
+			} else if y == 3 && (x == 4 || x == 7) {
+				6
+			} else if y == 5 {
+				3 + (x % 3) as u8
+			} else {
+				2
+			}
+		}));
+		let slime_palette = |outline: [u8; 3], body: [u8; 3], shine: [[u8; 3]; 3]| {
+			let colors = [[0, 0, 0], outline, body, shine[0], shine[1], shine[2], [16, 16, 16]];
+			Palette::new(colors.iter().map(|&[r, g, b]| [r, g, b, 255]).collect())
+		};
+		let mut palettes = PaletteSet::new();
+		palettes.add(slime_palette([16, 48, 16], [64, 176, 64], [[96, 208, 96], [128, 232, 128], [160, 255, 160]]));
+		palettes.add(slime_palette([64, 16, 16], [192, 56, 48], [[216, 96, 80], [232, 128, 112], [255, 160, 144]]));
+		// The blue one shimmers
+		palettes.add(slime_palette([16, 24, 64], [48, 96, 208], [[80, 128, 232], [128, 176, 255], [200, 224, 255]])
+			.with_cycle(3, 3, 150));
+		let debug_font_sheet = FontSheet::debug();
+		atlas_builder.add_sprite_sheet("debug_font", &debug_font_sheet.sheet);
+		let atlas = atlas_builder.build().expect("Failed to build atlas");
+		let debug_font = Font::from_sheet(&atlas, "debug_font", &debug_font_sheet);
+		let checker = atlas.region("test_checker");
+		let slime = atlas.region("test_slime");
+		let pulse = Arc::new(AnimationSet::from_sheet(&atlas, "test_pulse", &pulse_sheet));
+
+		let mut background = ParallaxBackground::new();
+		background.add_layer(ParallaxLayer::new(atlas.region("test_cloud"), [0.1, 0.1])
+			.with_offset([0.0, 140.0])
+			.with_repeat(true, false)
+			.with_auto_scroll([-6.0, 0.0]));
+		background.add_layer(ParallaxLayer::new(atlas.region("test_hills_far"), [0.25, 0.25])
+			.with_offset([0.0, 8.0])
+			.with_repeat(true, false));
+		background.add_layer(ParallaxLayer::new(atlas.region("test_hills_near"), [0.5, 0.5])
+			.with_offset([0.0, 4.0])
+			.with_repeat(true, false));
+
+		// Ground along the bottom, with some glowing animated tiles on top
+		let mut tileset = Tileset::new([8, 8]);
+		let ground = tileset.add(TileDef::new(atlas.region("test_ground")));
+		let glow = tileset.add(TileDef::animated(&pulse.get("pulse").unwrap())
+			.with_lighting(SpriteLighting { emissive: 255, ..SpriteLighting::default() }));
+		let mut tilemap = Tilemap::new(Arc::new(tileset), [64, 2], [-96, 0]);
+		tilemap.fill([0, 0], [64, 1], Some(ground));
+		for x in (4..64).step_by(8) {
+			tilemap.set_tile(x, 1, Some(glow));
+		}
+		tilemap.rebuild_chunks();
+
 		level.spawn_batch(
 			(0..10)
 				.map(|i|
 					(Pos {x: i*20, y: (i*70)%170},
-						DisplayElementComponent(Box::new(DisplayElementSquare{})),
+						DisplayElementComponent(Box::new(AnimatedSprite::new(pulse.clone(), "pulse"))),
 						Vel { vx: 1, vy: 1},
+						SpriteLighting { emissive: 255, ..SpriteLighting::default() },
 					)
 				));
 		level.spawn_batch(
 			(0..4)
 				.map(|i|
 					(Pos {x: (i%2)*312, y: (i/2)*172},
-					 DisplayElementComponent(Box::new(DisplayElementSquare{})),
+					 DisplayElementComponent(Box::new(DisplayElementSprite { region: checker })),
+					 // Corner markers go on top of the bouncing squares
+					 DrawOrder::new(RenderLayer::Foreground, 0),
+					 SpriteLighting { occluder: true, ..SpriteLighting::default() },
+					)
+				));
+		// One slime for each palette, standing on the ground
+		level.spawn_batch(
+			(0..3)
+				.map(|i|
+					(Pos {x: 24 + i as i32 * 24, y: 8},
+					 DisplayElementComponent(Box::new(DisplayElementSprite { region: slime })),
+					 SpriteEffects { palette: i, flash: 0 },
 					)
 				));
+		// Sparks trailing off the light
+		let sparks = Arc::new(ParticleEffect::new(ParticlePreset {
+			rate: 40.0,
+			lifetime_ms: [300, 800],
+			speed: [20.0, 60.0],
+			spread: 60.0,
+			gravity: [0.0, -120.0],
+			color: Curve(vec![(0.0, [255, 240, 160, 255]), (0.5, [255, 128, 32, 255]), (1.0, [160, 32, 0, 0])]),
+			size: Curve(vec![(0.0, 2.0), (1.0, 1.0)]),
+			blend_mode: BlendMode::Additive,
+			emissive: 255,
+			..ParticlePreset::default()
+		}, &atlas).expect("Invalid particle preset"));
+		level.spawn((
+			Pos {x: 100, y: 40},
+			Vel {vx: 1, vy: -1},
+			Light::point([1.0, 0.8, 0.5], 1.5, 120.0),
+			ParticleEmitter::new(sparks, 1),
+		));
+		// Follow the target around the ground, and shake whenever something bounces
+		let mut camera_rig = CameraRig::new(camera.pos);
+		camera_rig.add(Follow { dead_zone: [24.0, 16.0], smoothing: 0.15 });
+		camera_rig.add(LookAhead::new([32.0, 0.0], 0.05));
+		camera_rig.add(Bounds { min: [-96.0, 0.0], max: [416.0, 240.0] });
+		camera_rig.add(Shake::new([4.0, 4.0], 1.5, 0));
 		Game {
 			level,
+			atlas,
+			palettes,
+			animation_events: Vec::new(),
+			ambient_light: [0.3, 0.3, 0.4],
+			debug_font,
+			fps: 0.0,
+			background,
+			tilemap,
+			debug_draw: DebugDraw::new(),
+			camera_target: camera.pos,
+			camera_rig,
+			screen_resolution: DEFAULT_RESOLUTION,
+			split_screen: false,
+			monitor: RenderTexture::new([64, 36]),
 			camera,
 			input,
 		}
 	}
 
+	pub fn get_atlas(&self) -> &Atlas { &self.atlas }
+
+	pub fn get_palettes(&self) -> &PaletteSet { &self.palettes }
+
+	pub fn get_animation_events(&self) -> &[(Entity, AnimationEvent)] { &self.animation_events }
+
+	pub fn set_fps(&mut self, fps: f64) {
+		self.fps = fps;
+	}
+
 	pub fn tick(&mut self, tick_count: u32) {
 		if tick_count % 60 == 0 {
 			println!("Game tick!");
 		}
 		self.input.begin_tick();
+		self.debug_draw.tick();
 
-		// Temporary camera movement code
+		// Temporary target movement code
 		let speed = if self.input.get_key_pressed(VirtualKeyCode::LShift) { 0.5 } else { 4.0 };
 		let in_x = {
 			let mut i = 0.0;
@@ -68,13 +264,25 @@ impl Game {
 			if self.input.get_key_pressed(VirtualKeyCode::W) { i += 1.0 };
 			i
 		};
-		self.camera.pos.x += in_x * speed;
-		self.camera.pos.y += in_y * speed;
+		self.camera_target.x += in_x * speed;
+		self.camera_target.y += in_y * speed;
 
+		// Hold Z for a zoomed out view, to see what's going on past the edges of the screen,
+		// and X for split screen
+		let zoom = if self.input.get_key_pressed(VirtualKeyCode::Z) { 2 } else { 1 };
+		self.split_screen = self.input.get_key_pressed(VirtualKeyCode::X);
+		self.screen_resolution = [DEFAULT_RESOLUTION[0] * zoom, DEFAULT_RESOLUTION[1] * zoom];
+		let columns = if self.split_screen { 2 } else { 1 };
+		self.camera.set_resolution([self.screen_resolution[0] / columns, self.screen_resolution[1]]);
+
+		// Mark where things bounce for half a second
+		self.debug_draw.set_category("collisions");
+		self.debug_draw.set_duration(30);
 		let mut query = self.level.query::<(&mut Pos, &mut Vel)>();
 		for (id, (pos, vel)) in query.iter() {
 			pos.x += vel.vx;
 			pos.y += vel.vy;
+			let (old_vx, old_vy) = (vel.vx, vel.vy);
 			if pos.x < 0 && vel.vx < 0 {
 				vel.vx *= -1;
 			}
@@ -87,18 +295,127 @@ impl Game {
 			if pos.y > 172 && vel.vy > 0 {
 				vel.vy *= -1;
 			}
+			if (vel.vx, vel.vy) != (old_vx, old_vy) {
+				self.debug_draw.point([pos.x as f32, pos.y as f32], [255, 64, 64, 255]);
+				if let Some(shake) = self.camera_rig.get_mut::<Shake>() {
+					shake.add_trauma(0.3);
+				}
+			}
+		}
+		drop(query);
+
+		// After zooming, so bounds use this tick's resolution
+		self.camera_rig.update(&mut self.camera, Some(self.camera_target));
+		self.debug_draw.set_category("camera");
+		self.debug_draw.set_duration(1);
+		self.debug_draw.point([self.camera_target.x as f32, self.camera_target.y as f32], [64, 255, 64, 255]);
+
+		// The red slime flashes every couple of seconds, as if it's been hit
+		for (_, effects) in self.level.query::<&mut SpriteEffects>().iter() {
+			effects.flash = if effects.palette == 1 && tick_count.is_multiple_of(120) { 255 } else { effects.flash.saturating_sub(32) };
+		}
+
+		self.tilemap.tick();
+		for (_, (pos, emitter)) in self.level.query::<(&Pos, &mut ParticleEmitter)>().iter() {
+			emitter.tick([pos.x as f32, pos.y as f32]);
+		}
+
+		self.animation_events.clear();
+		for (id, display) in self.level.query::<&mut DisplayElementComponent>().iter() {
+			if let Some(event) = display.0.tick() {
+				self.animation_events.push((id, event));
+			}
 		}
 
 		self.input.end_tick();
 	}
 
-	pub fn draw_frame(&self, renderer: &mut Renderer, tick_count: u32, partial_ticks: f32, time: f32) {
+	pub fn draw_frame(&self, renderer: &mut Renderer, tick_count: u32, partial_ticks: f32, time: f32) -> Result<(), RendererError> {
+		// Until there's a second player, the right half of split screen follows the light around
+		let mut second_camera = Camera::new();
+		second_camera.set_resolution(self.camera.get_resolution());
+		if let Some((_, (pos, _))) = self.level.query::<(&Pos, &Light)>().iter().next() {
+			second_camera.pos = Vector2::new(pos.x as f64, pos.y as f64);
+		}
+		// Shows the whole level scaled down in the top right corner, without the HUD
+		let mut minimap_camera = Camera::new();
+		minimap_camera.set_resolution([512, 256]);
+		minimap_camera.pos = Vector2::new(160.0, 100.0);
+		let minimap_size = [128, 64];
+		let minimap_rect = ScreenRect::new([
+			self.screen_resolution[0] as i32 - minimap_size[0] as i32 - 2,
+			self.screen_resolution[1] as i32 - minimap_size[1] as i32 - 2,
+		], minimap_size);
+
+		// The monitor is in the foreground layer, so it doesn't try to show itself
+		let mut monitor_camera = Camera::new();
+		monitor_camera.set_resolution([128, 72]);
+		monitor_camera.pos = second_camera.pos;
+
+		let mut views = vec![
+			View::to_texture(self.build_frame(time, &monitor_camera), &monitor_camera, &self.monitor)
+				.with_layers(LayerMask::only(RenderLayer::Background).with(RenderLayer::World)),
+		];
+		if self.split_screen {
+			let rects = ScreenRect::split(self.screen_resolution, 2, 1);
+			views.push(View::new(self.build_frame(time, &self.camera), &self.camera).with_rect(rects[0]));
+			views.push(View::new(self.build_frame(time, &second_camera), &second_camera).with_rect(rects[1]));
+		} else {
+			views.push(View::new(self.build_frame(time, &self.camera), &self.camera));
+		}
+		views.push(View::new(self.build_frame(time, &minimap_camera), &minimap_camera)
+			.with_rect(minimap_rect)
+			.with_layers(LayerMask::only(RenderLayer::Background).with(RenderLayer::World)));
+		renderer.draw_views(self.screen_resolution, views)
+	}
+
+	/// Everything that gets drawn this frame by one camera, without actually drawing it.
+	/// Separate from `draw_frame` so the headless renderer can draw it too.
+	pub fn build_frame(&self, time: f32, camera: &Camera) -> FrameBuilder {
 		let mut frame = FrameBuilder::new(time);
+		self.background.draw(&mut frame, camera);
+		frame.draw_tilemap(&self.tilemap, camera);
 		let sprite_renderer = frame.get_sprite_renderer();
-		let mut query = self.level.query::<(&Pos, & DisplayElementComponent)>();
-		for (id, (pos, display)) in query.iter() {
+		let mut query = self.level.query::<(&Pos, & DisplayElementComponent, Option<&DrawOrder>, Option<&SpriteLighting>, Option<&SpriteEffects>)>();
+		for (_, (pos, display, order, lighting, effects)) in query.iter() {
+			sprite_renderer.set_draw_order(order.copied().unwrap_or_default());
+			sprite_renderer.set_sprite_lighting(lighting.copied().unwrap_or_default());
+			sprite_renderer.set_sprite_effects(effects.copied().unwrap_or_default());
 			display.0.draw(sprite_renderer, pos);
 		}
-		renderer.draw_frame(frame, &self.camera);
+
+		let instanced_renderer = frame.get_instanced_renderer();
+		for (_, emitter) in self.level.query::<&ParticleEmitter>().iter() {
+			emitter.draw(instanced_renderer);
+		}
+
+		let sprite_renderer = frame.get_sprite_renderer();
+		sprite_renderer.set_draw_order(DrawOrder::new(RenderLayer::Foreground, 0));
+		sprite_renderer.set_sprite_lighting(SpriteLighting::default());
+		sprite_renderer.set_sprite_effects(SpriteEffects::default());
+		let [monitor_width, monitor_height] = self.monitor.get_resolution();
+		sprite_renderer.draw_rect(14, 108, monitor_width + 4, monitor_height + 4, [48, 48, 56, 255]);
+		sprite_renderer.draw_render_texture(&self.monitor, 16, 110, [monitor_width, monitor_height]);
+
+		let [left, bottom] = camera.get_view_origin();
+		let top = bottom + camera.get_resolution()[1] as i32;
+		sprite_renderer.set_draw_order(DrawOrder::new(RenderLayer::Ui, 0));
+		sprite_renderer.draw_text(&self.debug_font, &format!("FPS {:.0}", self.fps), left + 2, top - 2,
+			&TextOptions::default(), [255, 255, 255, 255]);
+
+		let lighting = frame.get_lighting();
+		lighting.set_ambient(self.ambient_light);
+		for (_, (pos, light)) in self.level.query::<(&Pos, &Light)>().iter() {
+			lighting.add_light(pos.x as f32, pos.y as f32, *light);
+		}
+
+		let debug_draw = frame.get_debug_draw();
+		debug_draw.set_category("lights");
+		for (_, (pos, light)) in self.level.query::<(&Pos, &Light)>().iter() {
+			debug_draw.circle([pos.x as f32, pos.y as f32], light.radius, [255, 224, 128, 128]);
+		}
+		self.debug_draw.append_to(debug_draw);
+
+		frame
 	}
 }
\ No newline at end of file
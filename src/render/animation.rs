@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::game::Pos;
+use crate::render::atlas::{Atlas, AtlasRegion};
+use crate::render::display::{DisplayElement, SpriteRenderer};
+use crate::render::sprite_sheet::{AnimationDirection, FrameTag, SpriteSheet};
+use crate::util::timing::ms_to_ticks;
+
+/// One frame of an animation, with its duration already converted to ticks.
+#[derive(Debug, Clone, Copy)]
+pub struct AnimationFrame {
+	pub region: AtlasRegion,
+	pub ticks: u32,
+}
+
+/// The frame showing `ticks` ticks into a sequence of frames that loops forever, for things that don't need
+/// the full `AnimatedSprite` (animated tiles, particles, etc). Panics if `frames` is empty.
+pub(crate) fn looping_frame_at(frames: &[AnimationFrame], ticks: u32) -> &AnimationFrame {
+	let total: u32 = frames.iter().map(|frame| frame.ticks.max(1)).sum();
+	let mut remaining = ticks % total;
+	for frame in frames {
+		if remaining < frame.ticks.max(1) {
+			return frame;
+		}
+		remaining -= frame.ticks.max(1);
+	}
+	unreachable!()
+}
+
+/// A sequence of frames. Directions are flattened out when loading (e.g. ping-pong just repeats
+/// frames in reverse), so playback only ever has to walk forwards through `frames`.
+#[derive(Debug, Clone)]
+pub struct Animation {
+	pub frames: Vec<AnimationFrame>,
+	/// How many times to play before finishing. 0 loops forever.
+	pub repeat: u32,
+}
+
+impl Animation {
+	/// `sheet_name` is the name the sheet was given when it was added to the atlas.
+	pub fn from_tag(atlas: &Atlas, sheet_name: &str, sheet: &SpriteSheet, tag: &FrameTag) -> Self {
+		let forward = tag.from..=tag.to;
+		// Ping-pong doesn't show the end frames twice in a row
+		let back = (tag.from+1..tag.to).rev();
+		let indices: Vec<usize> = match tag.direction {
+			AnimationDirection::Forward => forward.collect(),
+			AnimationDirection::Reverse => forward.rev().collect(),
+			AnimationDirection::PingPong => forward.chain(back).collect(),
+			AnimationDirection::PingPongReverse => forward.rev().chain(back.rev()).collect(),
+		};
+		let frames = indices.into_iter()
+			.map(|i| {
+				let frame = &sheet.frames[i];
+				AnimationFrame {
+					region: atlas.region(&format!("{}/{}", sheet_name, frame.name)),
+					ticks: ms_to_ticks(frame.duration_ms),
+				}
+			})
+			.collect();
+		Self {
+			frames,
+			repeat: tag.repeat,
+		}
+	}
+}
+
+/// All the animations of one sprite sheet, by tag name.
+pub struct AnimationSet {
+	animations: HashMap<String, Arc<Animation>>,
+}
+
+impl AnimationSet {
+	/// One animation per tag in the sheet. A sheet without tags gets a single looping animation
+	/// called "default" that plays every frame, unless it has no frames either, in which case the set is empty.
+	pub fn from_sheet(atlas: &Atlas, sheet_name: &str, sheet: &SpriteSheet) -> Self {
+		let default_tag;
+		let tags = if sheet.frames.is_empty() {
+			&[]
+		} else if sheet.tags.is_empty() {
+			default_tag = [FrameTag {
+				name: "default".to_string(),
+				from: 0,
+				to: sheet.frames.len() - 1,
+				direction: AnimationDirection::Forward,
+				repeat: 0,
+			}];
+			&default_tag[..]
+		} else {
+			&sheet.tags[..]
+		};
+		let animations = tags.iter()
+			.map(|tag| (tag.name.clone(), Arc::new(Animation::from_tag(atlas, sheet_name, sheet, tag))))
+			.collect();
+		Self { animations }
+	}
+
+	pub fn get(&self, tag: &str) -> Option<Arc<Animation>> {
+		self.animations.get(tag).cloned()
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnimationEvent {
+	/// Got to the end and started again
+	Looped,
+	/// Got to the end of the last repeat; stays on the last frame
+	Finished,
+}
+
+/// A sprite that plays animations from an `AnimationSet`, advancing once per game tick.
+pub struct AnimatedSprite {
+	animations: Arc<AnimationSet>,
+	tag: String,
+	animation: Arc<Animation>,
+	frame: usize,
+	/// Ticks spent on the current frame so far
+	ticks: u32,
+	loops: u32,
+	finished: bool,
+}
+
+impl AnimatedSprite {
+	pub fn new(animations: Arc<AnimationSet>, tag: &str) -> Self {
+		let animation = Self::get_animation(&animations, tag);
+		Self {
+			animations,
+			tag: tag.to_string(),
+			animation,
+			frame: 0,
+			ticks: 0,
+			loops: 0,
+			finished: false,
+		}
+	}
+
+	fn get_animation(animations: &AnimationSet, tag: &str) -> Arc<Animation> {
+		animations.get(tag).unwrap_or_else(|| panic!("Missing animation {}", tag))
+	}
+
+	/// Switches to another animation, starting from the beginning.
+	/// Does nothing if it's already playing, so it's fine to call every tick.
+	pub fn play(&mut self, tag: &str) {
+		if self.tag != tag {
+			self.animation = Self::get_animation(&self.animations, tag);
+			self.tag = tag.to_string();
+			self.restart();
+		}
+	}
+
+	pub fn restart(&mut self) {
+		self.frame = 0;
+		self.ticks = 0;
+		self.loops = 0;
+		self.finished = false;
+	}
+
+	pub fn get_tag(&self) -> &str { &self.tag }
+
+	pub fn is_finished(&self) -> bool { self.finished }
+
+	pub fn get_region(&self) -> AtlasRegion {
+		self.animation.frames[self.frame].region
+	}
+
+	/// Advances the animation by one tick.
+	pub fn advance(&mut self) -> Option<AnimationEvent> {
+		if self.finished {
+			return None;
+		}
+		self.ticks += 1;
+		if self.ticks < self.animation.frames[self.frame].ticks {
+			return None;
+		}
+		self.ticks = 0;
+		self.frame += 1;
+		if self.frame < self.animation.frames.len() {
+			return None;
+		}
+		self.loops += 1;
+		if self.animation.repeat != 0 && self.loops >= self.animation.repeat {
+			self.frame = self.animation.frames.len() - 1;
+			self.finished = true;
+			Some(AnimationEvent::Finished)
+		} else {
+			self.frame = 0;
+			Some(AnimationEvent::Looped)
+		}
+	}
+}
+
+impl DisplayElement for AnimatedSprite {
+	fn draw(&self, renderer: &mut SpriteRenderer, pos: &Pos) {
+		renderer.draw_sprite(&self.get_region(), pos.x, pos.y);
+	}
+
+	fn tick(&mut self) -> Option<AnimationEvent> {
+		self.advance()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::render::atlas::AtlasBuilder;
+	use crate::render::texture::ImageData;
+
+	/// A sheet of four 4x4 frames in a row, lasting 1 to 4 ticks, added to an atlas as "sheet"
+	fn sheet_and_atlas(tags: Vec<FrameTag>) -> (SpriteSheet, Atlas) {
+		let mut sheet = SpriteSheet::from_grid(ImageData::new(16, 4), 4, 4).unwrap();
+		for (i, frame) in sheet.frames.iter_mut().enumerate() {
+			frame.duration_ms = (i as u32 + 1) * 1000 / 60;
+		}
+		sheet.tags = tags;
+		let mut builder = AtlasBuilder::new();
+		builder.add_sprite_sheet("sheet", &sheet);
+		(sheet, builder.build().unwrap())
+	}
+
+	fn tag(from: usize, to: usize, direction: AnimationDirection, repeat: u32) -> FrameTag {
+		FrameTag { name: "tag".to_string(), from, to, direction, repeat }
+	}
+
+	/// Which sheet frame each animation frame is, worked out from where it is in the atlas
+	fn frame_indices(atlas: &Atlas, animation: &Animation) -> Vec<usize> {
+		let first = atlas.region("sheet/0");
+		animation.frames.iter().map(|frame| ((frame.region.x - first.x) / 4) as usize).collect()
+	}
+
+	#[test]
+	fn directions_flatten() {
+		let (sheet, atlas) = sheet_and_atlas(Vec::new());
+		let indices = |from, to, direction| {
+			frame_indices(&atlas, &Animation::from_tag(&atlas, "sheet", &sheet, &tag(from, to, direction, 0)))
+		};
+		assert_eq!(indices(0, 3, AnimationDirection::Forward), [0, 1, 2, 3]);
+		assert_eq!(indices(0, 3, AnimationDirection::Reverse), [3, 2, 1, 0]);
+		assert_eq!(indices(0, 3, AnimationDirection::PingPong), [0, 1, 2, 3, 2, 1]);
+		assert_eq!(indices(0, 3, AnimationDirection::PingPongReverse), [3, 2, 1, 0, 1, 2]);
+		assert_eq!(indices(1, 2, AnimationDirection::PingPong), [1, 2]);
+		assert_eq!(indices(2, 2, AnimationDirection::PingPong), [2]);
+		assert_eq!(indices(2, 2, AnimationDirection::PingPongReverse), [2]);
+	}
+
+	#[test]
+	fn default_animation() {
+		let (sheet, atlas) = sheet_and_atlas(Vec::new());
+		let set = AnimationSet::from_sheet(&atlas, "sheet", &sheet);
+		assert_eq!(frame_indices(&atlas, &set.get("default").unwrap()), [0, 1, 2, 3]);
+
+		let (sheet, atlas) = sheet_and_atlas(vec![tag(0, 1, AnimationDirection::Forward, 0)]);
+		let set = AnimationSet::from_sheet(&atlas, "sheet", &sheet);
+		assert!(set.get("default").is_none());
+		assert!(set.get("tag").is_some());
+
+		let empty = SpriteSheet { image: ImageData::new(1, 1), frames: Vec::new(), tags: Vec::new() };
+		assert!(AnimationSet::from_sheet(&atlas, "empty", &empty).get("default").is_none());
+	}
+
+	#[test]
+	fn looping_frames() {
+		let (_, atlas) = sheet_and_atlas(Vec::new());
+		// Zero tick frames still show for a tick
+		let frames: Vec<_> = [2, 0, 3].iter().enumerate()
+			.map(|(i, &ticks)| AnimationFrame { region: atlas.region(&format!("sheet/{}", i)), ticks })
+			.collect();
+		let shown: Vec<u32> = (0..12).map(|tick| looping_frame_at(&frames, tick).region.x).collect();
+		let x = |i: usize| frames[i].region.x;
+		assert_eq!(shown, [x(0), x(0), x(1), x(2), x(2), x(2), x(0), x(0), x(1), x(2), x(2), x(2)]);
+	}
+
+	#[test]
+	fn advance_repeats_then_finishes() {
+		let (sheet, atlas) = sheet_and_atlas(vec![tag(0, 1, AnimationDirection::Forward, 2)]);
+		let set = Arc::new(AnimationSet::from_sheet(&atlas, "sheet", &sheet));
+		let mut sprite = AnimatedSprite::new(set, "tag");
+		// Frame 0 lasts 1 tick and frame 1 lasts 2, so each loop is 3 ticks
+		let events: Vec<_> = (0..8).map(|_| sprite.advance()).collect();
+		assert_eq!(events, [
+			None, None, Some(AnimationEvent::Looped),
+			None, None, Some(AnimationEvent::Finished),
+			None, None,
+		]);
+		assert!(sprite.is_finished());
+		assert_eq!(sprite.get_region(), atlas.region("sheet/1"));
+
+		sprite.restart();
+		assert!(!sprite.is_finished());
+		assert_eq!(sprite.get_region(), atlas.region("sheet/0"));
+	}
+
+	#[test]
+	fn advance_loops_forever() {
+		let (sheet, atlas) = sheet_and_atlas(vec![tag(0, 0, AnimationDirection::Forward, 0)]);
+		let mut sprite = AnimatedSprite::new(Arc::new(AnimationSet::from_sheet(&atlas, "sheet", &sheet)), "tag");
+		for _ in 0..100 {
+			assert_eq!(sprite.advance(), Some(AnimationEvent::Looped));
+		}
+		assert!(!sprite.is_finished());
+	}
+}
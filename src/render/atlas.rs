@@ -0,0 +1,277 @@
+use std::cmp::{max, Reverse};
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::render::palette::IndexedImage;
+use crate::render::sprite_sheet::{SpriteFrame, SpriteSheet};
+use crate::render::texture::ImageData;
+use crate::util::asset::AssetError;
+
+pub const MAX_ATLAS_SIZE: u32 = 4096;
+
+/// Every atlas has a single opaque white pixel at the origin, so untextured (tint-only) quads can
+/// be drawn in the same batch as everything else by giving them UVs of (0, 0).
+pub const WHITE_PIXEL_UV: [f32; 2] = [0.0, 0.0];
+
+/// A named rectangle in an atlas.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AtlasRegion {
+	pub x: u32,
+	pub y: u32,
+	pub width: u32,
+	pub height: u32,
+	/// Top left corner in texture coordinates
+	pub uv_min: [f32; 2],
+	/// Bottom right corner in texture coordinates
+	pub uv_max: [f32; 2],
+	/// Whether this holds palette indices rather than colors; see `IndexedImage`
+	pub indexed: bool,
+	/// Same as `SpriteFrame::offset`. Added to the position sprites are drawn at, so trimmed frames line up.
+	pub offset: [i32; 2],
+	/// Same as `SpriteFrame::source_size`
+	pub source_size: [u32; 2],
+}
+
+/// A packed atlas image, plus where everything ended up in it.
+/// Needs to be uploaded with `Renderer::upload_atlas` before its regions can be drawn.
+pub struct Atlas {
+	pub image: ImageData,
+	regions: HashMap<String, AtlasRegion>,
+}
+
+impl Atlas {
+	pub fn get_region(&self, name: &str) -> Option<AtlasRegion> {
+		self.regions.get(name).copied()
+	}
+
+	/// Like `get_region`, but panics if it doesn't exist. For regions the game can't run without.
+	pub fn region(&self, name: &str) -> AtlasRegion {
+		self.get_region(name).unwrap_or_else(|| panic!("Missing atlas region {}", name))
+	}
+
+	pub fn regions(&self) -> impl Iterator<Item = (&String, &AtlasRegion)> {
+		self.regions.iter()
+	}
+}
+
+/// An image waiting to be packed, along with the named regions inside it
+struct AtlasEntry {
+	image: ImageData,
+	regions: Vec<SpriteFrame>,
+	indexed: bool,
+}
+
+/// Collects images and sprite sheets and packs them into a single `Atlas`.
+/// Packing happens at load time, so there's no extra build step for art.
+pub struct AtlasBuilder {
+	entries: Vec<AtlasEntry>,
+	padding: u32,
+}
+
+impl Default for AtlasBuilder {
+	fn default() -> Self { Self::new() }
+}
+
+impl AtlasBuilder {
+	pub fn new() -> Self {
+		Self {
+			entries: vec![AtlasEntry {
+				image: ImageData::from_fn(1, 1, |_, _| [255, 255, 255, 255]),
+				regions: Vec::new(),
+				indexed: false,
+			}],
+			padding: 1,
+		}
+	}
+
+	/// Pixels of empty space left between packed images. Defaults to 1.
+	pub fn with_padding(mut self, padding: u32) -> Self {
+		self.padding = padding;
+		self
+	}
+
+	pub fn add_image(&mut self, name: &str, image: ImageData) {
+		self.add_entry(name, image, false);
+	}
+
+	/// Adds an image that's drawn with whichever palette the sprite renderer is set to; see `SpriteEffects`.
+	pub fn add_indexed_image(&mut self, name: &str, image: &IndexedImage) {
+		self.add_entry(name, image.to_atlas_image(), true);
+	}
+
+	fn add_entry(&mut self, name: &str, image: ImageData, indexed: bool) {
+		let frame = SpriteFrame::new(name.to_string(), 0, 0, image.width, image.height, 0);
+		self.entries.push(AtlasEntry {
+			image,
+			regions: vec![frame],
+			indexed,
+		});
+	}
+
+	pub fn add_png(&mut self, name: &str, path: &Path) -> Result<(), AssetError> {
+		self.add_image(name, ImageData::load_png(path)?);
+		Ok(())
+	}
+
+	/// Adds every frame of the sheet as a region named `"{name}/{frame name}"`.
+	/// The sheet is packed as a single image, so its frames stay together.
+	/// Takes a reference since the sheet's tags are still needed for building animations afterwards.
+	pub fn add_sprite_sheet(&mut self, name: &str, sheet: &SpriteSheet) {
+		let regions = sheet.frames.iter()
+			.map(|frame| SpriteFrame {
+				name: format!("{}/{}", name, frame.name),
+				..frame.clone()
+			})
+			.collect();
+		self.entries.push(AtlasEntry {
+			image: sheet.image.clone(),
+			regions,
+			indexed: false,
+		});
+	}
+
+	pub fn build(self) -> Result<Atlas, AssetError> {
+		let sizes: Vec<[u32; 2]> = self.entries.iter()
+			.map(|entry| [entry.image.width, entry.image.height])
+			.collect();
+		let (positions, [width, height]) = pack(&sizes, self.padding)
+			.ok_or_else(|| AssetError::Invalid(
+				format!("Atlas images don't fit in {0}x{0}", MAX_ATLAS_SIZE)))?;
+
+		let mut image = ImageData::new(width, height);
+		let mut regions = HashMap::new();
+		for (entry, [x, y]) in self.entries.into_iter().zip(positions) {
+			image.blit(&entry.image, x, y);
+			for frame in entry.regions {
+				let region = AtlasRegion {
+					x: x + frame.x,
+					y: y + frame.y,
+					width: frame.width,
+					height: frame.height,
+					uv_min: [
+						(x + frame.x) as f32 / width as f32,
+						(y + frame.y) as f32 / height as f32,
+					],
+					uv_max: [
+						(x + frame.x + frame.width) as f32 / width as f32,
+						(y + frame.y + frame.height) as f32 / height as f32,
+					],
+					indexed: entry.indexed,
+					offset: frame.offset,
+					source_size: frame.source_size,
+				};
+				if regions.insert(frame.name.clone(), region).is_some() {
+					return Err(AssetError::Invalid(format!("Duplicate atlas region {}", frame.name)));
+				}
+			}
+		}
+
+		Ok(Atlas { image, regions })
+	}
+}
+
+/// Shelf packer. Returns the position of each rectangle and the size of the atlas,
+/// or None if they don't fit in `MAX_ATLAS_SIZE`.
+fn pack(sizes: &[[u32; 2]], padding: u32) -> Option<(Vec<[u32; 2]>, [u32; 2])> {
+	// Tallest first packs the shelves much more tightly.
+	// Index 0 is the white pixel, which has to stay at the origin.
+	let mut order: Vec<usize> = (1..sizes.len()).collect();
+	order.sort_by_key(|&i| Reverse(sizes[i][1]));
+	order.insert(0, 0);
+
+	let mut width = 64;
+	loop {
+		if let Some((positions, height)) = pack_shelves(sizes, &order, width, padding) {
+			let height = height.next_power_of_two();
+			if height <= width || (width == MAX_ATLAS_SIZE && height <= MAX_ATLAS_SIZE) {
+				return Some((positions, [width, height]));
+			}
+		}
+		if width >= MAX_ATLAS_SIZE {
+			return None;
+		}
+		width *= 2;
+	}
+}
+
+/// Places rectangles left to right in rows ("shelves") of the given width.
+/// Returns the positions and total height used.
+fn pack_shelves(sizes: &[[u32; 2]], order: &[usize], width: u32, padding: u32) -> Option<(Vec<[u32; 2]>, u32)> {
+	let mut positions = vec![[0, 0]; sizes.len()];
+	let mut x = 0;
+	let mut y = 0;
+	let mut shelf_height = 0;
+	for &i in order {
+		let [w, h] = sizes[i];
+		if w > width {
+			return None;
+		}
+		if x + w > width {
+			x = 0;
+			y += shelf_height + padding;
+			shelf_height = 0;
+		}
+		positions[i] = [x, y];
+		x += w + padding;
+		shelf_height = max(shelf_height, h);
+	}
+	Some((positions, y + shelf_height))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn overlaps(a: ([u32; 2], [u32; 2]), b: ([u32; 2], [u32; 2])) -> bool {
+		let ([ax, ay], [aw, ah]) = a;
+		let ([bx, by], [bw, bh]) = b;
+		ax < bx + bw && bx < ax + aw && ay < by + bh && by < ay + ah
+	}
+
+	#[test]
+	fn pack_without_overlaps() {
+		let sizes: Vec<[u32; 2]> = (0..40).map(|i| [1 + (i * 7) % 23, 1 + (i * 13) % 17]).collect();
+		let padding = 1;
+		let (positions, [width, height]) = pack(&sizes, padding).unwrap();
+		assert!(width.is_power_of_two() && height.is_power_of_two());
+		for (i, (&a, &a_size)) in positions.iter().zip(&sizes).enumerate() {
+			assert!(a[0] + a_size[0] <= width && a[1] + a_size[1] <= height, "Rectangle {} is outside the atlas", i);
+			for (j, (&b, &b_size)) in positions.iter().zip(&sizes).enumerate().skip(i + 1) {
+				// Padding counts as part of each rectangle
+				let padded = |size: [u32; 2]| [size[0] + padding, size[1] + padding];
+				assert!(!overlaps((a, padded(a_size)), (b, padded(b_size))), "Rectangles {} and {} overlap", i, j);
+			}
+		}
+		assert_eq!(positions[0], [0, 0]);
+	}
+
+	#[test]
+	fn pack_too_big() {
+		assert!(pack(&[[1, 1], [MAX_ATLAS_SIZE + 1, 1]], 1).is_none());
+		assert!(pack(&[[1, 1], [MAX_ATLAS_SIZE, MAX_ATLAS_SIZE]], 1).is_none());
+	}
+
+	#[test]
+	fn build_keeps_white_pixel_at_origin() {
+		let mut builder = AtlasBuilder::new();
+		builder.add_image("red", ImageData::from_fn(3, 2, |_, _| [255, 0, 0, 255]));
+		builder.add_image("tall", ImageData::from_fn(2, 40, |_, _| [0, 255, 0, 255]));
+		let atlas = builder.build().unwrap();
+
+		assert_eq!(atlas.image.get_pixel(0, 0), [255, 255, 255, 255]);
+		let red = atlas.region("red");
+		assert_ne!([red.x, red.y], [0, 0]);
+		assert_eq!(atlas.image.get_pixel(red.x + 2, red.y + 1), [255, 0, 0, 255]);
+		assert_eq!(red.uv_min, [red.x as f32 / atlas.image.width as f32, red.y as f32 / atlas.image.height as f32]);
+		assert_eq!(red.source_size, [3, 2]);
+		assert!(atlas.get_region("missing").is_none());
+	}
+
+	#[test]
+	fn duplicate_regions() {
+		let mut builder = AtlasBuilder::new();
+		builder.add_image("a", ImageData::new(1, 1));
+		builder.add_image("a", ImageData::new(1, 1));
+		assert!(builder.build().is_err());
+	}
+}
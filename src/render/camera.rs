@@ -1,35 +1,56 @@
 use cgmath::Vector2;
 
-pub const PIXEL_RESOLUTION: [u32; 2] = [320, 180];
+/// Visible size of the intermediate image, unless changed with `Camera::set_resolution`
+pub const DEFAULT_RESOLUTION: [u32; 2] = [320, 180];
+/// Where the camera starts, which puts the world origin in the bottom left at the default resolution
 pub const CAMERA_CENTER_POS: [f64; 2] = [
-	160.0,
-	90.0,
+	DEFAULT_RESOLUTION[0] as f64 / 2.0,
+	DEFAULT_RESOLUTION[1] as f64 / 2.0,
 ];
-// This stuff was for sub-pixel movement, using full pixel movement rn so don't need it
-/*pub const PIXEL_MARGIN: [u32; 2] = [2, 2];
+/// Extra pixels rendered around the visible area, so there's something to show
+/// when the output pass shifts the image by a fraction of a pixel.
+pub const PIXEL_MARGIN: [u32; 2] = [2, 2];
+/// Where the visible area starts in the intermediate image when there's no sub-pixel offset
 pub const PIXEL_OFFSET: [u32; 2] = [1, 1];
-pub const PIXEL_FULL_RESOLUTION: [u32; 2] = [
-	PIXEL_RESOLUTION[0] + PIXEL_MARGIN[0],
-	PIXEL_RESOLUTION[1] + PIXEL_MARGIN[1],
-];
-// TODO camera_width_height as float form of pixel_full_resolution?
-//      and the actually visible width/height too
-pub const CAMERA_CENTER_POS: [f64; 2] = [
-	PIXEL_FULL_RESOLUTION[0] as f64 / 2.0,
-	PIXEL_FULL_RESOLUTION[1] as f64 / 2.0,
-];*/
 
 pub struct Camera {
 	pub pos: cgmath::Vector2<f64>,
+	/// Whether to scroll by fractions of a pixel. Sprites are still drawn at whole pixels relative
+	/// to the camera; the output pass just shifts the whole image by the leftover fraction.
+	/// Otherwise the camera snaps to whole pixels, which makes slow pans jitter.
+	pub smooth_scrolling: bool,
+	/// How many pixels are visible. The renderer resizes its images to match.
+	resolution: [u32; 2],
 }
 
 impl Camera {
 	pub fn new() -> Self {
 		Camera {
-			pos: Vector2::new(CAMERA_CENTER_POS[0], CAMERA_CENTER_POS[1])
+			pos: Vector2::new(CAMERA_CENTER_POS[0], CAMERA_CENTER_POS[1]),
+			smooth_scrolling: false,
+			resolution: DEFAULT_RESOLUTION,
 		}
 	}
 
+	/// Changes how many pixels are visible, e.g. for a different aspect ratio or a zoomed out view.
+	/// The camera stays centered on the same position.
+	pub fn set_resolution(&mut self, resolution: [u32; 2]) {
+		assert!(resolution[0] > 0 && resolution[1] > 0, "Invalid resolution {:?}", resolution);
+		self.resolution = resolution;
+	}
+
+	pub fn get_resolution(&self) -> [u32; 2] { self.resolution }
+
+	/// Size of the intermediate image, including the margin
+	pub fn get_full_resolution(&self) -> [u32; 2] {
+		[self.resolution[0] + PIXEL_MARGIN[0], self.resolution[1] + PIXEL_MARGIN[1]]
+	}
+
+	/// Where the camera's position ends up in the visible area. Always a whole pixel so sprites stay aligned.
+	fn get_center(&self) -> [f64; 2] {
+		[(self.resolution[0] / 2) as f64, (self.resolution[1] / 2) as f64]
+	}
+
 	fn get_game_pos_f64(&self) -> Vector2<f64> {
 		Vector2::new(self.pos.x.floor(), self.pos.y.floor())
 	}
@@ -38,17 +59,49 @@ impl Camera {
 		Vector2::new(self.pos.x.floor() as i32, self.pos.y.floor() as i32)
 	}
 
+	/// World position of the bottom left pixel of the visible area, for drawing things that stay put on screen.
+	pub fn get_view_origin(&self) -> [i32; 2] {
+		let pos = self.get_game_pos();
+		let center = self.get_center();
+		[pos.x - center[0] as i32, pos.y - center[1] as i32]
+	}
+
+	/// Converts a world position to pixel coordinates in the intermediate image (Y+ down, origin top left).
+	/// This includes the margin, so the visible area starts at `PIXEL_OFFSET`.
+	pub fn world_to_pixel(&self, world: Vector2<f32>) -> Vector2<f32> {
+		let pos = self.get_game_pos_f64();
+		let center = self.get_center();
+		let x = world.x as f64 - pos.x + center[0] + PIXEL_OFFSET[0] as f64;
+		let y = world.y as f64 - pos.y + center[1] + PIXEL_OFFSET[1] as f64;
+		Vector2::new(x as f32, (self.get_full_resolution()[1] as f64 - y) as f32)
+	}
+
+	/// Top left corner of the visible area in the intermediate image, in pixels.
+	/// Whole pixels unless smooth scrolling is on.
+	pub fn get_visible_offset(&self) -> [f32; 2] {
+		let mut offset = [PIXEL_OFFSET[0] as f32, PIXEL_OFFSET[1] as f32];
+		if self.smooth_scrolling {
+			// The camera is drawn at its floored position, so move the view the rest of the way.
+			// Y is flipped, since the image is Y+ down.
+			offset[0] += (self.pos.x - self.pos.x.floor()) as f32;
+			offset[1] -= (self.pos.y - self.pos.y.floor()) as f32;
+		}
+		offset
+	}
+
 	pub fn get_sprite_matrix(&self) -> cgmath::Matrix4<f32> {
 		let pos = self.get_game_pos_f64();
+		let center = self.get_center();
+		let full_resolution = self.get_full_resolution();
 		let pixel_offset = cgmath::Matrix4::from_translation(
 			cgmath::Vector3::new(
-				(CAMERA_CENTER_POS[0]-pos.x) as f32,
-				(CAMERA_CENTER_POS[1]-pos.y) as f32,
+				(center[0]+PIXEL_OFFSET[0] as f64-pos.x) as f32,
+				(center[1]+PIXEL_OFFSET[1] as f64-pos.y) as f32,
 				0.0));
 		// 2.0 instead of 1.0 as x,y need to span from -1 to 1
 		let scale = cgmath::Matrix4::from_nonuniform_scale(
-			2.0/PIXEL_RESOLUTION[0] as f32,// 2.0/PIXEL_FULL_RESOLUTION[0] as f64,
-			2.0/PIXEL_RESOLUTION[1] as f32,// 2.0/PIXEL_FULL_RESOLUTION[1] as f64,
+			2.0/full_resolution[0] as f32,
+			2.0/full_resolution[1] as f32,
 			1.0);
 		// translate from (0, 2) to (-1, 1) range
 		let final_translate = cgmath::Matrix4::from_translation(
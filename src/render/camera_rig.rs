@@ -0,0 +1,235 @@
+use std::any::Any;
+
+use cgmath::{Vector2, Zero};
+
+use crate::render::camera::Camera;
+use crate::util::random::Rng;
+use crate::util::timing::TICKS_PER_SECOND;
+
+/// What behaviours get to know about each tick.
+#[derive(Debug, Clone, Copy)]
+pub struct CameraContext {
+	/// What the camera is following, if anything
+	pub target: Option<Vector2<f64>>,
+	/// How far the target moved since last tick
+	pub target_velocity: Vector2<f64>,
+	/// Visible area in pixels, for keeping it inside bounds
+	pub view_size: Vector2<f64>,
+}
+
+/// Where the camera is looking, split in two so that temporary effects don't feed back into next tick.
+#[derive(Debug, Clone, Copy)]
+pub struct CameraState {
+	/// Kept between ticks. Follow behaviours move this.
+	pub focus: Vector2<f64>,
+	/// Added to `focus` for this tick only, then reset. Look-ahead and shake go here.
+	pub offset: Vector2<f64>,
+}
+
+/// One part of how a `CameraRig` moves the camera. Behaviours run in the order they were added,
+/// so e.g. bounds should go after anything that moves the camera, and shake after bounds if it's
+/// allowed to show past the edge of the level.
+pub trait CameraBehaviour: Any + Send + Sync {
+	/// Called once per tick.
+	fn update(&mut self, state: &mut CameraState, context: &CameraContext);
+}
+
+/// Keeps the target inside a box around the middle of the screen, catching up smoothly when it leaves.
+#[derive(Debug, Clone)]
+pub struct Follow {
+	/// Half the size of the box the target can move around in without the camera moving, in pixels
+	pub dead_zone: [f64; 2],
+	/// Fraction of the remaining distance to cover each tick, from just above 0 (very floaty) to 1 (no smoothing)
+	pub smoothing: f64,
+}
+
+impl CameraBehaviour for Follow {
+	fn update(&mut self, state: &mut CameraState, context: &CameraContext) {
+		let Some(target) = context.target else { return };
+		let mut desired = state.focus;
+		for axis in 0..2 {
+			let distance = target[axis] - state.focus[axis];
+			let dead_zone = self.dead_zone[axis];
+			if distance.abs() > dead_zone {
+				desired[axis] = target[axis] - dead_zone * distance.signum();
+			}
+		}
+		state.focus += (desired - state.focus) * self.smoothing;
+	}
+}
+
+/// Shifts the view ahead of the target in whichever direction it's moving, so there's more to see ahead.
+/// Holds the last direction when the target stops, so the view doesn't swing back and forth.
+#[derive(Debug, Clone)]
+pub struct LookAhead {
+	/// How far ahead to look along each axis, in pixels
+	pub distance: [f64; 2],
+	/// Like `Follow::smoothing`
+	pub smoothing: f64,
+	/// -1, 0 or 1 on each axis
+	direction: Vector2<f64>,
+	current: Vector2<f64>,
+}
+
+impl LookAhead {
+	pub fn new(distance: [f64; 2], smoothing: f64) -> Self {
+		Self {
+			distance,
+			smoothing,
+			direction: Vector2::zero(),
+			current: Vector2::zero(),
+		}
+	}
+}
+
+impl CameraBehaviour for LookAhead {
+	fn update(&mut self, state: &mut CameraState, context: &CameraContext) {
+		for axis in 0..2 {
+			let velocity = context.target_velocity[axis];
+			if velocity != 0.0 {
+				self.direction[axis] = velocity.signum();
+			}
+			let desired = self.direction[axis] * self.distance[axis];
+			self.current[axis] += (desired - self.current[axis]) * self.smoothing;
+		}
+		state.offset += self.current;
+	}
+}
+
+/// Keeps the view inside a rectangle, e.g. the level. If the view is bigger than the rectangle along an axis,
+/// it's centered on it instead.
+#[derive(Debug, Clone)]
+pub struct Bounds {
+	pub min: [f64; 2],
+	pub max: [f64; 2],
+}
+
+impl CameraBehaviour for Bounds {
+	fn update(&mut self, state: &mut CameraState, context: &CameraContext) {
+		let mut pos = state.focus + state.offset;
+		for axis in 0..2 {
+			let half_view = context.view_size[axis] / 2.0;
+			let (min, max) = (self.min[axis] + half_view, self.max[axis] - half_view);
+			pos[axis] = if min > max { (self.min[axis] + self.max[axis]) / 2.0 } else { pos[axis].clamp(min, max) };
+		}
+		// Clamping the focus too stops it drifting off past the edge while the offset is clamped away,
+		// which would make the camera take a while to come back
+		let clamped_offset = pos - state.focus;
+		let focus_error = state.offset - clamped_offset;
+		state.focus -= focus_error;
+		state.offset = pos - state.focus;
+	}
+}
+
+/// Trauma-based screen shake: things like hits and explosions add trauma, which decays over time,
+/// and the camera shakes by the square of it so small amounts are subtle and big ones are violent.
+/// Random offsets come from a seeded `Rng`, so the same trauma on the same ticks shakes the same way.
+#[derive(Debug, Clone)]
+pub struct Shake {
+	/// Furthest the camera can be thrown along each axis at full trauma, in pixels
+	pub max_offset: [f64; 2],
+	/// Trauma lost per second
+	pub decay: f64,
+	trauma: f64,
+	rng: Rng,
+}
+
+impl Shake {
+	pub fn new(max_offset: [f64; 2], decay: f64, seed: u64) -> Self {
+		Self {
+			max_offset,
+			decay,
+			trauma: 0.0,
+			rng: Rng::new(seed),
+		}
+	}
+
+	/// Trauma is kept between 0 and 1.
+	pub fn add_trauma(&mut self, amount: f64) {
+		self.trauma = (self.trauma + amount).clamp(0.0, 1.0);
+	}
+
+	pub fn get_trauma(&self) -> f64 { self.trauma }
+}
+
+impl CameraBehaviour for Shake {
+	fn update(&mut self, state: &mut CameraState, _context: &CameraContext) {
+		if self.trauma <= 0.0 {
+			return;
+		}
+		let shake = self.trauma * self.trauma;
+		for axis in 0..2 {
+			state.offset[axis] += self.max_offset[axis] * shake * self.rng.range(-1.0, 1.0) as f64;
+		}
+		self.trauma = (self.trauma - self.decay / TICKS_PER_SECOND as f64).max(0.0);
+	}
+}
+
+/// Moves a `Camera` with a stack of behaviours, once per tick.
+///
+/// ```ignore
+/// let mut rig = CameraRig::new(camera.pos);
+/// rig.add(Follow { dead_zone: [16.0, 8.0], smoothing: 0.1 });
+/// rig.add(Bounds { min: [0.0, 0.0], max: [640.0, 360.0] });
+/// rig.add(Shake::new([6.0, 6.0], 1.5, 0));
+/// // Later, when something explodes
+/// rig.get_mut::<Shake>().unwrap().add_trauma(0.5);
+/// ```
+pub struct CameraRig {
+	behaviours: Vec<Box<dyn CameraBehaviour>>,
+	focus: Vector2<f64>,
+	last_target: Option<Vector2<f64>>,
+}
+
+impl CameraRig {
+	pub fn new(focus: Vector2<f64>) -> Self {
+		Self {
+			behaviours: Vec::new(),
+			focus,
+			last_target: None,
+		}
+	}
+
+	/// Adds a behaviour after the ones already added.
+	pub fn add<B: CameraBehaviour>(&mut self, behaviour: B) {
+		self.behaviours.push(Box::new(behaviour));
+	}
+
+	/// The first behaviour of type `B`, for changing its settings or e.g. adding trauma to a `Shake`.
+	pub fn get_mut<B: CameraBehaviour>(&mut self) -> Option<&mut B> {
+		self.behaviours.iter_mut()
+			.find_map(|behaviour| (behaviour.as_mut() as &mut dyn Any).downcast_mut::<B>())
+	}
+
+	/// Jumps straight to `focus`, e.g. when the level changes.
+	pub fn snap_to(&mut self, focus: Vector2<f64>) {
+		self.focus = focus;
+		self.last_target = None;
+	}
+
+	pub fn get_focus(&self) -> Vector2<f64> { self.focus }
+
+	/// Runs every behaviour and moves the camera to the result. Call once per tick.
+	pub fn update(&mut self, camera: &mut Camera, target: Option<Vector2<f64>>) {
+		let resolution = camera.get_resolution();
+		let context = CameraContext {
+			target,
+			target_velocity: match (target, self.last_target) {
+				(Some(target), Some(last_target)) => target - last_target,
+				_ => Vector2::zero(),
+			},
+			view_size: Vector2::new(resolution[0] as f64, resolution[1] as f64),
+		};
+		self.last_target = target;
+
+		let mut state = CameraState {
+			focus: self.focus,
+			offset: Vector2::zero(),
+		};
+		for behaviour in &mut self.behaviours {
+			behaviour.update(&mut state, &context);
+		}
+		self.focus = state.focus;
+		camera.pos = state.focus + state.offset;
+	}
+}
@@ -0,0 +1,134 @@
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::render::texture::ImageData;
+use crate::util::asset::AssetError;
+
+pub const SCREENSHOT_DIRECTORY: &str = "screenshots";
+
+/// Which image to capture, with `Renderer::request_capture`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureTarget {
+	/// The first view on the screen at its camera's resolution, straight out of post-processing:
+	/// no scaling, CRT effect or debug shapes. One pixel per game pixel, which is usually what you want for sharing.
+	Intermediate,
+	/// Exactly what's in the window, scaled up and with bars.
+	/// Not every platform allows reading back swapchain images, in which case this is skipped.
+	Output,
+}
+
+impl CaptureTarget {
+	fn name(self) -> &'static str {
+		match self {
+			CaptureTarget::Intermediate => "intermediate",
+			CaptureTarget::Output => "output",
+		}
+	}
+}
+
+/// Saves a captured frame to the screenshot directory with a timestamped name, and returns where it went.
+pub fn save_screenshot(image: &ImageData, target: CaptureTarget) -> Result<PathBuf, AssetError> {
+	fs::create_dir_all(SCREENSHOT_DIRECTORY)?;
+	let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+	let path = Path::new(SCREENSHOT_DIRECTORY)
+		.join(format!("screenshot-{}-{:03}-{}.png", time.as_secs(), time.subsec_millis(), target.name()));
+	image.save_png(&path)?;
+	Ok(path)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SequenceFormat {
+	/// `frame_00000.png`, `frame_00001.png`, ... in a directory. Written as frames come in.
+	Png,
+	/// A single looping GIF, written by `FrameRecorder::finish`. Colors get quantized to 256.
+	Gif,
+}
+
+/// Records every frame drawn during a range of ticks, e.g. for bug reports or trailers.
+///
+/// Each frame, check `wants_frame` and call `Renderer::request_capture` before drawing,
+/// then pass whatever `Renderer::take_captures` gives back to `add_frame`.
+/// Capturing waits for the GPU, so expect the framerate to drop while recording.
+pub struct FrameRecorder {
+	path: PathBuf,
+	target: CaptureTarget,
+	ticks: Range<u32>,
+	format: SequenceFormat,
+	frame_count: u32,
+	/// Only used for GIFs, which can't be written until all the frames are in
+	gif_frames: Vec<(f32, ImageData)>,
+}
+
+impl FrameRecorder {
+	/// `path` is a directory for PNG sequences, or the file to write for GIFs.
+	pub fn new(path: &Path, target: CaptureTarget, ticks: Range<u32>, format: SequenceFormat) -> Self {
+		Self {
+			path: path.to_path_buf(),
+			target,
+			ticks,
+			format,
+			frame_count: 0,
+			gif_frames: Vec::new(),
+		}
+	}
+
+	pub fn get_target(&self) -> CaptureTarget { self.target }
+
+	pub fn wants_frame(&self, tick: u32) -> bool {
+		self.ticks.contains(&tick)
+	}
+
+	/// Whether the range of ticks is over, so it's time to call `finish`.
+	pub fn is_done(&self, tick: u32) -> bool {
+		tick >= self.ticks.end
+	}
+
+	/// `time` is the frame's time in seconds, which is used for GIF frame delays.
+	pub fn add_frame(&mut self, image: ImageData, time: f32) -> Result<(), AssetError> {
+		match self.format {
+			SequenceFormat::Png => {
+				fs::create_dir_all(&self.path)?;
+				image.save_png(&self.path.join(format!("frame_{:05}.png", self.frame_count)))?;
+			},
+			SequenceFormat::Gif => self.gif_frames.push((time, image)),
+		}
+		self.frame_count += 1;
+		Ok(())
+	}
+
+	/// Writes out anything that couldn't be written as it came in. Returns the number of frames recorded.
+	pub fn finish(self) -> Result<u32, AssetError> {
+		if self.format == SequenceFormat::Gif && !self.gif_frames.is_empty() {
+			Self::write_gif(&self.path, &self.gif_frames)?;
+		}
+		Ok(self.frame_count)
+	}
+
+	fn write_gif(path: &Path, frames: &[(f32, ImageData)]) -> Result<(), AssetError> {
+		if let Some(parent) = path.parent() {
+			fs::create_dir_all(parent)?;
+		}
+		let (_, first) = &frames[0];
+		let mut encoder = gif::Encoder::new(
+			BufWriter::new(File::create(path)?), first.width as u16, first.height as u16, &[])?;
+		encoder.set_repeat(gif::Repeat::Infinite)?;
+		for (i, (time, image)) in frames.iter().enumerate() {
+			// GIF delays are in hundredths of a second. The last frame reuses the previous delay.
+			let delay = if let Some((next_time, _)) = frames.get(i + 1) {
+				next_time - time
+			} else if i > 0 {
+				time - frames[i - 1].0
+			} else {
+				0.0
+			};
+			let mut pixels = image.pixels.clone();
+			let mut frame = gif::Frame::from_rgba_speed(image.width as u16, image.height as u16, &mut pixels, 10);
+			frame.delay = (delay * 100.0).round().max(1.0) as u16;
+			encoder.write_frame(&frame)?;
+		}
+		Ok(())
+	}
+}
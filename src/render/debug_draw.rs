@@ -0,0 +1,266 @@
+use std::collections::HashSet;
+use std::f32::consts::PI;
+
+use cgmath::Vector2;
+
+use crate::render::camera::{Camera, PIXEL_OFFSET};
+use crate::render::vert::VertexDebug;
+
+/// Size of the square drawn for `DebugDraw::point`, in pixels
+const POINT_SIZE: f32 = 2.0;
+
+/// Which coordinates a debug shape is in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DebugSpace {
+	/// World pixels, like sprites
+	#[default]
+	World,
+	/// Pixels from the bottom left of the visible area, Y+ up. Stays put when the camera moves.
+	Screen,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DebugShape {
+	Line { from: [f32; 2], to: [f32; 2] },
+	Rect { min: [f32; 2], max: [f32; 2], filled: bool },
+	Circle { center: [f32; 2], radius: f32, filled: bool },
+	Point { pos: [f32; 2] },
+}
+
+#[derive(Debug, Clone)]
+struct DebugCommand {
+	shape: DebugShape,
+	color: [u8; 4],
+	space: DebugSpace,
+	category: &'static str,
+	/// Ticks left before `DebugDraw::tick` removes it
+	ticks_left: u32,
+}
+
+/// Collects lines, rectangles, circles and points for the debug overlay, which is drawn over the scene
+/// after lighting and post-processing so it's always clearly visible.
+///
+/// `FrameBuilder` has one that's emptied every frame. Systems that run during ticks (collisions, AI, etc.)
+/// can keep one of their own to draw into, call `tick` at the start of each tick, and `append_to` the
+/// frame's each frame; shapes then stay up for however many ticks `set_duration` asks for.
+///
+/// Like `SpriteRenderer`, the category, space and duration apply to everything drawn after they're set.
+pub struct DebugDraw {
+	commands: Vec<DebugCommand>,
+	category: &'static str,
+	space: DebugSpace,
+	duration: u32,
+}
+
+impl Default for DebugDraw {
+	fn default() -> Self { Self::new() }
+}
+
+impl DebugDraw {
+	pub fn new() -> Self {
+		Self {
+			commands: Vec::new(),
+			category: "default",
+			space: DebugSpace::World,
+			duration: 1,
+		}
+	}
+
+	/// Sets the category for everything drawn after this call. Categories can be hidden with
+	/// `DebugSettings::set_category_enabled`.
+	pub fn set_category(&mut self, category: &'static str) {
+		self.category = category;
+	}
+
+	pub fn set_space(&mut self, space: DebugSpace) {
+		self.space = space;
+	}
+
+	/// How many ticks shapes drawn after this call stay up for, for a `DebugDraw` that's kept between ticks.
+	/// Defaults to 1, i.e. until the next tick. Ignored by the one in `FrameBuilder`, which is emptied every frame.
+	pub fn set_duration(&mut self, ticks: u32) {
+		self.duration = ticks.max(1);
+	}
+
+	pub fn draw(&mut self, shape: DebugShape, color: [u8; 4]) {
+		self.commands.push(DebugCommand {
+			shape,
+			color,
+			space: self.space,
+			category: self.category,
+			ticks_left: self.duration,
+		});
+	}
+
+	pub fn line(&mut self, from: [f32; 2], to: [f32; 2], color: [u8; 4]) {
+		self.draw(DebugShape::Line { from, to }, color);
+	}
+
+	pub fn rect(&mut self, min: [f32; 2], max: [f32; 2], color: [u8; 4]) {
+		self.draw(DebugShape::Rect { min, max, filled: false }, color);
+	}
+
+	pub fn fill_rect(&mut self, min: [f32; 2], max: [f32; 2], color: [u8; 4]) {
+		self.draw(DebugShape::Rect { min, max, filled: true }, color);
+	}
+
+	pub fn circle(&mut self, center: [f32; 2], radius: f32, color: [u8; 4]) {
+		self.draw(DebugShape::Circle { center, radius, filled: false }, color);
+	}
+
+	pub fn fill_circle(&mut self, center: [f32; 2], radius: f32, color: [u8; 4]) {
+		self.draw(DebugShape::Circle { center, radius, filled: true }, color);
+	}
+
+	pub fn point(&mut self, pos: [f32; 2], color: [u8; 4]) {
+		self.draw(DebugShape::Point { pos }, color);
+	}
+
+	/// Counts down one tick, and removes shapes whose time is up.
+	pub fn tick(&mut self) {
+		self.commands.retain_mut(|command| {
+			command.ticks_left -= 1;
+			command.ticks_left > 0
+		});
+	}
+
+	/// Copies everything drawn here into another `DebugDraw`, usually the frame's.
+	pub fn append_to(&self, other: &mut DebugDraw) {
+		other.commands.extend(self.commands.iter().cloned());
+	}
+
+	pub fn clear(&mut self) {
+		self.commands.clear();
+	}
+
+	/// Converts everything in enabled categories into vertices in normalized device coordinates
+	/// for the overlay image, which is the size of the camera's full resolution.
+	pub(crate) fn build_vertices(&self, camera: &Camera, settings: &DebugSettings) -> DebugVertices {
+		let mut vertices = DebugVertices {
+			lines: Vec::new(),
+			triangles: Vec::new(),
+		};
+		if !settings.is_enabled() {
+			return vertices;
+		}
+
+		let [full_width, full_height] = camera.get_full_resolution();
+		let [_, height] = camera.get_resolution();
+		for command in self.commands.iter().filter(|command| settings.is_category_enabled(command.category)) {
+			let to_ndc = |[x, y]: [f32; 2]| {
+				let pixel = match command.space {
+					DebugSpace::World => camera.world_to_pixel(Vector2::new(x, y)),
+					DebugSpace::Screen => Vector2::new(
+						PIXEL_OFFSET[0] as f32 + x,
+						(PIXEL_OFFSET[1] + height) as f32 - y),
+				};
+				VertexDebug {
+					position: [pixel.x / full_width as f32 * 2.0 - 1.0, pixel.y / full_height as f32 * 2.0 - 1.0],
+					color: command.color,
+				}
+			};
+			// Lines go through the middle of pixels, so a line at a whole pixel coordinate lands on that pixel
+			let center = |[x, y]: [f32; 2]| to_ndc([x + 0.5, y + 0.5]);
+
+			match command.shape {
+				DebugShape::Line { from, to } => {
+					vertices.lines.extend_from_slice(&[center(from), center(to)]);
+				},
+				DebugShape::Rect { min, max, filled: false } => {
+					// Outlines go around the inside edge, so they cover the same pixels as a filled rect
+					let corners = [
+						center(min),
+						center([max[0] - 1.0, min[1]]),
+						center([max[0] - 1.0, max[1] - 1.0]),
+						center([min[0], max[1] - 1.0]),
+					];
+					for i in 0..4 {
+						vertices.lines.extend_from_slice(&[corners[i], corners[(i + 1) % 4]]);
+					}
+				},
+				DebugShape::Rect { min, max, filled: true } => {
+					vertices.push_quad([to_ndc(min), to_ndc([max[0], min[1]]), to_ndc(max), to_ndc([min[0], max[1]])]);
+				},
+				DebugShape::Circle { center: circle_center, radius, filled } => {
+					let segments = ((radius * 2.0).ceil() as usize).clamp(8, 64);
+					let points: Vec<[f32; 2]> = (0..segments)
+						.map(|i| {
+							let angle = i as f32 / segments as f32 * 2.0 * PI;
+							[circle_center[0] + radius * angle.cos(), circle_center[1] + radius * angle.sin()]
+						})
+						.collect();
+					for i in 0..segments {
+						let (a, b) = (points[i], points[(i + 1) % segments]);
+						if filled {
+							vertices.triangles.extend_from_slice(&[to_ndc(circle_center), to_ndc(a), to_ndc(b)]);
+						} else {
+							vertices.lines.extend_from_slice(&[center(a), center(b)]);
+						}
+					}
+				},
+				DebugShape::Point { pos } => {
+					let half = POINT_SIZE / 2.0;
+					let [x, y] = [pos[0] + 0.5, pos[1] + 0.5];
+					vertices.push_quad([
+						to_ndc([x - half, y - half]),
+						to_ndc([x + half, y - half]),
+						to_ndc([x + half, y + half]),
+						to_ndc([x - half, y + half]),
+					]);
+				},
+			}
+		}
+		vertices
+	}
+}
+
+pub(crate) struct DebugVertices {
+	/// Line list
+	pub lines: Vec<VertexDebug>,
+	/// Triangle list
+	pub triangles: Vec<VertexDebug>,
+}
+
+impl DebugVertices {
+	fn push_quad(&mut self, [a, b, c, d]: [VertexDebug; 4]) {
+		self.triangles.extend_from_slice(&[a, b, c, a, c, d]);
+	}
+}
+
+/// Which debug shapes get drawn. Kept by the renderer, so it applies to every `DebugDraw`.
+/// The overlay starts on in debug builds and off in release builds.
+pub struct DebugSettings {
+	enabled: bool,
+	disabled_categories: HashSet<String>,
+}
+
+impl Default for DebugSettings {
+	fn default() -> Self {
+		Self {
+			enabled: cfg!(debug_assertions),
+			disabled_categories: HashSet::new(),
+		}
+	}
+}
+
+impl DebugSettings {
+	/// Turns the whole overlay on or off
+	pub fn set_enabled(&mut self, enabled: bool) {
+		self.enabled = enabled;
+	}
+
+	pub fn is_enabled(&self) -> bool { self.enabled }
+
+	/// Categories are all enabled until turned off here.
+	pub fn set_category_enabled(&mut self, category: &str, enabled: bool) {
+		if enabled {
+			self.disabled_categories.remove(category);
+		} else {
+			self.disabled_categories.insert(category.to_string());
+		}
+	}
+
+	pub fn is_category_enabled(&self, category: &str) -> bool {
+		!self.disabled_categories.contains(category)
+	}
+}
@@ -0,0 +1,125 @@
+use std::cmp::Reverse;
+use std::env;
+use std::sync::Arc;
+
+use vulkano::device::{Device, DeviceExtensions, Features, Queue};
+use vulkano::instance::{Instance, PhysicalDevice, PhysicalDeviceType, QueueFamily};
+use vulkano::swapchain::Surface;
+use winit::window::Window;
+
+use crate::render::error::RendererError;
+
+/// Set this to pick a device by index or (part of) its name, e.g. `RENDER_DEVICE=1` or `RENDER_DEVICE=nvidia`.
+/// Takes priority over the device passed in from config.
+pub const DEVICE_OVERRIDE_VAR: &str = "RENDER_DEVICE";
+
+/// What a device has to support for us to be able to use it.
+pub(crate) struct DeviceRequirements<'a> {
+	pub extensions: DeviceExtensions,
+	/// Extensions that get enabled if the device supports them, but aren't required
+	pub optional_extensions: DeviceExtensions,
+	pub features: Features,
+	/// Surface the graphics queue has to be able to present to, if there's a window
+	pub surface: Option<&'a Arc<Surface<Window>>>,
+}
+
+impl DeviceRequirements<'_> {
+	fn find_queue_family<'a>(&self, physical: PhysicalDevice<'a>) -> Option<QueueFamily<'a>> {
+		physical.queue_families().find(|&q| {
+			q.supports_graphics() && self.surface.is_none_or(|surface| surface.is_supported(q).unwrap_or(false))
+		})
+	}
+
+	fn is_supported_by(&self, physical: PhysicalDevice) -> bool {
+		DeviceExtensions::supported_by_device(physical).intersection(&self.extensions) == self.extensions
+			&& physical.supported_features().superset_of(&self.features)
+			&& self.find_queue_family(physical).is_some()
+	}
+}
+
+/// How much we'd like to use a device, or `None` if we can't use it at all.
+fn score_device(physical: PhysicalDevice, requirements: &DeviceRequirements) -> Option<u32> {
+	if !requirements.is_supported_by(physical) {
+		return None;
+	}
+	let type_score = match physical.ty() {
+		PhysicalDeviceType::DiscreteGpu => 4,
+		PhysicalDeviceType::IntegratedGpu => 3,
+		PhysicalDeviceType::VirtualGpu => 2,
+		PhysicalDeviceType::Cpu => 1,
+		PhysicalDeviceType::Other => 0,
+	};
+	// Max texture size is a rough way of breaking ties between devices of the same type
+	Some(type_score * 1_000_000 + physical.limits().max_image_dimension_2d())
+}
+
+/// Whether `name` refers to this device, either by index or case-insensitive part of its name.
+fn matches_name(physical: PhysicalDevice, name: &str) -> bool {
+	match name.parse::<usize>() {
+		Ok(index) => physical.index() == index,
+		Err(_) => physical.name().to_lowercase().contains(&name.to_lowercase()),
+	}
+}
+
+/// Picks the best device that meets the requirements, preferring discrete GPUs.
+/// `preferred` (a device index or part of a name, usually from config) is used instead if given,
+/// unless `DEVICE_OVERRIDE_VAR` is set. Asking for a device that isn't there or isn't suitable is an error
+/// rather than a silent fallback, so it's obvious when an override isn't doing anything.
+pub(crate) fn select_physical_device<'a>(
+	instance: &'a Arc<Instance>,
+	requirements: &DeviceRequirements,
+	preferred: Option<&str>,
+) -> Result<PhysicalDevice<'a>, RendererError> {
+	let devices: Vec<PhysicalDevice> = PhysicalDevice::enumerate(instance).collect();
+
+	println!("{} devices found:", devices.len());
+	let mut device_list = String::new();
+	for dev in &devices {
+		let score = score_device(*dev, requirements);
+		let line = format!("{}: {} (type: {:?}, score: {:?})", dev.index(), dev.name(), dev.ty(), score);
+		println!("{}", line);
+		device_list.push_str(&line);
+		device_list.push('\n');
+	}
+
+	let override_name = env::var(DEVICE_OVERRIDE_VAR).ok();
+	let physical = match override_name.as_deref().or(preferred) {
+		Some(name) => *devices.iter()
+			.find(|&&dev| matches_name(dev, name) && requirements.is_supported_by(dev))
+			.ok_or_else(|| RendererError::DeviceNotFound(name.to_string()))?,
+		None => *devices.iter()
+			.filter_map(|dev| score_device(*dev, requirements).map(|score| (dev, score)))
+			// Ties go to the first device, which is what the driver lists as its favourite
+			.max_by_key(|&(dev, score)| (score, Reverse(dev.index())))
+			.map(|(dev, _)| dev)
+			.ok_or(RendererError::NoSuitableDevice(device_list))?,
+	};
+
+	println!("Selected device: {}", physical.name());
+
+	Ok(physical)
+}
+
+/// Creates a logical device with a single queue that can do graphics, and present if there's a surface.
+/// `physical` should have come from `select_physical_device` with the same requirements.
+pub(crate) fn create_device(physical: PhysicalDevice, requirements: &DeviceRequirements)
+		-> Result<(Arc<Device>, Arc<Queue>), RendererError> {
+	let queue_family = requirements.find_queue_family(physical)
+		.ok_or_else(|| RendererError::DeviceNotFound(physical.name().to_string()))?;
+
+	println!("Selected queue family: {:?}", queue_family);
+
+	let extensions = requirements.extensions
+		.union(&DeviceExtensions::supported_by_device(physical).intersection(&requirements.optional_extensions));
+	let (device, mut queues) = Device::new(
+		physical,
+		&requirements.features,
+		&extensions,
+		[(queue_family, 0.5)].iter().cloned(),
+	)?;
+
+	// We only have one queue
+	// TODO use multiple queues?
+	let queue = queues.next().unwrap();
+	Ok((device, queue))
+}
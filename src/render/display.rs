@@ -1,12 +1,29 @@
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+
 use vulkano::buffer::cpu_access::WriteLock;
-use crate::render::vert::Vertex3d;
+use crate::render::vert::VertexSprite;
 use vulkano::buffer::CpuAccessibleBuffer;
 use crate::game::Pos;
+use crate::render::atlas::{AtlasRegion, WHITE_PIXEL_UV};
+use crate::render::animation::AnimationEvent;
+use crate::render::debug_draw::DebugDraw;
+use crate::render::tilemap::{Tilemap, TilemapChunkDraw};
+use crate::render::camera::Camera;
+use crate::render::instanced::InstancedSpriteRenderer;
+use crate::render::font::{Font, TextLayout, TextOptions};
+use crate::render::lighting::{Lighting, SpriteLighting};
+use crate::render::palette::SpriteEffects;
+use crate::render::view::RenderTexture;
 
 pub struct DisplayElementComponent(pub Box<dyn DisplayElement + Send + Sync>);
 
 pub trait DisplayElement {
 	fn draw(&self, renderer: &mut SpriteRenderer, pos: &Pos) -> ();
+
+	/// Called once per game tick, for display elements that change over time.
+	fn tick(&mut self) -> Option<AnimationEvent> { None }
 }
 
 pub struct DisplayElementSquare {
@@ -19,9 +36,24 @@ impl DisplayElement for DisplayElementSquare {
 	}
 }
 
+/// Draws a single, unanimated atlas region with its bottom left corner at the entity's position.
+pub struct DisplayElementSprite {
+	pub region: AtlasRegion,
+}
+
+impl DisplayElement for DisplayElementSprite {
+	fn draw(&self, renderer: &mut SpriteRenderer, pos: &Pos) {
+		renderer.draw_sprite(&self.region, pos.x, pos.y);
+	}
+}
+
 /// Stores information needed to render a given frame probably idk
 pub struct FrameBuilder {
 	sprite_renderer: SpriteRenderer,
+	instanced_renderer: InstancedSpriteRenderer,
+	lighting: Lighting,
+	debug_draw: DebugDraw,
+	tilemap_chunks: Vec<TilemapChunkDraw>,
 	time: f32,
 }
 
@@ -29,6 +61,10 @@ impl FrameBuilder {
 	pub fn new(time: f32) -> Self {
 		Self {
 			sprite_renderer: SpriteRenderer::new(),
+			instanced_renderer: InstancedSpriteRenderer::new(),
+			lighting: Lighting::new(),
+			debug_draw: DebugDraw::new(),
+			tilemap_chunks: Vec::new(),
 			time
 		}
 	}
@@ -36,45 +72,323 @@ impl FrameBuilder {
 	pub fn get_time(&self) -> f32 { self.time }
 
 	pub fn get_sprite_renderer(&mut self) -> &mut SpriteRenderer { &mut self.sprite_renderer }
+
+	/// For lots of small sprites, like bullets and particles. See `InstancedSpriteRenderer`.
+	pub fn get_instanced_renderer(&mut self) -> &mut InstancedSpriteRenderer { &mut self.instanced_renderer }
+
+	pub fn get_lighting(&mut self) -> &mut Lighting { &mut self.lighting }
+
+	pub fn get_debug_draw(&mut self) -> &mut DebugDraw { &mut self.debug_draw }
+
+	/// Draws the parts of a tilemap the camera can see. Same as `Tilemap::draw`.
+	pub fn draw_tilemap(&mut self, tilemap: &Tilemap, camera: &Camera) {
+		tilemap.draw(self, camera);
+	}
+
+	pub(crate) fn draw_tilemap_chunk(&mut self, chunk: TilemapChunkDraw) {
+		self.tilemap_chunks.push(chunk);
+	}
+
+	/// Sorted by draw order.
+	pub(crate) fn take_tilemap_chunks(&mut self) -> Vec<TilemapChunkDraw> {
+		let mut chunks = std::mem::take(&mut self.tilemap_chunks);
+		chunks.sort_by_key(|chunk| chunk.order);
+		chunks
+	}
+}
+
+/// Layers are drawn in the order they're declared here, so later layers are on top.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RenderLayer {
+	Background,
+	World,
+	Foreground,
+	Ui,
+}
+
+/// Component controlling where an entity is drawn relative to other entities.
+/// Entities without one are drawn with the default (world layer, sort key 0).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DrawOrder {
+	pub layer: RenderLayer,
+	/// Within a layer, higher sort keys draw on top.
+	/// Sprites with the same layer and sort key draw in the order they were submitted.
+	pub sort_key: i32,
+}
+
+impl DrawOrder {
+	pub fn new(layer: RenderLayer, sort_key: i32) -> Self {
+		Self { layer, sort_key }
+	}
+}
+
+impl Default for DrawOrder {
+	fn default() -> Self {
+		Self::new(RenderLayer::World, 0)
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum BlendMode {
+	/// Normal transparency
+	Alpha,
+	/// Adds to what's underneath, scaled by alpha. For glows, fire, etc.
+	Additive,
+	/// Darkens what's underneath, scaled by alpha. For shadows, tinting, etc.
+	Multiply,
+}
+
+impl BlendMode {
+	pub const ALL: [BlendMode; 3] = [BlendMode::Alpha, BlendMode::Additive, BlendMode::Multiply];
+}
+
+/// What a sprite is drawn from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum SpriteTexture {
+	#[default]
+	Atlas,
+	/// A `RenderTexture`, by ID
+	Render(u64),
+}
+
+// Must match the flags in fs_sprite
+const FLAG_OCCLUDER: u8 = 1;
+const FLAG_INVISIBLE: u8 = 2;
+const FLAG_INDEXED: u8 = 4;
+const FLAG_UNLIT: u8 = 8;
+
+struct SpriteQuad {
+	order: DrawOrder,
+	/// Position in submission order, which breaks ties between sprites with the same draw order
+	sequence: u32,
+	blend_mode: BlendMode,
+	texture: SpriteTexture,
+	vertices: [VertexSprite; 4],
+}
+
+/// The four corners of a sprite with its bottom left corner at `x, y`, in the order `quad_indices` expects.
+pub(crate) fn quad_vertices(x: i32, y: i32, size: [u32; 2], uv: [[f32; 2]; 2], tint: [u8; 4], params: [u8; 4]) -> [VertexSprite; 4] {
+	let x = x as f32;
+	let y = y as f32;
+	let width = size[0] as f32;
+	let height = size[1] as f32;
+	let [uv_min, uv_max] = uv;
+	// Y+ is up in world space, but V+ is down in the texture, hence the V flip.
+	[
+		VertexSprite {position: [x, y, 0.0], uv: [uv_min[0], uv_max[1]], tint, params},
+		VertexSprite {position: [x+width, y, 0.0], uv: [uv_max[0], uv_max[1]], tint, params},
+		VertexSprite {position: [x, y+height, 0.0], uv: [uv_min[0], uv_min[1]], tint, params},
+		VertexSprite {position: [x+width, y+height, 0.0], uv: [uv_max[0], uv_min[1]], tint, params},
+	]
+}
+
+/// Two triangles for a quad whose vertices start at `offset`.
+pub(crate) fn quad_indices(offset: u32) -> [u32; 6] {
+	// 0 1 2 2 1 3
+	[
+		offset,
+		offset+1,
+		offset+2,
+		offset+2,
+		offset+1,
+		offset+3,
+	]
+}
+
+/// Vertex params for sprites drawn with the given lighting and effects, from an indexed region or not. See `VertexSprite`.
+pub(crate) fn sprite_params(lighting: SpriteLighting, effects: SpriteEffects, indexed: bool, layer: RenderLayer) -> [u8; 4] {
+	let mut flags = if lighting.occluder { FLAG_OCCLUDER } else { 0 };
+	if indexed {
+		flags |= FLAG_INDEXED;
+	}
+	if lighting.unlit || layer == RenderLayer::Ui {
+		flags |= FLAG_UNLIT;
+	}
+	[lighting.emissive, flags, effects.palette, effects.flash]
+}
+
+/// A run of indices that can be drawn in one draw call.
+pub struct SpriteBatch {
+	/// Draw order of the first sprite in the batch
+	pub order: DrawOrder,
+	pub blend_mode: BlendMode,
+	pub texture: SpriteTexture,
+	pub indices: Range<usize>,
+}
+
+/// Everything drawn with a `SpriteRenderer`, ready for uploading.
+pub struct SpriteBuffers {
+	pub vertices: Vec<VertexSprite>,
+	pub indices: Vec<u32>,
+	pub batches: Vec<SpriteBatch>,
 }
 
 // SpriteCollector or smth might be a better name? It's instantiated every frame...
 pub struct SpriteRenderer {
-	vertices: Vec<Vertex3d>,
-	indices: Vec<u32>,
+	quads: Vec<SpriteQuad>,
+	draw_order: DrawOrder,
+	blend_mode: BlendMode,
+	lighting: SpriteLighting,
+	effects: SpriteEffects,
 }
 
 impl SpriteRenderer {
 	pub fn new() -> Self {
 		Self {
-			vertices: Vec::new(),
-			indices: Vec::new(),
+			quads: Vec::new(),
+			draw_order: DrawOrder::default(),
+			blend_mode: BlendMode::Alpha,
+			lighting: SpriteLighting::default(),
+			effects: SpriteEffects::default(),
 		}
 	}
 
+	/// Sets the layer and sort key used for everything drawn after this call.
+	pub fn set_draw_order(&mut self, order: DrawOrder) {
+		self.draw_order = order;
+	}
+
+	pub fn get_draw_order(&self) -> DrawOrder { self.draw_order }
+
+	/// Sets the blend mode used for everything drawn after this call.
+	pub fn set_blend_mode(&mut self, blend_mode: BlendMode) {
+		self.blend_mode = blend_mode;
+	}
+
+	pub fn get_blend_mode(&self) -> BlendMode { self.blend_mode }
+
+	/// Sets emissiveness and shadow casting for everything drawn after this call.
+	pub fn set_sprite_lighting(&mut self, lighting: SpriteLighting) {
+		self.lighting = lighting;
+	}
+
+	pub fn get_sprite_lighting(&self) -> SpriteLighting { self.lighting }
+
+	/// Sets the palette and flash for everything drawn after this call.
+	pub fn set_sprite_effects(&mut self, effects: SpriteEffects) {
+		self.effects = effects;
+	}
+
+	pub fn get_sprite_effects(&self) -> SpriteEffects { self.effects }
+
+	/// Draws with the given blend mode, then switches back to the previous one.
+	pub fn with_blend_mode<F: FnOnce(&mut Self)>(&mut self, blend_mode: BlendMode, f: F) {
+		let previous = self.blend_mode;
+		self.blend_mode = blend_mode;
+		f(self);
+		self.blend_mode = previous;
+	}
+
 	/// Draw an 8x8 square. For testing until actual rendering stuff is implemented.
 	pub fn draw_test_square(&mut self, x: i32, y: i32) {
-		let x = x as f32;
-		let y = y as f32;
-		let offset = self.vertices.len() as u32;
-		self.vertices.extend(vec![
-			Vertex3d {position: [x, y, 0.0]},
-			Vertex3d {position: [x+8.0, y, 0.0]},
-			Vertex3d {position: [x, y+8.0, 0.0]},
-			Vertex3d {position: [x+8.0, y+8.0, 0.0]},
-		].into_iter());
-		// 0 1 2 2 1 3
-		self.indices.extend([
-			offset,
-			offset+1,
-			offset+2,
-			offset+2,
-			offset+1,
-			offset+3,
-		].iter());
-	}
-
-	pub fn get_buffers(&self) -> (&Vec<Vertex3d>, &Vec<u32>) {
-		(&self.vertices, &self.indices)
-	}
-}
\ No newline at end of file
+		self.draw_rect(x, y, 8, 8, [255, 255, 255, 255]);
+	}
+
+	/// Draw a solid colored rectangle with its bottom left corner at `x, y`.
+	pub fn draw_rect(&mut self, x: i32, y: i32, width: u32, height: u32, color: [u8; 4]) {
+		self.push_quad(x, y, [width, height], [WHITE_PIXEL_UV, WHITE_PIXEL_UV], color, false);
+	}
+
+	/// Draw an invisible rectangle that casts shadows.
+	pub fn draw_occluder_rect(&mut self, x: i32, y: i32, width: u32, height: u32) {
+		let params = [self.lighting.emissive, FLAG_OCCLUDER | FLAG_INVISIBLE, 0, 0];
+		self.push_quad_with_params(x, y, [width, height], [WHITE_PIXEL_UV, WHITE_PIXEL_UV], [255, 255, 255, 255], params);
+	}
+
+	/// Draw an atlas region at its actual size with its bottom left corner at `x, y`.
+	pub fn draw_sprite(&mut self, region: &AtlasRegion, x: i32, y: i32) {
+		self.draw_sprite_tinted(region, x, y, [255, 255, 255, 255]);
+	}
+
+	/// Like `draw_sprite`, with the texture's colors multiplied by `tint`.
+	pub fn draw_sprite_tinted(&mut self, region: &AtlasRegion, x: i32, y: i32, tint: [u8; 4]) {
+		let (x, y) = (x + region.offset[0], y + region.offset[1]);
+		self.push_quad(x, y, [region.width, region.height], [region.uv_min, region.uv_max], tint, region.indexed);
+	}
+
+	/// Draws whatever was last drawn into a render texture, stretched to `size` with its bottom left corner at `x, y`.
+	/// Skipped if nothing has been drawn into it yet. Always unlit, since the texture was lit by its own view.
+	pub fn draw_render_texture(&mut self, texture: &RenderTexture, x: i32, y: i32, size: [u32; 2]) {
+		let lighting = SpriteLighting { unlit: true, ..self.lighting };
+		self.quads.push(SpriteQuad {
+			order: self.draw_order,
+			sequence: self.quads.len() as u32,
+			blend_mode: self.blend_mode,
+			texture: SpriteTexture::Render(texture.get_id()),
+			vertices: quad_vertices(x, y, size, [[0.0, 0.0], [1.0, 1.0]], [255, 255, 255, 255], sprite_params(lighting, self.effects, false, self.draw_order.layer)),
+		});
+	}
+
+	/// Draws text with the top left of the block at `x, y`. Lines go downwards from there.
+	pub fn draw_text(&mut self, font: &Font, text: &str, x: i32, y: i32, options: &TextOptions, tint: [u8; 4]) {
+		self.draw_text_layout(&font.layout(text, options), x, y, |_, _| tint);
+	}
+
+	/// Like `draw_text`, with a separate tint for each character, e.g. for highlighting words in dialogue.
+	/// `tint` gets the index of each character in `text` (counted in chars), and the character itself.
+	pub fn draw_text_with<F: FnMut(usize, char) -> [u8; 4]>(
+		&mut self, font: &Font, text: &str, x: i32, y: i32, options: &TextOptions, tint: F,
+	) {
+		self.draw_text_layout(&font.layout(text, options), x, y, tint);
+	}
+
+	/// Draws text that's already been laid out, for text that gets drawn every frame without changing.
+	pub fn draw_text_layout<F: FnMut(usize, char) -> [u8; 4]>(&mut self, layout: &TextLayout, x: i32, y: i32, mut tint: F) {
+		for glyph in &layout.glyphs {
+			let tint = tint(glyph.char_index, glyph.character);
+			self.draw_sprite_tinted(&glyph.region, x + glyph.pos[0], y + glyph.pos[1], tint);
+		}
+	}
+
+	fn push_quad(&mut self, x: i32, y: i32, size: [u32; 2], uv: [[f32; 2]; 2], tint: [u8; 4], indexed: bool) {
+		self.push_quad_with_params(x, y, size, uv, tint, sprite_params(self.lighting, self.effects, indexed, self.draw_order.layer));
+	}
+
+	fn push_quad_with_params(&mut self, x: i32, y: i32, size: [u32; 2], uv: [[f32; 2]; 2], tint: [u8; 4], params: [u8; 4]) {
+		self.quads.push(SpriteQuad {
+			order: self.draw_order,
+			sequence: self.quads.len() as u32,
+			blend_mode: self.blend_mode,
+			texture: SpriteTexture::Atlas,
+			vertices: quad_vertices(x, y, size, uv, tint, params),
+		});
+	}
+
+	/// Sorts everything drawn so far by draw order and flattens it into vertex and index buffers.
+	/// A new batch is started wherever the blend mode or texture changes, and wherever the draw order reaches one of
+	/// `breaks`, so that something else can be drawn in between.
+	// There's no depth buffer, so the z coordinate is unused and ordering is purely draw order.
+	// Translucent sprites need back-to-front drawing anyway, which a depth test can't give us.
+	pub fn build_buffers(&mut self, breaks: &[DrawOrder]) -> SpriteBuffers {
+		self.quads.sort_unstable_by_key(|quad| (quad.order, quad.sequence));
+
+		let mut vertices = Vec::with_capacity(self.quads.len() * 4);
+		let mut indices = Vec::with_capacity(self.quads.len() * 6);
+		let mut batches: Vec<SpriteBatch> = Vec::new();
+		let mut previous_order = None;
+		for quad in self.quads.iter() {
+			let crosses_break = previous_order.is_some_and(|previous|
+				breaks.iter().any(|&order| previous < order && order <= quad.order));
+			previous_order = Some(quad.order);
+			match batches.last_mut() {
+				Some(batch) if batch.blend_mode == quad.blend_mode && batch.texture == quad.texture && !crosses_break =>
+					batch.indices.end += 6,
+				_ => batches.push(SpriteBatch {
+					order: quad.order,
+					blend_mode: quad.blend_mode,
+					texture: quad.texture,
+					indices: indices.len()..indices.len()+6,
+				}),
+			}
+
+			let offset = vertices.len() as u32;
+			vertices.extend_from_slice(&quad.vertices);
+			indices.extend_from_slice(&quad_indices(offset));
+		}
+		SpriteBuffers {
+			vertices,
+			indices,
+			batches,
+		}
+	}
+}
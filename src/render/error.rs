@@ -0,0 +1,156 @@
+use std::fmt;
+
+use vulkano::OomError;
+use vulkano::command_buffer::{
+	AutoCommandBufferBuilderContextError, BeginRenderPassError, BuildError, CommandBufferExecError, CopyBufferError,
+	CopyBufferImageError, DrawError, DrawIndexedError,
+};
+use vulkano::descriptor::descriptor_set::{PersistentDescriptorSetBuildError, PersistentDescriptorSetError};
+use vulkano::device::DeviceCreationError;
+use vulkano::framebuffer::{FramebufferCreationError, RenderPassCreationError};
+use vulkano::image::ImageCreationError;
+use vulkano::instance::InstanceCreationError;
+use vulkano::memory::DeviceMemoryAllocError;
+use vulkano::pipeline::GraphicsPipelineCreationError;
+use vulkano::sampler::SamplerCreationError;
+use vulkano::swapchain::{AcquireError, CapabilitiesError, SwapchainCreationError};
+use vulkano::sync::FlushError;
+
+/// Something went wrong setting up or talking to the GPU, badly enough that we can't carry on drawing.
+/// Anything recoverable (e.g. the swapchain going out of date on resize) is dealt with internally instead.
+#[derive(Debug)]
+pub enum RendererError {
+	Instance(InstanceCreationError),
+	Window(vulkano_win::CreationError),
+	/// No device supports everything we need. The string lists what was found, for the error message.
+	NoSuitableDevice(String),
+	/// The device asked for by config or `DEVICE_OVERRIDE_VAR` doesn't exist or isn't suitable
+	DeviceNotFound(String),
+	Device(DeviceCreationError),
+	SurfaceCapabilities(CapabilitiesError),
+	/// The window surface is missing something we need to present to it, e.g. any image formats
+	UnsupportedSurface(&'static str),
+	SwapchainCreation(SwapchainCreationError),
+	Acquire(AcquireError),
+	Flush(FlushError),
+	OutOfMemory(OomError),
+	Allocation(DeviceMemoryAllocError),
+	Image(ImageCreationError),
+	Sampler(SamplerCreationError),
+	RenderPass(RenderPassCreationError),
+	Pipeline(GraphicsPipelineCreationError),
+	Framebuffer(FramebufferCreationError),
+	/// A color grading LUT that isn't `height` slices of `height` x `height`. Holds its width and height.
+	InvalidLut([u32; 2]),
+	/// Descriptor sets that don't match their layout. Kept as a message, since there's nothing useful to match on.
+	DescriptorSet(String),
+	/// Commands that vulkano rejected while recording or submitting them, also kept as a message
+	CommandBuffer(String),
+}
+
+impl fmt::Display for RendererError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			RendererError::Instance(e) => write!(f, "Failed to create Vulkan instance (is a Vulkan driver installed?): {}", e),
+			RendererError::Window(e) => write!(f, "Failed to create window: {}", e),
+			RendererError::NoSuitableDevice(devices) => write!(f, "No suitable graphics device found. Devices available:\n{}", devices),
+			RendererError::DeviceNotFound(name) => write!(f, "Requested graphics device {:?} not found or not suitable", name),
+			RendererError::Device(e) => write!(f, "Failed to create device: {}", e),
+			RendererError::SurfaceCapabilities(e) => write!(f, "Failed to query window surface: {}", e),
+			RendererError::UnsupportedSurface(missing) => write!(f, "Window surface supports no {}", missing),
+			RendererError::SwapchainCreation(e) => write!(f, "Failed to create swapchain: {}", e),
+			RendererError::Acquire(e) => write!(f, "Failed to acquire swapchain image: {}", e),
+			RendererError::Flush(e) => write!(f, "Failed to submit frame: {}", e),
+			RendererError::OutOfMemory(e) => write!(f, "Out of memory: {}", e),
+			RendererError::Allocation(e) => write!(f, "Failed to allocate GPU memory: {}", e),
+			RendererError::Image(e) => write!(f, "Failed to create image: {}", e),
+			RendererError::Sampler(e) => write!(f, "Failed to create sampler: {}", e),
+			RendererError::RenderPass(e) => write!(f, "Failed to create render pass: {}", e),
+			RendererError::Pipeline(e) => write!(f, "Failed to create pipeline: {}", e),
+			RendererError::Framebuffer(e) => write!(f, "Failed to create framebuffer: {}", e),
+			RendererError::InvalidLut([width, height]) => write!(f,
+				"Color grading LUT is {}x{}, but should be N slices of NxN side by side (N at least 2)", width, height),
+			RendererError::DescriptorSet(e) => write!(f, "Failed to create descriptor set: {}", e),
+			RendererError::CommandBuffer(e) => write!(f, "Failed to record commands: {}", e),
+		}
+	}
+}
+
+impl std::error::Error for RendererError {}
+
+impl From<InstanceCreationError> for RendererError {
+	fn from(e: InstanceCreationError) -> Self { RendererError::Instance(e) }
+}
+
+impl From<vulkano_win::CreationError> for RendererError {
+	fn from(e: vulkano_win::CreationError) -> Self { RendererError::Window(e) }
+}
+
+impl From<DeviceCreationError> for RendererError {
+	fn from(e: DeviceCreationError) -> Self { RendererError::Device(e) }
+}
+
+impl From<CapabilitiesError> for RendererError {
+	fn from(e: CapabilitiesError) -> Self { RendererError::SurfaceCapabilities(e) }
+}
+
+impl From<SwapchainCreationError> for RendererError {
+	fn from(e: SwapchainCreationError) -> Self { RendererError::SwapchainCreation(e) }
+}
+
+impl From<AcquireError> for RendererError {
+	fn from(e: AcquireError) -> Self { RendererError::Acquire(e) }
+}
+
+impl From<FlushError> for RendererError {
+	fn from(e: FlushError) -> Self { RendererError::Flush(e) }
+}
+
+impl From<OomError> for RendererError {
+	fn from(e: OomError) -> Self { RendererError::OutOfMemory(e) }
+}
+
+impl From<DeviceMemoryAllocError> for RendererError {
+	fn from(e: DeviceMemoryAllocError) -> Self { RendererError::Allocation(e) }
+}
+
+impl From<ImageCreationError> for RendererError {
+	fn from(e: ImageCreationError) -> Self { RendererError::Image(e) }
+}
+
+impl From<SamplerCreationError> for RendererError {
+	fn from(e: SamplerCreationError) -> Self { RendererError::Sampler(e) }
+}
+
+impl From<RenderPassCreationError> for RendererError {
+	fn from(e: RenderPassCreationError) -> Self { RendererError::RenderPass(e) }
+}
+
+impl From<GraphicsPipelineCreationError> for RendererError {
+	fn from(e: GraphicsPipelineCreationError) -> Self { RendererError::Pipeline(e) }
+}
+
+impl From<FramebufferCreationError> for RendererError {
+	fn from(e: FramebufferCreationError) -> Self { RendererError::Framebuffer(e) }
+}
+
+/// For the errors that are only kept as a message
+macro_rules! from_message {
+	($variant:ident: $($error:ty),* $(,)?) => {
+		$(impl From<$error> for RendererError {
+			fn from(e: $error) -> Self { RendererError::$variant(e.to_string()) }
+		})*
+	};
+}
+
+from_message!(DescriptorSet: PersistentDescriptorSetError, PersistentDescriptorSetBuildError);
+from_message!(CommandBuffer:
+	AutoCommandBufferBuilderContextError,
+	BeginRenderPassError,
+	BuildError,
+	CommandBufferExecError,
+	CopyBufferError,
+	CopyBufferImageError,
+	DrawError,
+	DrawIndexedError,
+);
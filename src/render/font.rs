@@ -0,0 +1,527 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::render::atlas::{Atlas, AtlasRegion};
+use crate::render::sprite_sheet::{SpriteFrame, SpriteSheet};
+use crate::render::texture::ImageData;
+use crate::util::asset::AssetError;
+
+/// Drawn in place of characters the font doesn't have, if the font has it
+const FALLBACK_CHAR: char = '?';
+
+/// Where a glyph sits relative to the pen, in pixels. Offsets are from the top of the line, Y+ down,
+/// which is how BMFont describes them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GlyphMetrics {
+	pub offset: [i32; 2],
+	/// How far to move the pen along after drawing this glyph
+	pub advance: i32,
+}
+
+/// A bitmap font's image and metrics. Like `SpriteSheet`, it doesn't do anything until it's added to an
+/// atlas and turned into a `Font` with `Font::from_sheet`.
+#[derive(Debug, Clone)]
+pub struct FontSheet {
+	/// One frame per glyph with any pixels, named by the character's code point
+	pub sheet: SpriteSheet,
+	pub glyphs: HashMap<char, GlyphMetrics>,
+	/// Kerning adjustments to the advance between pairs of characters
+	pub kerning: HashMap<(char, char), i32>,
+	/// Distance from one line to the next
+	pub line_height: u32,
+}
+
+impl FontSheet {
+	/// A fixed-width pixel font, with characters laid out in a grid in row-major order.
+	/// `chars` lists the character in each cell; leftover cells are ignored.
+	/// Any spacing between glyphs should be part of the cells, since they're drawn right next to each other.
+	pub fn from_grid(image: ImageData, cell_width: u32, cell_height: u32, chars: &str) -> Result<Self, AssetError> {
+		if cell_width == 0 || cell_height == 0 {
+			return Err(AssetError::Invalid(format!("Invalid font cell size {}x{}", cell_width, cell_height)));
+		}
+		let columns = image.width / cell_width;
+		let rows = image.height / cell_height;
+		let mut frames = Vec::new();
+		let mut glyphs = HashMap::new();
+		for (i, c) in chars.chars().enumerate().take((rows * columns) as usize) {
+			let i = i as u32;
+			frames.push(SpriteFrame::new(
+				frame_name(c), (i % columns) * cell_width, (i / columns) * cell_height, cell_width, cell_height, 0));
+			glyphs.insert(c, GlyphMetrics {
+				offset: [0, 0],
+				advance: cell_width as i32,
+			});
+		}
+		Ok(Self {
+			sheet: SpriteSheet {
+				image,
+				frames,
+				tags: Vec::new(),
+			},
+			glyphs,
+			kerning: HashMap::new(),
+			line_height: cell_height,
+		})
+	}
+
+	/// Loads an AngelCode BMFont in the text format (usually `.fnt`), as written by BMFont, Hiero, Littera, etc.
+	/// The page image is loaded relative to the font file. Only single-page fonts are supported,
+	/// and channel-packed fonts aren't; export with the glyphs in color or white on transparent.
+	pub fn load_bmfont(path: &Path) -> Result<Self, AssetError> {
+		let text = fs::read_to_string(path)?;
+		let invalid = |msg: String| AssetError::Invalid(format!("{:?}: {}", path, msg));
+
+		let mut line_height = None;
+		let mut page_file = None;
+		let mut frames = Vec::new();
+		let mut glyphs = HashMap::new();
+		let mut kerning = HashMap::new();
+		for line in text.lines() {
+			let (tag, values) = parse_bmfont_line(line);
+			let get = |key: &str| -> Result<i32, AssetError> {
+				values.get(key)
+					.ok_or_else(|| invalid(format!("{} is missing {}", tag, key)))?
+					.parse()
+					.map_err(|_| invalid(format!("{} has invalid {}", tag, key)))
+			};
+			let get_char = |key: &str| -> Result<char, AssetError> {
+				std::char::from_u32(get(key)? as u32).ok_or_else(|| invalid(format!("{} has invalid {}", tag, key)))
+			};
+			match tag {
+				"common" => {
+					line_height = Some(get("lineHeight")? as u32);
+					if values.get("pages").is_some_and(|pages| pages != "1") {
+						return Err(invalid("only single-page fonts are supported".to_string()));
+					}
+					if values.get("packed").is_some_and(|packed| packed != "0") {
+						return Err(invalid("channel-packed fonts aren't supported".to_string()));
+					}
+				},
+				"page" => {
+					page_file = Some(values.get("file")
+						.ok_or_else(|| invalid("page is missing file".to_string()))?
+						.clone());
+				},
+				"char" => {
+					let c = get_char("id")?;
+					let (width, height) = (get("width")? as u32, get("height")? as u32);
+					// Spaces and the like have nothing to draw
+					if width > 0 && height > 0 {
+						frames.push(SpriteFrame::new(frame_name(c), get("x")? as u32, get("y")? as u32, width, height, 0));
+					}
+					glyphs.insert(c, GlyphMetrics {
+						offset: [get("xoffset")?, get("yoffset")?],
+						advance: get("xadvance")?,
+					});
+				},
+				"kerning" => {
+					kerning.insert((get_char("first")?, get_char("second")?), get("amount")?);
+				},
+				_ => {},
+			}
+		}
+
+		let line_height = line_height.ok_or_else(|| invalid("missing common line".to_string()))?;
+		let page_file = page_file.ok_or_else(|| invalid("missing page line".to_string()))?;
+		let image = ImageData::load_png(&path.parent().unwrap_or_else(|| Path::new("")).join(page_file))?;
+		for frame in &frames {
+			if frame.x + frame.width > image.width || frame.y + frame.height > image.height {
+				return Err(invalid(format!("glyph {} lies outside the image", frame.name)));
+			}
+		}
+
+		Ok(Self {
+			sheet: SpriteSheet {
+				image,
+				frames,
+				tags: Vec::new(),
+			},
+			glyphs,
+			kerning,
+			line_height,
+		})
+	}
+
+	/// A tiny built-in 3x5 font with uppercase letters, digits and some punctuation, for debug text
+	/// when there aren't any font assets around. Lowercase letters are drawn as uppercase.
+	pub fn debug() -> Self {
+		const CELL: [u32; 2] = [4, 6];
+		let chars: String = DEBUG_GLYPHS.iter().map(|&(c, _)| c)
+			.chain(DEBUG_GLYPHS.iter().map(|&(c, _)| c).filter(char::is_ascii_uppercase).map(|c| c.to_ascii_lowercase()))
+			.collect();
+		let rows: Vec<[u8; 5]> = DEBUG_GLYPHS.iter().map(|&(_, rows)| rows)
+			.chain(DEBUG_GLYPHS.iter().filter(|(c, _)| c.is_ascii_uppercase()).map(|&(_, rows)| rows))
+			.collect();
+		let columns = 16;
+		let image_rows = (rows.len() as u32).div_ceil(columns);
+		let image = ImageData::from_fn(columns * CELL[0], image_rows * CELL[1], |x, y| {
+			let glyph = ((y / CELL[1]) * columns + x / CELL[0]) as usize;
+			let (x, y) = (x % CELL[0], y % CELL[1]);
+			let lit = glyph < rows.len() && x < 3 && y < 5 && rows[glyph][y as usize] & (0b100 >> x) != 0;
+			if lit { [255, 255, 255, 255] } else { [0, 0, 0, 0] }
+		});
+		Self::from_grid(image, CELL[0], CELL[1], &chars).expect("Invalid debug font")
+	}
+}
+
+fn frame_name(c: char) -> String {
+	(c as u32).to_string()
+}
+
+/// Splits a BMFont text line like `char id=65 x=0 file="a b.png"` into its tag and key/value pairs.
+fn parse_bmfont_line(line: &str) -> (&str, HashMap<&str, String>) {
+	let line = line.trim();
+	let (tag, mut rest) = line.split_at(line.find(' ').unwrap_or(line.len()));
+	let mut values = HashMap::new();
+	loop {
+		rest = rest.trim_start();
+		let Some(eq) = rest.find('=') else { break };
+		let key = &rest[..eq];
+		rest = &rest[eq + 1..];
+		let value = if let Some(quoted) = rest.strip_prefix('"') {
+			let end = quoted.find('"').unwrap_or(quoted.len());
+			rest = quoted.get(end + 1..).unwrap_or("");
+			&quoted[..end]
+		} else {
+			let end = rest.find(' ').unwrap_or(rest.len());
+			let value = &rest[..end];
+			rest = &rest[end..];
+			value
+		};
+		values.insert(key, value.to_string());
+	}
+	(tag, values)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Glyph {
+	/// `None` for glyphs with nothing to draw, like spaces
+	pub region: Option<AtlasRegion>,
+	pub metrics: GlyphMetrics,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TextAlign {
+	#[default]
+	Left,
+	Center,
+	Right,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TextOptions {
+	/// Lines are aligned within the width of the text block: `wrap_width` if set, otherwise the longest line
+	pub align: TextAlign,
+	/// Breaks lines between words to keep them within this many pixels.
+	/// Words too long to fit on a line by themselves overflow rather than being split.
+	pub wrap_width: Option<u32>,
+}
+
+/// A glyph positioned relative to the top left of a block of text.
+#[derive(Debug, Clone, Copy)]
+pub struct PlacedGlyph {
+	/// Index of the character in the original string, counted in chars
+	pub char_index: usize,
+	pub character: char,
+	pub region: AtlasRegion,
+	/// Bottom left corner of the glyph, Y+ up like world space, so Y is always negative
+	pub pos: [i32; 2],
+}
+
+/// Text laid out and ready to draw, from `Font::layout`.
+#[derive(Debug, Clone)]
+pub struct TextLayout {
+	pub glyphs: Vec<PlacedGlyph>,
+	/// Width of the text block and total height of its lines, in pixels
+	pub size: [u32; 2],
+}
+
+/// A bitmap font whose glyphs have been packed into an atlas, ready for drawing with
+/// `SpriteRenderer::draw_text`. Everything is in whole pixels, so text stays crisp.
+pub struct Font {
+	glyphs: HashMap<char, Glyph>,
+	kerning: HashMap<(char, char), i32>,
+	line_height: u32,
+}
+
+impl Font {
+	/// `font_name` is the name the font's sheet was given when it was added to the atlas.
+	pub fn from_sheet(atlas: &Atlas, font_name: &str, font: &FontSheet) -> Self {
+		let glyphs = font.glyphs.iter()
+			.map(|(&c, &metrics)| {
+				let region = atlas.get_region(&format!("{}/{}", font_name, frame_name(c)));
+				(c, Glyph { region, metrics })
+			})
+			.collect();
+		Self {
+			glyphs,
+			kerning: font.kerning.clone(),
+			line_height: font.line_height,
+		}
+	}
+
+	pub fn get_line_height(&self) -> u32 { self.line_height }
+
+	fn glyph(&self, c: char) -> Option<&Glyph> {
+		self.glyphs.get(&c).or_else(|| self.glyphs.get(&FALLBACK_CHAR))
+	}
+
+	fn kerning(&self, previous: Option<char>, c: char) -> i32 {
+		previous.and_then(|previous| self.kerning.get(&(previous, c)).copied()).unwrap_or(0)
+	}
+
+	/// Width of a single line of characters, not counting trailing spaces
+	fn line_width(&self, chars: &[(usize, char)]) -> i32 {
+		let trimmed = chars.iter().rposition(|&(_, c)| c != ' ').map_or(0, |last| last + 1);
+		let mut width = 0;
+		let mut previous = None;
+		for &(_, c) in &chars[..trimmed] {
+			width += self.kerning(previous, c) + self.glyph(c).map_or(0, |glyph| glyph.metrics.advance);
+			previous = Some(c);
+		}
+		width
+	}
+
+	/// Breaks a paragraph into lines no wider than `wrap_width`, at spaces.
+	fn wrap(&self, chars: &[(usize, char)], wrap_width: Option<u32>) -> Vec<Vec<(usize, char)>> {
+		let wrap_width = match wrap_width {
+			Some(wrap_width) => wrap_width as i32,
+			None => return vec![chars.to_vec()],
+		};
+		let mut lines = Vec::new();
+		let mut line: Vec<(usize, char)> = Vec::new();
+		let mut rest = chars;
+		while !rest.is_empty() {
+			// The next word, along with the spaces before it
+			let spaces = rest.iter().take_while(|&&(_, c)| c == ' ').count();
+			let word = rest[spaces..].iter().take_while(|&&(_, c)| c != ' ').count();
+			let (token, remaining) = rest.split_at(spaces + word);
+			rest = remaining;
+
+			let mut extended = line.clone();
+			extended.extend_from_slice(token);
+			if !line.is_empty() && self.line_width(&extended) > wrap_width {
+				// The spaces at the break get dropped
+				lines.push(std::mem::replace(&mut line, token[spaces..].to_vec()));
+			} else {
+				line = extended;
+			}
+		}
+		lines.push(line);
+		lines
+	}
+
+	/// Works out where every glyph goes, relative to the top left of the text. `\n` starts a new line.
+	pub fn layout(&self, text: &str, options: &TextOptions) -> TextLayout {
+		let chars: Vec<(usize, char)> = text.chars().enumerate().collect();
+		let lines: Vec<Vec<(usize, char)>> = chars.split(|&(_, c)| c == '\n')
+			.flat_map(|paragraph| self.wrap(paragraph, options.wrap_width))
+			.collect();
+
+		let widths: Vec<i32> = lines.iter().map(|line| self.line_width(line)).collect();
+		let block_width = options.wrap_width
+			.map(|wrap_width| wrap_width as i32)
+			.unwrap_or_else(|| widths.iter().copied().max().unwrap_or(0));
+
+		let mut glyphs = Vec::new();
+		for (i, (line, width)) in lines.iter().zip(widths).enumerate() {
+			let top = -((i as u32 * self.line_height) as i32);
+			let mut pen = match options.align {
+				TextAlign::Left => 0,
+				// Rounds down, so odd leftovers don't put glyphs between pixels
+				TextAlign::Center => (block_width - width).div_euclid(2),
+				TextAlign::Right => block_width - width,
+			};
+			let mut previous = None;
+			for &(char_index, c) in line {
+				let glyph = match self.glyph(c) {
+					Some(glyph) => glyph,
+					None => continue,
+				};
+				pen += self.kerning(previous, c);
+				if let Some(region) = glyph.region {
+					glyphs.push(PlacedGlyph {
+						char_index,
+						character: c,
+						region,
+						pos: [
+							pen + glyph.metrics.offset[0],
+							top - glyph.metrics.offset[1] - region.height as i32,
+						],
+					});
+				}
+				pen += glyph.metrics.advance;
+				previous = Some(c);
+			}
+		}
+
+		TextLayout {
+			glyphs,
+			size: [block_width.max(0) as u32, lines.len() as u32 * self.line_height],
+		}
+	}
+
+	/// Size of the text in pixels, as it would be drawn with these options.
+	pub fn measure(&self, text: &str, options: &TextOptions) -> [u32; 2] {
+		self.layout(text, options).size
+	}
+}
+
+/// 3x5 glyphs for `FontSheet::debug`. Each row is 3 bits, left pixel in the highest bit.
+const DEBUG_GLYPHS: &[(char, [u8; 5])] = &[
+	(' ', [0b000, 0b000, 0b000, 0b000, 0b000]),
+	('0', [0b111, 0b101, 0b101, 0b101, 0b111]),
+	('1', [0b010, 0b110, 0b010, 0b010, 0b111]),
+	('2', [0b111, 0b001, 0b111, 0b100, 0b111]),
+	('3', [0b111, 0b001, 0b111, 0b001, 0b111]),
+	('4', [0b101, 0b101, 0b111, 0b001, 0b001]),
+	('5', [0b111, 0b100, 0b111, 0b001, 0b111]),
+	('6', [0b111, 0b100, 0b111, 0b101, 0b111]),
+	('7', [0b111, 0b001, 0b010, 0b010, 0b010]),
+	('8', [0b111, 0b101, 0b111, 0b101, 0b111]),
+	('9', [0b111, 0b101, 0b111, 0b001, 0b111]),
+	('A', [0b010, 0b101, 0b111, 0b101, 0b101]),
+	('B', [0b110, 0b101, 0b110, 0b101, 0b110]),
+	('C', [0b011, 0b100, 0b100, 0b100, 0b011]),
+	('D', [0b110, 0b101, 0b101, 0b101, 0b110]),
+	('E', [0b111, 0b100, 0b110, 0b100, 0b111]),
+	('F', [0b111, 0b100, 0b110, 0b100, 0b100]),
+	('G', [0b011, 0b100, 0b101, 0b101, 0b011]),
+	('H', [0b101, 0b101, 0b111, 0b101, 0b101]),
+	('I', [0b111, 0b010, 0b010, 0b010, 0b111]),
+	('J', [0b001, 0b001, 0b001, 0b101, 0b010]),
+	('K', [0b101, 0b101, 0b110, 0b101, 0b101]),
+	('L', [0b100, 0b100, 0b100, 0b100, 0b111]),
+	('M', [0b101, 0b111, 0b111, 0b101, 0b101]),
+	('N', [0b110, 0b101, 0b101, 0b101, 0b101]),
+	('O', [0b010, 0b101, 0b101, 0b101, 0b010]),
+	('P', [0b110, 0b101, 0b110, 0b100, 0b100]),
+	('Q', [0b010, 0b101, 0b101, 0b110, 0b011]),
+	('R', [0b110, 0b101, 0b110, 0b101, 0b101]),
+	('S', [0b011, 0b100, 0b010, 0b001, 0b110]),
+	('T', [0b111, 0b010, 0b010, 0b010, 0b010]),
+	('U', [0b101, 0b101, 0b101, 0b101, 0b111]),
+	('V', [0b101, 0b101, 0b101, 0b101, 0b010]),
+	('W', [0b101, 0b101, 0b111, 0b111, 0b101]),
+	('X', [0b101, 0b101, 0b010, 0b101, 0b101]),
+	('Y', [0b101, 0b101, 0b010, 0b010, 0b010]),
+	('Z', [0b111, 0b001, 0b010, 0b100, 0b111]),
+	('.', [0b000, 0b000, 0b000, 0b000, 0b010]),
+	(',', [0b000, 0b000, 0b000, 0b010, 0b100]),
+	(':', [0b000, 0b010, 0b000, 0b010, 0b000]),
+	('!', [0b010, 0b010, 0b010, 0b000, 0b010]),
+	('?', [0b110, 0b001, 0b010, 0b000, 0b010]),
+	('-', [0b000, 0b000, 0b111, 0b000, 0b000]),
+	('+', [0b000, 0b010, 0b111, 0b010, 0b000]),
+	('=', [0b000, 0b111, 0b000, 0b111, 0b000]),
+	('/', [0b001, 0b001, 0b010, 0b100, 0b100]),
+	('(', [0b001, 0b010, 0b010, 0b010, 0b001]),
+	(')', [0b100, 0b010, 0b010, 0b010, 0b100]),
+	('\'', [0b010, 0b010, 0b000, 0b000, 0b000]),
+	('%', [0b101, 0b001, 0b010, 0b100, 0b101]),
+	('_', [0b000, 0b000, 0b000, 0b000, 0b111]),
+];
+
+#[cfg(test)]
+mod tests {
+	use std::path::PathBuf;
+
+	use super::*;
+	use crate::render::atlas::AtlasBuilder;
+
+	fn font(sheet: &FontSheet) -> Font {
+		let mut builder = AtlasBuilder::new();
+		builder.add_sprite_sheet("font", &sheet.sheet);
+		Font::from_sheet(&builder.build().unwrap(), "font", sheet)
+	}
+
+	/// Fixed width: 4x6 cells, so every glyph advances 4 and lines are 6 apart
+	fn grid_font() -> Font {
+		font(&FontSheet::from_grid(ImageData::new(16, 6), 4, 6, "AB ?").unwrap())
+	}
+
+	fn write_bmfont() -> PathBuf {
+		let dir = std::env::temp_dir().join(format!("font_test_{}", std::process::id()));
+		fs::create_dir_all(&dir).unwrap();
+		ImageData::new(16, 8).save_png(&dir.join("font page.png")).unwrap();
+		let path = dir.join("font.fnt");
+		fs::write(&path, "\
+info face=\"Test\" size=8
+common lineHeight=10 base=8 scaleW=16 scaleH=8 pages=1 packed=0
+page id=0 file=\"font page.png\"
+chars count=3
+char id=65 x=0 y=0 width=5 height=7 xoffset=0 yoffset=1 xadvance=6 page=0
+char id=86 x=5 y=0 width=5 height=7 xoffset=1 yoffset=1 xadvance=6 page=0
+char id=32 x=0 y=0 width=0 height=0 xoffset=0 yoffset=0 xadvance=3 page=0
+kernings count=1
+kerning first=65 second=86 amount=-2
+").unwrap();
+		path
+	}
+
+	fn placed(layout: &TextLayout) -> Vec<(usize, char, [i32; 2])> {
+		layout.glyphs.iter().map(|glyph| (glyph.char_index, glyph.character, glyph.pos)).collect()
+	}
+
+	fn options(align: TextAlign, wrap_width: Option<u32>) -> TextOptions {
+		TextOptions { align, wrap_width }
+	}
+
+	#[test]
+	fn wrapped() {
+		let layout = grid_font().layout("AB A", &options(TextAlign::Left, Some(8)));
+		// The space at the break isn't drawn
+		assert_eq!(placed(&layout), [(0, 'A', [0, -6]), (1, 'B', [4, -6]), (3, 'A', [0, -12])]);
+		assert_eq!(layout.size, [8, 12]);
+	}
+
+	#[test]
+	fn long_words_overflow() {
+		let layout = grid_font().layout("AAA B", &options(TextAlign::Left, Some(8)));
+		let lines: Vec<_> = layout.glyphs.iter().map(|glyph| glyph.pos[1]).collect();
+		assert_eq!(lines, [-6, -6, -6, -12]);
+		assert_eq!(layout.size, [8, 12]);
+	}
+
+	#[test]
+	fn aligned() {
+		let font = grid_font();
+		let xs = |align, wrap_width| -> Vec<i32> {
+			font.layout("AB A", &options(align, wrap_width)).glyphs.iter().map(|glyph| glyph.pos[0]).collect()
+		};
+		// 1 pixel left over on the first line, which rounds down
+		assert_eq!(xs(TextAlign::Center, Some(9)), [0, 4, 2]);
+		assert_eq!(xs(TextAlign::Right, Some(9)), [1, 5, 5]);
+
+		// Without wrapping, lines are aligned within the longest one
+		let layout = font.layout("AB\nA", &options(TextAlign::Center, None));
+		assert_eq!(placed(&layout), [(0, 'A', [0, -6]), (1, 'B', [4, -6]), (3, 'A', [2, -12])]);
+		assert_eq!(layout.size, [8, 12]);
+	}
+
+	#[test]
+	fn fallback() {
+		let layout = grid_font().layout("Az", &options(TextAlign::Left, None));
+		assert_eq!(placed(&layout), [(0, 'A', [0, -6]), (1, 'z', [4, -6])]);
+	}
+
+	#[test]
+	fn bmfont_kerning_and_offsets() {
+		let sheet = FontSheet::load_bmfont(&write_bmfont()).unwrap();
+		assert_eq!(sheet.line_height, 10);
+		assert_eq!(sheet.kerning[&('A', 'V')], -2);
+		// Nothing to draw for the space
+		assert_eq!(sheet.sheet.frames.len(), 2);
+
+		let layout = font(&sheet).layout("AVA V", &TextOptions::default());
+		assert_eq!(placed(&layout), [
+			(0, 'A', [0, -8]),
+			// Kerned 2 closer to the A, plus its own x offset of 1
+			(1, 'V', [5, -8]),
+			(2, 'A', [10, -8]),
+			(4, 'V', [20, -8]),
+		]);
+		assert_eq!(layout.size, [25, 10]);
+	}
+}
@@ -0,0 +1,193 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::device::{Device, DeviceExtensions, Queue};
+use vulkano::format::Format;
+use vulkano::instance::{Instance, InstanceExtensions};
+use vulkano::sync::{self, GpuFuture};
+
+use crate::render::atlas::Atlas;
+use crate::render::camera::{Camera, DEFAULT_RESOLUTION};
+use crate::render::debug_draw::DebugSettings;
+use crate::render::display::FrameBuilder;
+use crate::render::palette::PaletteSet;
+use crate::render::post::PostProcessing;
+use crate::render::device::{self, DeviceRequirements};
+use crate::render::error::RendererError;
+use crate::render::renderer::{Readback, ReadbackTarget, RenderData};
+use crate::render::texture::ImageData;
+use crate::render::view::View;
+use crate::util::asset::AssetError;
+
+/// 8 bits per channel so it can be read back straight into an `ImageData`
+const OUTPUT_FORMAT: Format = Format::R8G8B8A8Srgb;
+
+/// Set this to overwrite golden images with whatever is rendered now, after an intended change.
+pub const UPDATE_GOLDEN_IMAGES_VAR: &str = "UPDATE_GOLDEN_IMAGES";
+
+/// Renders frames without a window or swapchain and reads them back to the CPU, e.g. for golden-image tests.
+/// Frames go through exactly the same passes as with `Renderer`, but the output pass draws at 1x
+/// instead of scaling up to a window, so the result is the visible area at the camera's resolution.
+///
+/// Doesn't need any instance or device extensions, so it runs on software implementations like lavapipe.
+pub struct HeadlessRenderer {
+	device: Arc<Device>,
+	queue: Arc<Queue>,
+	data: RenderData,
+	output: ReadbackTarget,
+	/// Uploads waiting to happen before the next frame
+	previous_frame_end: Option<Box<dyn GpuFuture>>,
+}
+
+impl HeadlessRenderer {
+	/// Picks a device the same way as `Renderer::init`, including `DEVICE_OVERRIDE_VAR`.
+	pub fn init() -> Result<Self, RendererError> {
+		let instance = Instance::new(None, &InstanceExtensions::none(), None)?;
+		let requirements = DeviceRequirements {
+			extensions: DeviceExtensions::none(),
+			optional_extensions: DeviceExtensions::none(),
+			features: RenderData::required_features(),
+			surface: None,
+		};
+		let physical = device::select_physical_device(&instance, &requirements, None)?;
+		let (device, queue) = device::create_device(physical, &requirements)?;
+
+		let (data, upload_future) = RenderData::init(&device, &queue, OUTPUT_FORMAT)?;
+		let output = ReadbackTarget::new(&device, &data, OUTPUT_FORMAT, DEFAULT_RESOLUTION)?;
+
+		let previous_frame_end = Some(sync::now(device.clone()).join(upload_future).boxed());
+
+		Ok(Self {
+			device,
+			queue,
+			data,
+			output,
+			previous_frame_end,
+		})
+	}
+
+	/// See `Renderer::upload_atlas`.
+	pub fn upload_atlas(&mut self, atlas: &Atlas) -> Result<(), RendererError> {
+		let upload_future = self.data.set_atlas(atlas, &self.queue)?;
+		self.join_previous_frame(upload_future);
+		Ok(())
+	}
+
+	/// See `Renderer::upload_palettes`.
+	pub fn upload_palettes(&mut self, palettes: &PaletteSet) -> Result<(), RendererError> {
+		let upload_future = self.data.set_palettes(palettes, &self.queue)?;
+		self.join_previous_frame(upload_future);
+		Ok(())
+	}
+
+	/// See `Renderer::set_color_grading_lut`.
+	pub fn set_color_grading_lut(&mut self, lut: &ImageData) -> Result<(), RendererError> {
+		let upload_future = self.data.set_color_grading_lut(lut, &self.queue)?;
+		self.join_previous_frame(upload_future);
+		Ok(())
+	}
+
+	pub fn get_post_processing(&mut self) -> &mut PostProcessing {
+		&mut self.data.post_processing
+	}
+
+	pub fn get_debug_settings(&mut self) -> &mut DebugSettings {
+		&mut self.data.debug_settings
+	}
+
+	/// Renders a frame and waits for it to finish. Returns the visible area, `camera.get_resolution()` in size.
+	pub fn render(&mut self, frame: FrameBuilder, camera: &Camera) -> Result<ImageData, RendererError> {
+		self.render_views(camera.get_resolution(), vec![View::new(frame, camera)])
+	}
+
+	/// Like `Renderer::draw_views`. Returns the whole screen, `screen_resolution` in size.
+	pub fn render_views(&mut self, screen_resolution: [u32; 2], views: Vec<View>) -> Result<ImageData, RendererError> {
+		if self.output.image.dimensions() != screen_resolution {
+			self.output = ReadbackTarget::new(&self.device, &self.data, OUTPUT_FORMAT, screen_resolution)?;
+		}
+
+		let mut builder = AutoCommandBufferBuilder::primary_one_time_submit(
+			self.device.clone(),
+			self.queue.family(),
+		)?;
+
+		let view_outputs = self.data.record_views(&mut builder, &self.device, views)?;
+		self.data.record_output(
+			&mut builder,
+			&view_outputs,
+			screen_resolution,
+			&self.output.framebuffer,
+			&self.output.dynamic_state,
+			[0.0, 0.0, 0.0],
+		)?;
+		let readback = Readback::record(
+			&mut builder, &self.device, self.output.image.clone(), [0, 0], screen_resolution, OUTPUT_FORMAT)?;
+		let command_buffer = builder.build()?;
+
+		let result = self.take_previous_frame_end()
+			.then_execute(self.queue.clone(), command_buffer)?
+			.then_signal_fence_and_flush()
+			.and_then(|future| future.wait(None));
+		self.previous_frame_end = Some(sync::now(self.device.clone()).boxed());
+		result?;
+
+		Ok(readback.read())
+	}
+
+	/// See `Renderer::take_previous_frame_end`.
+	fn take_previous_frame_end(&mut self) -> Box<dyn GpuFuture> {
+		self.previous_frame_end.take().unwrap_or_else(|| sync::now(self.device.clone()).boxed())
+	}
+
+	fn join_previous_frame(&mut self, future: Box<dyn GpuFuture>) {
+		self.previous_frame_end = Some(self.take_previous_frame_end().join(future).boxed());
+	}
+}
+
+/// Compares a rendered image with the PNG at `path`, allowing each channel to be off by up to `tolerance`
+/// to cover differences between GPUs. If `UPDATE_GOLDEN_IMAGES` is set, the image is saved there instead,
+/// which is the only way golden images get created; a missing one is an error, so a test can't pass without one.
+///
+/// On a mismatch the rendered image is saved next to the golden one with `.actual.png` on the end, for comparison.
+pub fn check_golden_image(image: &ImageData, path: &Path, tolerance: u8) -> Result<(), AssetError> {
+	if let Some(parent) = path.parent() {
+		fs::create_dir_all(parent)?;
+	}
+	if env::var_os(UPDATE_GOLDEN_IMAGES_VAR).is_some() {
+		println!("Writing golden image {:?}", path);
+		return image.save_png(path);
+	}
+	if !path.exists() {
+		image.save_png(&path.with_extension("actual.png"))?;
+		return Err(AssetError::Invalid(
+			format!("Golden image {:?} doesn't exist; set {} to create it", path, UPDATE_GOLDEN_IMAGES_VAR)));
+	}
+
+	let golden = ImageData::load_png(path)?;
+	let error = if golden.width != image.width || golden.height != image.height {
+		Some(format!("{:?} is {}x{}, but the rendered image is {}x{}",
+			path, golden.width, golden.height, image.width, image.height))
+	} else {
+		let max_difference = golden.pixels.iter()
+			.zip(&image.pixels)
+			.map(|(&a, &b)| a.abs_diff(b))
+			.max()
+			.unwrap_or(0);
+		if max_difference > tolerance {
+			Some(format!("Rendered image differs from {:?} by up to {} (tolerance {})", path, max_difference, tolerance))
+		} else {
+			None
+		}
+	};
+
+	match error {
+		Some(error) => {
+			image.save_png(&path.with_extension("actual.png"))?;
+			Err(AssetError::Invalid(error))
+		},
+		None => Ok(()),
+	}
+}
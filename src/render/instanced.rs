@@ -0,0 +1,132 @@
+use crate::render::atlas::{AtlasRegion, WHITE_PIXEL_UV};
+use crate::render::display::{self, BlendMode, DrawOrder};
+use crate::render::lighting::SpriteLighting;
+use crate::render::palette::SpriteEffects;
+use crate::render::vert::InstanceSprite;
+
+/// Instances with the same draw order and blend mode, which can be drawn in one draw call.
+pub(crate) struct InstanceBatch {
+	pub order: DrawOrder,
+	pub blend_mode: BlendMode,
+	pub instances: Vec<InstanceSprite>,
+}
+
+/// Like `SpriteRenderer`, but each sprite is a single `InstanceSprite` that the vertex shader expands
+/// into a quad, instead of four vertices and six indices built on the CPU. Much cheaper for thousands
+/// of small things like bullets and particles.
+///
+/// Instanced sprites are drawn under `SpriteRenderer` sprites with the same draw order. Within the same
+/// draw order they keep submission order, as long as the blend mode doesn't keep changing; each change
+/// starts another draw call.
+pub struct InstancedSpriteRenderer {
+	batches: Vec<InstanceBatch>,
+	draw_order: DrawOrder,
+	blend_mode: BlendMode,
+	lighting: SpriteLighting,
+	effects: SpriteEffects,
+}
+
+impl Default for InstancedSpriteRenderer {
+	fn default() -> Self { Self::new() }
+}
+
+impl InstancedSpriteRenderer {
+	pub fn new() -> Self {
+		Self {
+			batches: Vec::new(),
+			draw_order: DrawOrder::default(),
+			blend_mode: BlendMode::Alpha,
+			lighting: SpriteLighting::default(),
+			effects: SpriteEffects::default(),
+		}
+	}
+
+	/// Sets the layer and sort key used for everything drawn after this call.
+	pub fn set_draw_order(&mut self, order: DrawOrder) {
+		self.draw_order = order;
+	}
+
+	pub fn get_draw_order(&self) -> DrawOrder { self.draw_order }
+
+	/// Sets the blend mode used for everything drawn after this call.
+	pub fn set_blend_mode(&mut self, blend_mode: BlendMode) {
+		self.blend_mode = blend_mode;
+	}
+
+	pub fn get_blend_mode(&self) -> BlendMode { self.blend_mode }
+
+	/// Sets emissiveness and shadow casting for everything drawn after this call.
+	pub fn set_sprite_lighting(&mut self, lighting: SpriteLighting) {
+		self.lighting = lighting;
+	}
+
+	pub fn get_sprite_lighting(&self) -> SpriteLighting { self.lighting }
+
+	/// Sets the palette and flash for everything drawn after this call.
+	pub fn set_sprite_effects(&mut self, effects: SpriteEffects) {
+		self.effects = effects;
+	}
+
+	pub fn get_sprite_effects(&self) -> SpriteEffects { self.effects }
+
+	/// Draw a solid colored rectangle with its bottom left corner at `x, y`.
+	pub fn draw_rect(&mut self, x: i32, y: i32, width: u32, height: u32, color: [u8; 4]) {
+		self.push(InstanceSprite {
+			origin: [x as f32, y as f32],
+			size: [width as f32, height as f32],
+			uv_min: WHITE_PIXEL_UV,
+			uv_max: WHITE_PIXEL_UV,
+			tint: color,
+			params: display::sprite_params(self.lighting, self.effects, false, self.draw_order.layer),
+		});
+	}
+
+	/// Draw an atlas region at its actual size with its bottom left corner at `x, y`.
+	pub fn draw_sprite(&mut self, region: &AtlasRegion, x: i32, y: i32) {
+		self.draw_sprite_tinted(region, x, y, [255, 255, 255, 255]);
+	}
+
+	/// Like `draw_sprite`, with the texture's colors multiplied by `tint`.
+	pub fn draw_sprite_tinted(&mut self, region: &AtlasRegion, x: i32, y: i32, tint: [u8; 4]) {
+		self.push(InstanceSprite {
+			origin: [(x + region.offset[0]) as f32, (y + region.offset[1]) as f32],
+			size: [region.width as f32, region.height as f32],
+			uv_min: region.uv_min,
+			uv_max: region.uv_max,
+			tint,
+			params: display::sprite_params(self.lighting, self.effects, region.indexed, self.draw_order.layer),
+		});
+	}
+
+	/// Adds an instance as is, ignoring the current lighting and effects. Positions don't have to be whole pixels,
+	/// but sprites that aren't will be blurry or uneven.
+	pub fn push(&mut self, instance: InstanceSprite) {
+		match self.batches.last_mut() {
+			Some(batch) if batch.order == self.draw_order && batch.blend_mode == self.blend_mode => {
+				batch.instances.push(instance);
+			},
+			_ => self.batches.push(InstanceBatch {
+				order: self.draw_order,
+				blend_mode: self.blend_mode,
+				instances: vec![instance],
+			}),
+		}
+	}
+
+	/// How many instances have been drawn so far
+	pub fn len(&self) -> usize {
+		self.batches.iter().map(|batch| batch.instances.len()).sum()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.batches.is_empty()
+	}
+
+	/// Everything drawn so far, sorted by draw order.
+	pub(crate) fn take_batches(&mut self) -> Vec<InstanceBatch> {
+		let mut batches = std::mem::take(&mut self.batches);
+		// Stable, so ties keep submission order
+		batches.sort_by_key(|batch| batch.order);
+		batches
+	}
+}
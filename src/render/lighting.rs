@@ -0,0 +1,89 @@
+use cgmath::Vector2;
+
+/// Lights past this many in a frame are ignored. Must match MAX_LIGHTS in fs_lighting.
+pub const MAX_LIGHTS: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LightCone {
+	/// Direction the cone points in, in radians counterclockwise from +X
+	pub direction: f32,
+	/// Half of the cone's total angle, in radians
+	pub half_angle: f32,
+}
+
+/// A light source. As a component, the light sits at the entity's `Pos`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Light {
+	pub color: [f32; 3],
+	/// Brightness at the center. Can go above 1, since lighting is done in HDR.
+	pub intensity: f32,
+	/// Distance in pixels at which the light falls off to nothing
+	pub radius: f32,
+	/// None for a point light
+	pub cone: Option<LightCone>,
+}
+
+impl Light {
+	pub fn point(color: [f32; 3], intensity: f32, radius: f32) -> Self {
+		Self {
+			color,
+			intensity,
+			radius,
+			cone: None,
+		}
+	}
+
+	pub fn cone(color: [f32; 3], intensity: f32, radius: f32, direction: f32, half_angle: f32) -> Self {
+		Self {
+			color,
+			intensity,
+			radius,
+			cone: Some(LightCone { direction, half_angle }),
+		}
+	}
+}
+
+/// Component for how an entity's sprites interact with lighting.
+/// Entities without one are lit, aren't emissive and don't cast shadows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SpriteLighting {
+	/// How much the sprite lights itself, from 0 (not at all) to 255 (full brightness regardless of lights)
+	pub emissive: u8,
+	/// Whether the sprite's opaque pixels cast shadows
+	pub occluder: bool,
+	/// Whether to skip lighting and draw the sprite's colors as they are. Sprites in the UI layer always are.
+	pub unlit: bool,
+}
+
+/// Collects the lights for a frame. Lit sprites are multiplied by the ambient color plus any lights
+/// reaching them, so the default (white ambient, no lights) leaves everything unchanged.
+pub struct Lighting {
+	ambient: [f32; 3],
+	lights: Vec<(Vector2<f32>, Light)>,
+}
+
+impl Lighting {
+	pub fn new() -> Self {
+		Self {
+			ambient: [1.0, 1.0, 1.0],
+			lights: Vec::new(),
+		}
+	}
+
+	pub fn set_ambient(&mut self, ambient: [f32; 3]) {
+		self.ambient = ambient;
+	}
+
+	pub fn get_ambient(&self) -> [f32; 3] { self.ambient }
+
+	/// Adds a light at the given world position.
+	pub fn add_light(&mut self, x: f32, y: f32, light: Light) {
+		self.lights.push((Vector2::new(x, y), light));
+	}
+
+	pub fn get_lights(&self) -> &[(Vector2<f32>, Light)] { &self.lights }
+}
+
+impl Default for Lighting {
+	fn default() -> Self { Self::new() }
+}
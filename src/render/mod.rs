@@ -1,4 +1,27 @@
 pub mod camera;
 pub mod vert;
 pub mod renderer;
+pub mod device;
+pub mod error;
+pub mod video;
 pub mod display;
+pub mod texture;
+pub mod atlas;
+pub mod sprite_sheet;
+pub mod animation;
+pub mod lighting;
+pub mod post;
+pub mod scaling;
+pub mod headless;
+pub mod capture;
+pub mod font;
+pub mod debug_draw;
+pub mod tilemap;
+pub mod instanced;
+pub mod particles;
+pub mod parallax;
+pub mod camera_rig;
+pub mod view;
+pub mod palette;
+pub mod shader_reload;
+pub mod shader_interface;
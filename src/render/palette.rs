@@ -0,0 +1,153 @@
+use crate::render::texture::ImageData;
+use crate::util::asset::AssetError;
+
+/// Colors in each palette. Indexed images have one byte per pixel, so this is as many as they can use.
+pub const PALETTE_SIZE: usize = 256;
+/// Cycles past this many (across every palette) are ignored. Must match MAX_PALETTE_CYCLES in fs_sprite.
+pub const MAX_PALETTE_CYCLES: usize = 16;
+
+/// Rotates a run of a palette's colors over time, for things like flowing water and flickering lights
+/// without any extra frames of animation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PaletteCycle {
+	/// First color in the run
+	pub start: u8,
+	/// How many colors are in the run
+	pub length: u8,
+	/// How long before the colors move along by one
+	pub step_ms: u32,
+}
+
+/// Colors for drawing indexed images with. Index 0 is always transparent, whatever color it's given.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Palette {
+	/// Up to `PALETTE_SIZE`. Indices past the end are transparent.
+	pub colors: Vec<[u8; 4]>,
+	pub cycles: Vec<PaletteCycle>,
+}
+
+impl Palette {
+	pub fn new(colors: Vec<[u8; 4]>) -> Self {
+		Self {
+			colors,
+			cycles: Vec::new(),
+		}
+	}
+
+	/// Reads colors left to right along the top row of an image, e.g. a palette strip exported from an art program.
+	pub fn from_image(image: &ImageData) -> Self {
+		let width = image.width.min(PALETTE_SIZE as u32);
+		Self::new((0..width).map(|x| image.get_pixel(x, 0)).collect())
+	}
+
+	pub fn with_cycle(mut self, start: u8, length: u8, step_ms: u32) -> Self {
+		self.cycles.push(PaletteCycle { start, length, step_ms });
+		self
+	}
+}
+
+/// Every palette the game uses, uploaded together with `Renderer::upload_palettes`.
+/// Sprites pick one by its index here, with `SpriteEffects::palette`.
+#[derive(Debug, Clone, Default)]
+pub struct PaletteSet {
+	palettes: Vec<Palette>,
+}
+
+impl PaletteSet {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Returns the palette's index. There can be up to 256 palettes.
+	pub fn add(&mut self, palette: Palette) -> u8 {
+		assert!(self.palettes.len() < 256, "Too many palettes");
+		assert!(palette.colors.len() <= PALETTE_SIZE, "Palettes can have at most {} colors", PALETTE_SIZE);
+		self.palettes.push(palette);
+		(self.palettes.len() - 1) as u8
+	}
+
+	pub fn get(&self, index: u8) -> Option<&Palette> {
+		self.palettes.get(index as usize)
+	}
+
+	/// One row per palette, with unused colors transparent. Always at least one row, so there's something to upload.
+	pub(crate) fn build_image(&self) -> ImageData {
+		let mut image = ImageData::new(PALETTE_SIZE as u32, self.palettes.len().max(1) as u32);
+		for (y, palette) in self.palettes.iter().enumerate() {
+			for (x, &color) in palette.colors.iter().enumerate().skip(1) {
+				image.set_pixel(x as u32, y as u32, color);
+			}
+		}
+		image
+	}
+
+	/// Every palette's cycles, along with the index of the palette they belong to
+	pub(crate) fn cycles(&self) -> Vec<(u8, PaletteCycle)> {
+		self.palettes.iter()
+			.enumerate()
+			.flat_map(|(i, palette)| palette.cycles.iter().map(move |&cycle| (i as u8, cycle)))
+			.collect()
+	}
+}
+
+/// Component for recoloring an entity's sprites. Entities without one use the first palette, without flashing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SpriteEffects {
+	/// Which palette indexed sprites are drawn with, e.g. for enemy variants. Doesn't affect other sprites.
+	pub palette: u8,
+	/// How far to mix the sprite's colors towards white, from 0 (not at all) to 255 (completely white),
+	/// e.g. for flashing when hit. Works on any sprite.
+	pub flash: u8,
+}
+
+/// A sprite stored as palette indices rather than colors, so it can be drawn with any palette.
+/// Added to an atlas with `AtlasBuilder::add_indexed_image`.
+#[derive(Debug, Clone)]
+pub struct IndexedImage {
+	pub width: u32,
+	pub height: u32,
+	/// Row-major, top row first, one index per pixel. 0 is transparent.
+	pub indices: Vec<u8>,
+}
+
+impl IndexedImage {
+	pub fn from_fn<F: Fn(u32, u32) -> u8>(width: u32, height: u32, f: F) -> Self {
+		let indices = (0..height)
+			.flat_map(|y| (0..width).map(move |x| (x, y)))
+			.map(|(x, y)| f(x, y))
+			.collect();
+		Self { width, height, indices }
+	}
+
+	/// Converts an image drawn with `palette` to indices, e.g. art drawn in the first of several palette swaps.
+	/// Fully transparent pixels become index 0. Fails if any other pixel isn't one of the palette's colors.
+	pub fn from_image(image: &ImageData, palette: &Palette) -> Result<Self, AssetError> {
+		let mut indices = Vec::with_capacity((image.width * image.height) as usize);
+		for y in 0..image.height {
+			for x in 0..image.width {
+				let pixel = image.get_pixel(x, y);
+				let index = if pixel[3] == 0 {
+					0
+				} else {
+					palette.colors.iter()
+						.skip(1)
+						.position(|&color| color == pixel)
+						.map(|i| i + 1)
+						.ok_or_else(|| AssetError::Invalid(format!("Color {:?} at {}, {} isn't in the palette", pixel, x, y)))?
+				};
+				indices.push(index as u8);
+			}
+		}
+		Ok(Self { width: image.width, height: image.height, indices })
+	}
+
+	/// How indexed images are stored in the atlas: the index goes in alpha, which is the one channel
+	/// that comes out of an sRGB texture exactly as it went in.
+	pub(crate) fn to_atlas_image(&self) -> ImageData {
+		let mut image = ImageData::new(self.width, self.height);
+		for (pixel, &index) in image.pixels.chunks_mut(4).zip(&self.indices) {
+			pixel.copy_from_slice(&[255, 255, 255, index]);
+		}
+		image
+	}
+}
@@ -0,0 +1,139 @@
+use crate::render::atlas::AtlasRegion;
+use crate::render::camera::{Camera, PIXEL_OFFSET};
+use crate::render::display::{DrawOrder, FrameBuilder, RenderLayer};
+use crate::render::lighting::SpriteLighting;
+
+/// Parallax layers go in the background layer at this sort key and up, one per layer,
+/// so they're under anything else in the background drawn with the default sort key of 0 (e.g. tilemaps).
+pub const PARALLAX_SORT_KEY_BASE: i32 = -1000;
+
+/// One image in a `ParallaxBackground`.
+#[derive(Debug, Clone)]
+pub struct ParallaxLayer {
+	pub region: AtlasRegion,
+	/// How much the layer moves when the camera does, on each axis. 0 stays put on screen (e.g. a distant sky),
+	/// 1 moves with the world, and anything in between looks further away the closer it is to 0.
+	pub scroll_factor: [f32; 2],
+	/// Where the image's bottom left corner is when the bottom left of the view is at the world origin.
+	/// For a scroll factor of 0 that's a position on screen; for 1 it's a position in the world.
+	pub offset: [f32; 2],
+	/// Whether to tile the image to fill the view along each axis
+	pub repeat: [bool; 2],
+	/// Movement on top of parallax, in pixels per second, for things like clouds
+	pub auto_scroll: [f32; 2],
+	pub tint: [u8; 4],
+	pub lighting: SpriteLighting,
+}
+
+impl ParallaxLayer {
+	pub fn new(region: AtlasRegion, scroll_factor: [f32; 2]) -> Self {
+		Self {
+			region,
+			scroll_factor,
+			offset: [0.0, 0.0],
+			repeat: [false, false],
+			auto_scroll: [0.0, 0.0],
+			tint: [255, 255, 255, 255],
+			lighting: SpriteLighting::default(),
+		}
+	}
+
+	pub fn with_offset(mut self, offset: [f32; 2]) -> Self {
+		self.offset = offset;
+		self
+	}
+
+	pub fn with_repeat(mut self, repeat_x: bool, repeat_y: bool) -> Self {
+		self.repeat = [repeat_x, repeat_y];
+		self
+	}
+
+	pub fn with_auto_scroll(mut self, auto_scroll: [f32; 2]) -> Self {
+		self.auto_scroll = auto_scroll;
+		self
+	}
+
+	pub fn with_tint(mut self, tint: [u8; 4]) -> Self {
+		self.tint = tint;
+		self
+	}
+
+	pub fn with_lighting(mut self, lighting: SpriteLighting) -> Self {
+		self.lighting = lighting;
+		self
+	}
+}
+
+/// Layers of scenery that scroll slower than the camera, so levels get some depth.
+/// Everything is drawn in the background layer, under tilemaps and sprites.
+#[derive(Debug, Clone, Default)]
+pub struct ParallaxBackground {
+	/// Furthest away first
+	layers: Vec<ParallaxLayer>,
+}
+
+impl ParallaxBackground {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Adds a layer in front of the ones already added.
+	pub fn add_layer(&mut self, layer: ParallaxLayer) {
+		self.layers.push(layer);
+	}
+
+	pub fn get_layers(&self) -> &[ParallaxLayer] { &self.layers }
+
+	pub fn get_layers_mut(&mut self) -> &mut [ParallaxLayer] { &mut self.layers }
+
+	/// Draws every layer at wherever the camera puts it. Auto-scrolling uses the frame's time.
+	/// Layers with an empty image are skipped.
+	pub fn draw(&self, frame: &mut FrameBuilder, camera: &Camera) {
+		let time = frame.get_time();
+		// Everything in the intermediate image, including the margin, since it can end up on screen
+		let [view_x, view_y] = camera.get_view_origin();
+		let view_min = [view_x - PIXEL_OFFSET[0] as i32, view_y - PIXEL_OFFSET[1] as i32];
+		let [full_width, full_height] = camera.get_full_resolution();
+		let view_max = [view_min[0] + full_width as i32, view_min[1] + full_height as i32];
+
+		let sprite_renderer = frame.get_sprite_renderer();
+		let (previous_order, previous_lighting) = (sprite_renderer.get_draw_order(), sprite_renderer.get_sprite_lighting());
+		for (i, layer) in self.layers.iter().enumerate() {
+			// Trimmed images still repeat at their original size
+			let size = [layer.region.source_size[0] as i32, layer.region.source_size[1] as i32];
+			// Nothing to draw, and tiling by zero would never get past the view
+			if size[0] <= 0 || size[1] <= 0 {
+				continue;
+			}
+			sprite_renderer.set_draw_order(DrawOrder::new(RenderLayer::Background, PARALLAX_SORT_KEY_BASE + i as i32));
+			sprite_renderer.set_sprite_lighting(layer.lighting);
+
+			// Rounded so layers move in whole pixels like everything else
+			let pos = [0, 1].map(|axis| {
+				let view = [view_x, view_y][axis] as f32;
+				(layer.offset[axis] + view * (1.0 - layer.scroll_factor[axis]) + layer.auto_scroll[axis] * time).round() as i32
+			});
+			// Repeating layers start from the copy just before the view, and go until they're past it
+			let ranges = [0, 1].map(|axis| {
+				if layer.repeat[axis] {
+					let first = pos[axis] + (view_min[axis] - pos[axis]).div_euclid(size[axis]) * size[axis];
+					(first, view_max[axis])
+				} else {
+					(pos[axis], pos[axis] + 1)
+				}
+			});
+
+			let mut y = ranges[1].0;
+			while y < ranges[1].1 {
+				let mut x = ranges[0].0;
+				while x < ranges[0].1 {
+					sprite_renderer.draw_sprite_tinted(&layer.region, x, y, layer.tint);
+					x += size[0];
+				}
+				y += size[1];
+			}
+		}
+		sprite_renderer.set_draw_order(previous_order);
+		sprite_renderer.set_sprite_lighting(previous_lighting);
+	}
+}
@@ -0,0 +1,457 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::render::animation::{self, AnimationFrame};
+use crate::render::atlas::{Atlas, AtlasRegion, WHITE_PIXEL_UV};
+use crate::render::display::{self, BlendMode};
+use crate::render::instanced::InstancedSpriteRenderer;
+use crate::render::lighting::SpriteLighting;
+use crate::render::vert::InstanceSprite;
+use crate::util::asset::{self, AssetError};
+use crate::util::random::Rng;
+use crate::util::timing::{ms_to_ticks, TICKS_PER_SECOND};
+
+/// Something that can be blended between keyframes of a `Curve`.
+pub trait Lerp: Copy {
+	fn lerp(self, other: Self, t: f32) -> Self;
+}
+
+impl Lerp for f32 {
+	fn lerp(self, other: Self, t: f32) -> Self {
+		self + (other - self) * t
+	}
+}
+
+impl Lerp for [u8; 4] {
+	fn lerp(self, other: Self, t: f32) -> Self {
+		let mut result = [0; 4];
+		for i in 0..4 {
+			result[i] = (self[i] as f32).lerp(other[i] as f32, t).round() as u8;
+		}
+		result
+	}
+}
+
+/// A value that changes over a particle's life. Keys are `(t, value)` pairs sorted by `t`, where 0 is when
+/// the particle spawns and 1 is when it dies, and values in between are linearly interpolated.
+/// Before the first key and after the last, the value stays at that key's.
+///
+/// In JSON it's a list of `[t, value]` pairs, e.g. `[[0, 1.0], [1, 0.0]]`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Curve<T>(pub Vec<(f32, T)>);
+
+impl<T: Lerp> Curve<T> {
+	pub fn constant(value: T) -> Self {
+		Curve(vec![(0.0, value)])
+	}
+
+	/// Panics if there aren't any keys, which `ParticleEffect::new` checks for.
+	pub fn sample(&self, t: f32) -> T {
+		let keys = &self.0;
+		let next = keys.iter().position(|&(key_t, _)| key_t > t);
+		match next {
+			Some(0) => keys[0].1,
+			Some(i) => {
+				let (t0, v0) = keys[i - 1];
+				let (t1, v1) = keys[i];
+				v0.lerp(v1, (t - t0) / (t1 - t0))
+			},
+			None => keys.last().expect("Curve has no keys").1,
+		}
+	}
+}
+
+/// A number of particles spawned all at once.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Burst {
+	/// Time from when the emitter starts (or loops back to the start)
+	pub time_ms: u32,
+	pub count: u32,
+}
+
+/// What each particle looks like, before color and size are applied.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub enum ParticleSprite {
+	/// A single white pixel, so the size curve is the size in pixels
+	#[default]
+	Pixel,
+	/// An atlas region by name
+	Region(String),
+	/// Atlas regions by name, shown one after the other from when the particle spawns, looping if it outlives them
+	Animation { frames: Vec<String>, frame_ms: u32 },
+}
+
+/// Everything about how an emitter spawns and moves particles, in the form it's saved in.
+/// Times are in milliseconds, distances in pixels and angles in degrees, but particles are simulated in ticks.
+/// Anything missing from the file is left at its default.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ParticlePreset {
+	/// Particles per second while emitting, on top of any bursts
+	pub rate: f32,
+	pub bursts: Vec<Burst>,
+	/// How long the emitter emits for, or `None` for as long as it exists
+	pub duration_ms: Option<u32>,
+	/// Whether to start again (including bursts) once `duration_ms` is up, rather than stopping
+	pub looping: bool,
+	/// Spawning stops while an emitter has this many alive
+	pub max_particles: usize,
+	/// Each particle lives for a random time between these
+	pub lifetime_ms: [u32; 2],
+	/// Each particle starts at a random speed between these, in pixels per second
+	pub speed: [f32; 2],
+	/// Degrees counterclockwise from +X
+	pub direction: f32,
+	/// Particles head off up to this many degrees either side of `direction`
+	pub spread: f32,
+	/// Half the size of the box around the emitter that particles spawn in
+	pub spawn_extent: [f32; 2],
+	/// In pixels per second per second, Y+ up
+	pub gravity: [f32; 2],
+	pub color: Curve<[u8; 4]>,
+	/// Multiplies the sprite's size
+	pub size: Curve<f32>,
+	pub sprite: ParticleSprite,
+	pub blend_mode: BlendMode,
+	/// See `SpriteLighting::emissive`
+	pub emissive: u8,
+}
+
+impl Default for ParticlePreset {
+	fn default() -> Self {
+		Self {
+			rate: 10.0,
+			bursts: Vec::new(),
+			duration_ms: None,
+			looping: false,
+			max_particles: 256,
+			lifetime_ms: [1000, 1000],
+			speed: [20.0, 40.0],
+			direction: 90.0,
+			spread: 30.0,
+			spawn_extent: [0.0, 0.0],
+			gravity: [0.0, 0.0],
+			color: Curve::constant([255, 255, 255, 255]),
+			size: Curve::constant(1.0),
+			sprite: ParticleSprite::Pixel,
+			blend_mode: BlendMode::Alpha,
+			emissive: 0,
+		}
+	}
+}
+
+impl ParticlePreset {
+	pub fn load(path: &Path) -> Result<Self, AssetError> {
+		asset::load_json(path)
+	}
+
+	pub fn save(&self, path: &Path) -> Result<(), AssetError> {
+		asset::save_json(path, self)
+	}
+}
+
+/// A preset with its sprite looked up in the atlas and times converted to ticks, ready for emitters to share.
+#[derive(Debug, Clone)]
+pub struct ParticleEffect {
+	preset: ParticlePreset,
+	frames: Vec<AnimationFrame>,
+	lifetime_ticks: [u32; 2],
+	duration_ticks: Option<u32>,
+	/// Tick within the cycle, and count
+	bursts: Vec<(u32, u32)>,
+}
+
+impl ParticleEffect {
+	/// Fails if the preset names regions that aren't in the atlas, or doesn't make sense.
+	pub fn new(preset: ParticlePreset, atlas: &Atlas) -> Result<Self, AssetError> {
+		let region = |name: &str| atlas.get_region(name)
+			.ok_or_else(|| AssetError::Invalid(format!("Particle sprite {:?} isn't in the atlas", name)));
+		let frames = match &preset.sprite {
+			ParticleSprite::Pixel => vec![AnimationFrame {
+				region: AtlasRegion {
+					x: 0,
+					y: 0,
+					width: 1,
+					height: 1,
+					uv_min: WHITE_PIXEL_UV,
+					uv_max: WHITE_PIXEL_UV,
+					indexed: false,
+					offset: [0, 0],
+					source_size: [1, 1],
+				},
+				ticks: 1,
+			}],
+			ParticleSprite::Region(name) => vec![AnimationFrame { region: region(name)?, ticks: 1 }],
+			ParticleSprite::Animation { frames, frame_ms } => frames.iter()
+				.map(|name| Ok(AnimationFrame { region: region(name)?, ticks: ms_to_ticks(*frame_ms) }))
+				.collect::<Result<_, AssetError>>()?,
+		};
+		if frames.is_empty() {
+			return Err(AssetError::Invalid("Particle animation has no frames".to_string()));
+		}
+		if preset.color.0.is_empty() || preset.size.0.is_empty() {
+			return Err(AssetError::Invalid("Particle color and size curves need at least one key".to_string()));
+		}
+		if preset.lifetime_ms[0] > preset.lifetime_ms[1] {
+			return Err(AssetError::Invalid(format!("Particle lifetime {:?} is backwards", preset.lifetime_ms)));
+		}
+
+		Ok(Self {
+			lifetime_ticks: [ms_to_ticks(preset.lifetime_ms[0]), ms_to_ticks(preset.lifetime_ms[1])],
+			duration_ticks: preset.duration_ms.map(ms_to_ticks),
+			// Not ms_to_ticks, since a burst at 0 should happen on the very first tick.
+			// In u64 for the same reason as ms_to_ticks.
+			bursts: preset.bursts.iter()
+				.map(|burst| (((burst.time_ms as u64 * TICKS_PER_SECOND as u64 + 500) / 1000) as u32, burst.count))
+				.collect(),
+			frames,
+			preset,
+		})
+	}
+
+	/// Loads a preset from a JSON file. See `ParticlePreset` for the format.
+	pub fn load(path: &Path, atlas: &Atlas) -> Result<Self, AssetError> {
+		Self::new(ParticlePreset::load(path)?, atlas)
+	}
+
+	pub fn get_preset(&self) -> &ParticlePreset { &self.preset }
+}
+
+#[derive(Debug, Clone)]
+struct Particle {
+	pos: [f32; 2],
+	/// Pixels per tick
+	vel: [f32; 2],
+	age: u32,
+	lifetime: u32,
+}
+
+/// Spawns and simulates particles from a `ParticleEffect`. As a component, particles spawn around the entity's `Pos`.
+///
+/// Everything happens in `tick`, with randomness from a seeded `Rng`, so two emitters with the same effect and
+/// seed that are ticked at the same positions produce exactly the same particles.
+/// Particles are in world space, so they get left behind when the emitter moves.
+pub struct ParticleEmitter {
+	effect: Arc<ParticleEffect>,
+	rng: Rng,
+	particles: Vec<Particle>,
+	/// Ticks since the emitter started, or last looped
+	cycle_ticks: u32,
+	/// Fractions of a particle left over from previous ticks, so low rates still spawn now and then
+	spawn_accumulator: f32,
+	emitting: bool,
+}
+
+impl ParticleEmitter {
+	pub fn new(effect: Arc<ParticleEffect>, seed: u64) -> Self {
+		Self {
+			effect,
+			rng: Rng::new(seed),
+			particles: Vec::new(),
+			cycle_ticks: 0,
+			spawn_accumulator: 0.0,
+			emitting: true,
+		}
+	}
+
+	/// Stops or starts spawning. Particles that are already alive carry on either way.
+	/// Starting again restarts the cycle, bursts included.
+	pub fn set_emitting(&mut self, emitting: bool) {
+		if emitting && !self.emitting {
+			self.cycle_ticks = 0;
+			self.spawn_accumulator = 0.0;
+		}
+		self.emitting = emitting;
+	}
+
+	pub fn is_emitting(&self) -> bool { self.emitting }
+
+	/// Whether it's done emitting and every particle has died, so the emitter can be removed.
+	pub fn is_finished(&self) -> bool {
+		!self.emitting && self.particles.is_empty()
+	}
+
+	pub fn get_particle_count(&self) -> usize { self.particles.len() }
+
+	/// Spawns particles right now, on top of whatever the effect spawns by itself.
+	pub fn burst(&mut self, pos: [f32; 2], count: u32) {
+		for _ in 0..count {
+			self.spawn(pos);
+		}
+	}
+
+	/// Moves particles along, removes dead ones, and spawns new ones around `pos`.
+	pub fn tick(&mut self, pos: [f32; 2]) {
+		let effect = self.effect.clone();
+		let preset = &effect.preset;
+
+		// Per tick per tick
+		let tps = TICKS_PER_SECOND as f32;
+		let gravity = [preset.gravity[0] / (tps * tps), preset.gravity[1] / (tps * tps)];
+		self.particles.retain_mut(|particle| {
+			particle.vel[0] += gravity[0];
+			particle.vel[1] += gravity[1];
+			particle.pos[0] += particle.vel[0];
+			particle.pos[1] += particle.vel[1];
+			particle.age += 1;
+			particle.age < particle.lifetime
+		});
+
+		if !self.emitting {
+			return;
+		}
+		if effect.duration_ticks.is_some_and(|duration| self.cycle_ticks >= duration) {
+			if preset.looping {
+				self.cycle_ticks = 0;
+			} else {
+				self.emitting = false;
+				return;
+			}
+		}
+
+		for &(tick, count) in &effect.bursts {
+			if tick == self.cycle_ticks {
+				self.burst(pos, count);
+			}
+		}
+		self.spawn_accumulator += preset.rate / tps;
+		while self.spawn_accumulator >= 1.0 {
+			self.spawn_accumulator -= 1.0;
+			self.spawn(pos);
+		}
+		self.cycle_ticks += 1;
+	}
+
+	fn spawn(&mut self, pos: [f32; 2]) {
+		let effect = &self.effect;
+		let preset = &effect.preset;
+		if self.particles.len() >= preset.max_particles {
+			return;
+		}
+		let rng = &mut self.rng;
+		let angle = (preset.direction + rng.range(-preset.spread, preset.spread)).to_radians();
+		let speed = rng.range(preset.speed[0], preset.speed[1]) / TICKS_PER_SECOND as f32;
+		let offset = [
+			rng.range(-preset.spawn_extent[0], preset.spawn_extent[0]),
+			rng.range(-preset.spawn_extent[1], preset.spawn_extent[1]),
+		];
+		let [min_lifetime, max_lifetime] = effect.lifetime_ticks;
+		let lifetime = min_lifetime + (rng.next_u32() % (max_lifetime - min_lifetime + 1));
+		self.particles.push(Particle {
+			pos: [pos[0] + offset[0], pos[1] + offset[1]],
+			vel: [angle.cos() * speed, angle.sin() * speed],
+			age: 0,
+			lifetime,
+		});
+	}
+
+	/// Draws each particle centered on its position, snapped to whole pixels.
+	/// Uses the effect's blend mode, but the renderer's draw order and sprite effects.
+	pub fn draw(&self, renderer: &mut InstancedSpriteRenderer) {
+		let preset = &self.effect.preset;
+		let lighting = SpriteLighting { emissive: preset.emissive, ..SpriteLighting::default() };
+		let effects = renderer.get_sprite_effects();
+		let layer = renderer.get_draw_order().layer;
+
+		let previous_blend_mode = renderer.get_blend_mode();
+		renderer.set_blend_mode(preset.blend_mode);
+		for particle in &self.particles {
+			let t = particle.age as f32 / particle.lifetime as f32;
+			let scale = preset.size.sample(t);
+			if scale <= 0.0 {
+				continue;
+			}
+			let region = animation::looping_frame_at(&self.effect.frames, particle.age).region;
+			let size = [(region.width as f32 * scale).round().max(1.0), (region.height as f32 * scale).round().max(1.0)];
+			// Centered on the untrimmed frame, so trimmed animations don't wobble
+			let origin = [0, 1].map(|axis|
+				(particle.pos[axis] + (region.offset[axis] as f32 - region.source_size[axis] as f32 / 2.0) * scale).round());
+			renderer.push(InstanceSprite {
+				origin,
+				size,
+				uv_min: region.uv_min,
+				uv_max: region.uv_max,
+				tint: preset.color.sample(t),
+				params: display::sprite_params(lighting, effects, region.indexed, layer),
+			});
+		}
+		renderer.set_blend_mode(previous_blend_mode);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::render::atlas::AtlasBuilder;
+
+	fn effect(preset: ParticlePreset) -> Arc<ParticleEffect> {
+		let atlas = AtlasBuilder::new().build().unwrap();
+		Arc::new(ParticleEffect::new(preset, &atlas).unwrap())
+	}
+
+	/// Everything about the particles that the simulation decides
+	fn particles(emitter: &ParticleEmitter) -> Vec<([f32; 2], [f32; 2], u32, u32)> {
+		emitter.particles.iter().map(|p| (p.pos, p.vel, p.age, p.lifetime)).collect()
+	}
+
+	#[test]
+	fn curve_at_keys() {
+		let curve = Curve(vec![(0.0, 1.0), (0.5, 3.0), (1.0, 0.0)]);
+		assert_eq!(curve.sample(0.0), 1.0);
+		assert_eq!(curve.sample(0.5), 3.0);
+		assert_eq!(curve.sample(1.0), 0.0);
+	}
+
+	#[test]
+	fn curve_between_keys() {
+		let curve = Curve(vec![(0.0, 1.0), (0.5, 3.0), (1.0, 0.0)]);
+		assert_eq!(curve.sample(0.25), 2.0);
+		assert_eq!(curve.sample(0.75), 1.5);
+		let colors = Curve(vec![(0.0, [0, 0, 0, 255]), (1.0, [255, 100, 10, 255])]);
+		assert_eq!(colors.sample(0.5), [128, 50, 5, 255]);
+	}
+
+	#[test]
+	fn curve_outside_keys() {
+		let curve = Curve(vec![(0.25, 2.0), (0.75, 4.0)]);
+		assert_eq!(curve.sample(0.0), 2.0);
+		assert_eq!(curve.sample(1.0), 4.0);
+		assert_eq!(Curve::constant(5.0).sample(0.5), 5.0);
+	}
+
+	#[test]
+	fn same_seed_same_particles() {
+		let effect = effect(ParticlePreset {
+			rate: 30.0,
+			bursts: vec![Burst { time_ms: 0, count: 5 }],
+			lifetime_ms: [200, 800],
+			spawn_extent: [4.0, 2.0],
+			gravity: [0.0, -50.0],
+			..ParticlePreset::default()
+		});
+		let mut a = ParticleEmitter::new(effect.clone(), 42);
+		let mut b = ParticleEmitter::new(effect.clone(), 42);
+		let mut other_seed = ParticleEmitter::new(effect, 43);
+		for tick in 0..30 {
+			let pos = [tick as f32, 10.0];
+			a.tick(pos);
+			b.tick(pos);
+			other_seed.tick(pos);
+			assert_eq!(particles(&a), particles(&b));
+		}
+		assert!(a.get_particle_count() > 5);
+		assert_ne!(particles(&a), particles(&other_seed));
+	}
+
+	#[test]
+	fn late_bursts_dont_overflow() {
+		let effect = effect(ParticlePreset {
+			bursts: vec![Burst { time_ms: u32::MAX, count: 1 }],
+			..ParticlePreset::default()
+		});
+		// u32::MAX ms is 257698037.7 ticks
+		assert_eq!(effect.bursts, vec![(257_698_038, 1)]);
+	}
+}
@@ -0,0 +1,165 @@
+use crate::render::texture::ImageData;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TonemapOperator {
+	Reinhard,
+	/// Narkowicz's fit of the ACES filmic curve. Punchier than Reinhard.
+	Aces,
+}
+
+/// One step of the post-processing chain. Effects run on the lit HDR image in chain order,
+/// except `Crt`, which is always applied last when scaling up to the window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PostEffect {
+	/// Blurs everything brighter than `threshold` and adds it back on top
+	Bloom {
+		threshold: f32,
+		intensity: f32,
+		/// Blur radius in pixels. Capped at `MAX_BLOOM_RADIUS`.
+		radius: u32,
+	},
+	/// Maps HDR colors into 0-1. Anything after this in the chain works on LDR colors.
+	Tonemap {
+		exposure: f32,
+		operator: TonemapOperator,
+	},
+	/// Looks colors up in the LUT set with `Renderer::set_color_grading_lut`
+	ColorGrading {
+		/// 0 leaves colors unchanged, 1 uses the LUT's colors
+		intensity: f32,
+	},
+	Vignette {
+		color: [f32; 3],
+		intensity: f32,
+		/// Distance from the center where darkening starts, where the corners are 1
+		radius: f32,
+		/// Distance over which it fades in
+		softness: f32,
+	},
+	Crt {
+		/// How dark the gaps between scanlines are, from 0 to 1
+		scanline_intensity: f32,
+		/// How much the screen bulges out. Around 0.1 looks reasonable.
+		curvature: f32,
+	},
+}
+
+pub const MAX_BLOOM_RADIUS: u32 = 8;
+
+/// Side length of the default color grading LUT
+pub const DEFAULT_LUT_SIZE: u32 = 16;
+
+/// A LUT that maps every color to itself, laid out the way `Renderer::set_color_grading_lut` expects:
+/// `size` slices of `size` x `size` side by side, one per blue value, with red along X and green along Y.
+/// Save it out and edit it in an image editor to make a new grade. `size` has to be at least 2.
+pub fn identity_lut(size: u32) -> ImageData {
+	assert!(size >= 2, "LUT size must be at least 2, got {}", size);
+	let scale = |v: u32| (v * 255 / (size - 1)) as u8;
+	ImageData::from_fn(size * size, size, |x, y| [scale(x % size), scale(y), scale(x / size), 255])
+}
+
+pub struct PostEffectSlot {
+	pub name: String,
+	pub effect: PostEffect,
+	pub enabled: bool,
+}
+
+/// The post-processing chain. Effects are referred to by name, so they can be tweaked, toggled
+/// and reordered at runtime without keeping track of indices.
+pub struct PostProcessing {
+	effects: Vec<PostEffectSlot>,
+}
+
+impl PostProcessing {
+	/// An empty chain, which leaves the lit image unchanged.
+	pub fn new() -> Self {
+		Self { effects: Vec::new() }
+	}
+
+	/// Adds an effect to the end of the chain. Replaces the effect if the name is already taken.
+	pub fn add(&mut self, name: &str, effect: PostEffect) {
+		if let Some(slot) = self.get_slot_mut(name) {
+			slot.effect = effect;
+			return;
+		}
+		self.effects.push(PostEffectSlot {
+			name: name.to_string(),
+			effect,
+			enabled: true,
+		});
+	}
+
+	pub fn remove(&mut self, name: &str) {
+		self.effects.retain(|slot| slot.name != name);
+	}
+
+	fn get_slot_mut(&mut self, name: &str) -> Option<&mut PostEffectSlot> {
+		self.effects.iter_mut().find(|slot| slot.name == name)
+	}
+
+	/// For changing an effect's parameters.
+	pub fn get_mut(&mut self, name: &str) -> Option<&mut PostEffect> {
+		self.get_slot_mut(name).map(|slot| &mut slot.effect)
+	}
+
+	pub fn set_enabled(&mut self, name: &str, enabled: bool) {
+		if let Some(slot) = self.get_slot_mut(name) {
+			slot.enabled = enabled;
+		}
+	}
+
+	pub fn is_enabled(&self, name: &str) -> bool {
+		self.effects.iter().any(|slot| slot.name == name && slot.enabled)
+	}
+
+	/// Moves an effect to position `index` in the chain, clamped to the end.
+	pub fn move_to(&mut self, name: &str, index: usize) {
+		if let Some(from) = self.effects.iter().position(|slot| slot.name == name) {
+			let slot = self.effects.remove(from);
+			let index = index.min(self.effects.len());
+			self.effects.insert(index, slot);
+		}
+	}
+
+	pub fn iter(&self) -> impl Iterator<Item = &PostEffectSlot> {
+		self.effects.iter()
+	}
+
+	/// The enabled effects, in order
+	pub fn enabled(&self) -> impl Iterator<Item = &PostEffect> {
+		self.effects.iter().filter(|slot| slot.enabled).map(|slot| &slot.effect)
+	}
+}
+
+impl Default for PostProcessing {
+	/// Every effect with sensible parameters, but only bloom turned on.
+	/// Bloom only picks up HDR colors, so it leaves the rest of the image alone.
+	fn default() -> Self {
+		let mut post = Self::new();
+		post.add("bloom", PostEffect::Bloom {
+			threshold: 1.0,
+			intensity: 0.5,
+			radius: 4,
+		});
+		post.add("tonemap", PostEffect::Tonemap {
+			exposure: 1.0,
+			operator: TonemapOperator::Aces,
+		});
+		post.add("color_grading", PostEffect::ColorGrading { intensity: 1.0 });
+		post.add("vignette", PostEffect::Vignette {
+			color: [0.0, 0.0, 0.0],
+			intensity: 0.5,
+			radius: 0.6,
+			softness: 0.5,
+		});
+		post.add("crt", PostEffect::Crt {
+			scanline_intensity: 0.3,
+			curvature: 0.05,
+		});
+		post.set_enabled("tonemap", false);
+		post.set_enabled("color_grading", false);
+		post.set_enabled("vignette", false);
+		post.set_enabled("crt", false);
+		post
+	}
+}
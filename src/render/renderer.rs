@@ -1,69 +1,239 @@
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Weak};
 
-use vulkano::buffer::{BufferAccess, BufferUsage, CpuAccessibleBuffer, TypedBufferAccess, CpuBufferPool};
-use vulkano::command_buffer::{AutoCommandBufferBuilder, DynamicState, AutoCommandBuffer, CommandBufferExecFuture};
+use vulkano::buffer::{BufferAccess, BufferSlice, BufferUsage, CpuAccessibleBuffer, CpuBufferPool, DeviceLocalBuffer};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, DynamicState, AutoCommandBuffer};
 use vulkano::descriptor::{DescriptorSet, PipelineLayoutAbstract};
 use vulkano::descriptor::descriptor_set::PersistentDescriptorSet;
-use vulkano::device::{Device, DeviceExtensions, Features, Queue};
-use vulkano::format::Format;
-use vulkano::framebuffer::{Framebuffer, FramebufferAbstract, RenderPass, RenderPassAbstract, Subpass};
-use vulkano::image::{AttachmentImage, ImageUsage, SwapchainImage};
-use vulkano::instance::{Instance, PhysicalDevice};
-use vulkano::pipeline::{GraphicsPipeline, GraphicsPipelineAbstract};
+use vulkano::device::{Device, DeviceExtensions, DeviceOwned, Features, Queue};
+use vulkano::format::{ClearValue, Format};
+use vulkano::half::f16;
+use vulkano::framebuffer::{Framebuffer, FramebufferAbstract, FramebufferCreationError, RenderPassAbstract, Subpass};
+use vulkano::image::{AttachmentImage, Dimensions, ImageCreationError, ImageUsage, ImmutableImage, SwapchainImage};
+use vulkano::instance::{Instance, InstanceExtensions, PhysicalDevice};
+use vulkano::pipeline::{GraphicsPipeline, GraphicsPipelineAbstract, GraphicsPipelineCreationError};
+use vulkano::pipeline::blend::{AttachmentBlend, BlendFactor, BlendOp};
+use vulkano::pipeline::vertex::OneVertexOneInstanceDefinition;
 use vulkano::pipeline::viewport::Viewport;
 use vulkano::sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode};
-use vulkano::swapchain::{self, AcquireError, ColorSpace, FullscreenExclusive, PresentMode, Surface, SurfaceTransform, Swapchain, SwapchainCreationError, PresentFuture, SwapchainAcquireFuture};
-use vulkano::sync::{self, FlushError, GpuFuture, JoinFuture, FenceSignalFuture};
+use vulkano::swapchain::{self, AcquireError, ColorSpace, PresentMode, Surface, SurfaceTransform, Swapchain, SwapchainCreationError};
+use vulkano::sync::{self, FlushError, GpuFuture};
 use vulkano_win::VkSurfaceBuild;
 use winit::event_loop::EventLoop;
-use winit::window::{Window, WindowBuilder};
-
-use crate::render::vert::{Vertex2d, Vertex3d};
-use crate::render::display::FrameBuilder;
+use winit::dpi::{PhysicalPosition, PhysicalSize};
+use winit::window::{Fullscreen, Icon, Window, WindowBuilder};
+
+use crate::render::vert::{InstanceSprite, Vertex2d, VertexDebug, VertexSprite};
+use crate::render::display::{BlendMode, DrawOrder, FrameBuilder, SpriteTexture};
+use crate::render::atlas::{Atlas, AtlasBuilder};
+use crate::render::texture::ImageData;
+use crate::render::lighting::{Lighting, MAX_LIGHTS};
+use crate::render::shader_reload::{ShaderModules, ShaderWatcher};
+use crate::render::palette::{Palette, PaletteCycle, PaletteSet, MAX_PALETTE_CYCLES};
+use crate::render::scaling::ScalingMode;
+use crate::render::capture::CaptureTarget;
+use crate::render::debug_draw::DebugSettings;
+use crate::render::tilemap::{ChunkMesh, TilemapChunkDraw};
+use crate::render::instanced::InstanceBatch;
+use crate::render::view::{LayerMask, RenderTextureInfo, ScreenRect, View, ViewTarget};
+use crate::render::post::{self, PostEffect, PostProcessing, TonemapOperator, DEFAULT_LUT_SIZE, MAX_BLOOM_RADIUS};
 use vulkano::command_buffer::pool::standard::StandardCommandPoolAlloc;
-use crate::render::camera::Camera;
+use crate::render::camera::{Camera, DEFAULT_RESOLUTION, PIXEL_MARGIN, PIXEL_OFFSET};
+use crate::render::device::{self, DeviceRequirements};
+use crate::render::error::RendererError;
+use crate::render::video::{VideoSettings, WindowMode, WindowSettings};
+
+/// A swapchain along with the images it presents
+type SwapchainWithImages = (Arc<Swapchain<Window>>, Vec<Arc<SwapchainImage<Window>>>);
+
+/// An image being uploaded, along with the future that has to finish before it can be used
+type UploadedImage = (Arc<ImmutableImage<Format>>, Box<dyn GpuFuture>);
+
+/// A frame's commands, along with readbacks for any captures recorded into them
+type FrameCommands = (AutoCommandBuffer<StandardCommandPoolAlloc>, Vec<(CaptureTarget, Readback)>);
+
+/// Uploads an image for sampling in shaders. The image can't be used until the returned future has completed.
+/// `format` should be R8G8B8A8Srgb for anything with colors that get lit and blended,
+/// or R8G8B8A8Unorm for data that the shader wants to read back exactly as it was.
+fn upload_image(
+	image: &ImageData, format: Format, queue: &Arc<Queue>,
+) -> Result<UploadedImage, RendererError> {
+	let (image, future) = ImmutableImage::from_iter(
+		image.pixels.iter().cloned(),
+		Dimensions::Dim2d { width: image.width, height: image.height },
+		format,
+		queue.clone(),
+	)?;
+	Ok((image, future.boxed()))
+}
 
-pub const RESOLUTION: [u32; 2] = [320, 180];
+/// A pipeline that draws one full screen triangle with vs_output, like the lighting, post-processing and output passes.
+/// These are all the same apart from the fragment shader and render pass. Evaluates to a `Result`.
+macro_rules! fullscreen_pipeline {
+	($device:expr, $modules:expr, $fs:ident, $render_pass:expr) => {
+		GraphicsPipeline::start()
+			.vertex_input_single_buffer::<Vertex2d>()
+			.vertex_shader($modules.vs_output.main_entry_point(), ())
+			.triangle_list()
+			.viewports_dynamic_scissors_irrelevant(1)
+			.fragment_shader($modules.$fs.main_entry_point(), ())
+			.render_pass(Subpass::from($render_pass.clone(), 0).expect("Render pass has no subpass"))
+			.build($device.clone())
+			.map(|pipeline| Arc::new(pipeline) as Arc<dyn GraphicsPipelineAbstract + Send + Sync>)
+	};
+}
 
-fn select_physical_device(instance: &Arc<Instance>) -> PhysicalDevice {
-	let devices: Vec<PhysicalDevice> = PhysicalDevice::enumerate(&instance).collect();
+/// Debug lines and filled shapes need different topologies, but are otherwise the same. Evaluates to a `Result`.
+macro_rules! debug_pipeline {
+	($device:expr, $modules:expr, $topology:ident, $render_pass:expr) => {
+		GraphicsPipeline::start()
+			.vertex_input_single_buffer::<VertexDebug>()
+			.vertex_shader($modules.vs_debug.main_entry_point(), ())
+			.$topology()
+			.viewports_dynamic_scissors_irrelevant(1)
+			.fragment_shader($modules.fs_debug.main_entry_point(), ())
+			.blend_collective(RenderData::attachment_blend(BlendMode::Alpha))
+			.render_pass(Subpass::from($render_pass.clone(), 0).expect("Render pass has no subpass"))
+			.build($device.clone())
+			.map(|pipeline| Arc::new(pipeline) as Arc<dyn GraphicsPipelineAbstract + Send + Sync>)
+	};
+}
 
-	println!("{} devices found:", devices.len());
-	for dev in &devices {
-		println!("{:?} ({}, type: {:?})", dev, dev.name(), dev.ty());
+/// For hot reloading: swaps in a rebuilt pipeline, or keeps the old one and prints why if it couldn't be built.
+fn replace_pipeline<T>(pipeline: &mut T, name: &str, rebuilt: Result<T, GraphicsPipelineCreationError>) {
+	match rebuilt {
+		Ok(rebuilt) => *pipeline = rebuilt,
+		Err(e) => println!("Failed to rebuild the {} pipeline, keeping the old one: {}", name, e),
 	}
-
-	// TODO gracefully fail if no devices found - how?
-	let physical = *devices.get(0).expect("Failed to find any available devices");
-
-	println!("Selected device: {}", physical.name());
-
-	physical
 }
 
 // Not sure if this is the best name for this struct but whatever.
 // Contains all the various things used in the actual rendering process
 // (as opposed to Renderer, which just has devices and queues and the swapchain and such)
-struct RenderData {
-	intermediate_image: Arc<AttachmentImage>,
+pub(crate) struct RenderData {
+	/// One set for each view, in the order they were last drawn
+	targets: Vec<IntermediateTargets>,
 	sampler_simple_nearest: Arc<Sampler>,
-	vertex_buffer_pool: CpuBufferPool<Vertex3d>,
+	sampler_simple_linear: Arc<Sampler>,
+	vertex_buffer_pool: CpuBufferPool<VertexSprite>,
 	index_buffer_pool: CpuBufferPool<u32>,
 	vertex_buffer_square: Arc<dyn BufferAccess + Send + Sync>,
+	/// Corners of a unit quad as a triangle strip, for instanced sprites
+	vertex_buffer_quad: Arc<dyn BufferAccess + Send + Sync>,
+	instance_buffer_pool: CpuBufferPool<InstanceSprite>,
 	render_pass_main: Arc<dyn RenderPassAbstract + Send + Sync>,
-	render_pass_output: Arc<dyn RenderPassAbstract + Send + Sync>,
-	pipeline_main: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	/// Also used for the post-processing passes, which have the same single HDR attachment
+	render_pass_lighting: Arc<dyn RenderPassAbstract + Send + Sync>,
+	pub(crate) render_pass_output: Arc<dyn RenderPassAbstract + Send + Sync>,
+	/// Same as `render_pass_output`, but drawing into an HDR render texture
+	render_pass_render_texture: Arc<dyn RenderPassAbstract + Send + Sync>,
+	render_pass_overlay: Arc<dyn RenderPassAbstract + Send + Sync>,
+	/// One for each blend mode, since blend state is baked into the pipeline
+	pipelines_main: HashMap<BlendMode, Arc<dyn GraphicsPipelineAbstract + Send + Sync>>,
+	/// Same as `pipelines_main`, but for `InstancedSpriteRenderer`
+	pipelines_instanced: HashMap<BlendMode, Arc<dyn GraphicsPipelineAbstract + Send + Sync>>,
+	pipeline_lighting: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	pipeline_bloom: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	pipeline_tonemap: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	pipeline_color_grade: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	pipeline_vignette: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
 	pipeline_output: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
-	descriptor_set_output: Arc<dyn DescriptorSet + Send + Sync>,
+	pipeline_output_render_texture: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	pipeline_debug_lines: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	pipeline_debug_triangles: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	/// What the pipelines were built from, kept for rebuilding them when shaders are reloaded
+	shader_modules: ShaderModules,
+	descriptor_set_main: Arc<dyn DescriptorSet + Send + Sync>,
+	light_buffer_pool: CpuBufferPool<shaders::fs_lighting::ty::LightData>,
+	debug_vertex_pool: CpuBufferPool<VertexDebug>,
+	/// Device-local copies of tilemap chunks, by mesh ID
+	tilemap_buffers: HashMap<u64, TilemapChunkBuffers>,
+	/// Images for render textures, by ID
+	render_textures: HashMap<u64, RenderTextureTarget>,
+	color_grading_lut: Arc<ImmutableImage<Format>>,
+	/// One row per palette; see `PaletteSet::build_image`
+	palette_image: Arc<ImmutableImage<Format>>,
+	/// Along with the index of the palette each one belongs to
+	palette_cycles: Vec<(u8, PaletteCycle)>,
+	palette_cycle_pool: CpuBufferPool<shaders::fs_sprite::ty::PaletteCycles>,
+	pub(crate) post_processing: PostProcessing,
+	pub(crate) debug_settings: DebugSettings,
+	dynamic_state: DynamicState,
+}
+
+/// A view's finished image, waiting for the output pass to put it on the screen.
+pub(crate) struct ViewOutput {
+	source: Arc<AttachmentImage>,
+	/// The camera's resolution, i.e. the size of the visible part of `source`
+	resolution: [u32; 2],
+	overlay: Arc<AttachmentImage>,
+	rect: ScreenRect,
+	push_constants: shaders::fs_output::ty::PushConstants,
+}
+
+/// Something drawn in the main pass in between sprite batches.
+enum MainPassDraw {
+	/// Mesh ID of a chunk in `RenderData::tilemap_buffers`
+	TilemapChunk(u64),
+	Instances {
+		blend_mode: BlendMode,
+		instances: Arc<dyn BufferAccess + Send + Sync>,
+	},
+}
+
+/// A tilemap chunk uploaded to device-local memory. Dropped once the tilemap drops the mesh, i.e. when the chunk changes.
+struct TilemapChunkBuffers {
+	mesh: Weak<ChunkMesh>,
+	vertices: Arc<DeviceLocalBuffer<[VertexSprite]>>,
+	indices: Arc<DeviceLocalBuffer<[u32]>>,
+}
+
+/// A `RenderTexture`'s image. Dropped once every handle to the texture has been.
+struct RenderTextureTarget {
+	texture: Weak<RenderTextureInfo>,
+	framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+	/// Viewport covering the whole image
+	dynamic_state: DynamicState,
+	/// For drawing sprites with it
+	descriptor_set: Arc<dyn DescriptorSet + Send + Sync>,
+}
+
+/// Everything that depends on the internal resolution, so it can all be rebuilt when that changes.
+struct IntermediateTargets {
+	/// The visible resolution these were made for; the images themselves also have the margin
+	resolution: [u32; 2],
+	/// The intermediate image after lighting
+	lit_image: Arc<AttachmentImage>,
+	/// Post-processing effects ping-pong between these
+	post_images: [Arc<AttachmentImage>; 2],
+	/// Just the images; the lights themselves go in set 1, which changes every frame
+	descriptor_set_lighting: Arc<dyn DescriptorSet + Send + Sync>,
+	/// Viewport covering the whole intermediate image, for every pass before output
 	dynamic_state: DynamicState,
 	framebuffer_main: Arc<dyn FramebufferAbstract + Send + Sync>,
+	framebuffer_lighting: Arc<dyn FramebufferAbstract + Send + Sync>,
+	framebuffers_post: [Arc<dyn FramebufferAbstract + Send + Sync>; 2],
+	/// Debug shapes, premultiplied and transparent everywhere else, which the output pass draws on top
+	overlay_image: Arc<AttachmentImage>,
+	framebuffer_overlay: Arc<dyn FramebufferAbstract + Send + Sync>,
 }
 
-impl RenderData {
-	fn init(device: &Arc<Device>, swapchain_format: Format) -> Self {
+impl IntermediateTargets {
+	fn new(
+		device: &Arc<Device>,
+		resolution: [u32; 2],
+		render_pass_main: Arc<dyn RenderPassAbstract + Send + Sync>,
+		render_pass_lighting: Arc<dyn RenderPassAbstract + Send + Sync>,
+		render_pass_overlay: Arc<dyn RenderPassAbstract + Send + Sync>,
+		pipeline_lighting: &Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+		sampler: &Arc<Sampler>,
+	) -> Result<Self, RendererError> {
+		let full_resolution = [resolution[0] + PIXEL_MARGIN[0], resolution[1] + PIXEL_MARGIN[1]];
+
 		let intermediate_image = AttachmentImage::with_usage(
 			device.clone(),
-			RESOLUTION,
+			full_resolution,
 			Format::R16G16B16A16Sfloat,
 			ImageUsage {
 				storage: true,
@@ -71,8 +241,205 @@ impl RenderData {
 				sampled: true,
 				..ImageUsage::none()
 			}
-		).expect("Failed to create intermediate image");
+		)?;
+
+		// Per-pixel emissiveness (and in green, how unlit) and shadow casters written by sprites, used by the lighting pass.
+		// The main framebuffer keeps these alive, along with the intermediate image.
+		let emissive_image = Self::create_attachment_image(device, full_resolution, Format::R8G8Unorm)?;
+		let occlusion_image = Self::create_attachment_image(device, full_resolution, Format::R8Unorm)?;
+		let lit_image = Self::create_attachment_image(device, full_resolution, Format::R16G16B16A16Sfloat)?;
+		let post_images = [
+			Self::create_attachment_image(device, full_resolution, Format::R16G16B16A16Sfloat)?,
+			Self::create_attachment_image(device, full_resolution, Format::R16G16B16A16Sfloat)?,
+		];
+		let overlay_image = Self::create_attachment_image(device, full_resolution, Format::R8G8B8A8Srgb)?;
+
+		let layout = pipeline_lighting.descriptor_set_layout(0).expect("Failed to get set layout");
+		let descriptor_set_lighting = Arc::new(
+			PersistentDescriptorSet::start(layout.clone())
+				.add_sampled_image(intermediate_image.clone(), sampler.clone())?
+				.add_sampled_image(emissive_image.clone(), sampler.clone())?
+				.add_sampled_image(occlusion_image.clone(), sampler.clone())?
+				.build()?,
+		);
+
+		let mut dynamic_state = DynamicState::none();
+		dynamic_state.viewports = Some(vec![Viewport {
+			origin: [0.0, 0.0],
+			dimensions: [full_resolution[0] as f32, full_resolution[1] as f32],
+			depth_range: 0.0..1.0,
+		}]);
+
+		let framebuffer_main = Arc::new(
+			Framebuffer::start(render_pass_main)
+				.add(intermediate_image)?
+				.add(emissive_image)?
+				.add(occlusion_image)?
+				.build()?
+		);
+
+		let framebuffer_lighting = Self::create_single_framebuffer(render_pass_lighting.clone(), &lit_image)?;
+		let framebuffers_post = [
+			Self::create_single_framebuffer(render_pass_lighting.clone(), &post_images[0])?,
+			Self::create_single_framebuffer(render_pass_lighting, &post_images[1])?,
+		];
+		let framebuffer_overlay = Self::create_single_framebuffer(render_pass_overlay, &overlay_image)?;
+
+		Ok(Self {
+			resolution,
+			lit_image,
+			post_images,
+			descriptor_set_lighting,
+			dynamic_state,
+			framebuffer_main,
+			framebuffer_lighting,
+			framebuffers_post,
+			overlay_image,
+			framebuffer_overlay,
+		})
+	}
+
+	fn create_single_framebuffer(
+		render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+		image: &Arc<AttachmentImage>,
+	) -> Result<Arc<dyn FramebufferAbstract + Send + Sync>, FramebufferCreationError> {
+		Ok(Arc::new(
+			Framebuffer::start(render_pass)
+				.add(image.clone())?
+				.build()?
+		))
+	}
+
+	fn create_attachment_image(
+		device: &Arc<Device>, dimensions: [u32; 2], format: Format,
+	) -> Result<Arc<AttachmentImage>, ImageCreationError> {
+		AttachmentImage::with_usage(
+			device.clone(),
+			dimensions,
+			format,
+			ImageUsage {
+				color_attachment: true,
+				sampled: true,
+				// For intermediate captures
+				transfer_source: true,
+				..ImageUsage::none()
+			}
+		)
+	}
+}
+
+/// An offscreen image the output pass can draw into at 1x, for reading frames back without a window.
+pub(crate) struct ReadbackTarget {
+	pub(crate) image: Arc<AttachmentImage>,
+	pub(crate) framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+	/// Viewport covering the whole image
+	pub(crate) dynamic_state: DynamicState,
+}
+
+impl ReadbackTarget {
+	/// `format` has to match `render_pass_output`, and be one `Readback` understands.
+	pub(crate) fn new(device: &Arc<Device>, data: &RenderData, format: Format, resolution: [u32; 2]) -> Result<Self, RendererError> {
+		let image = AttachmentImage::with_usage(
+			device.clone(),
+			resolution,
+			format,
+			ImageUsage {
+				color_attachment: true,
+				transfer_source: true,
+				..ImageUsage::none()
+			}
+		)?;
+
+		let framebuffer = Arc::new(
+			Framebuffer::start(data.render_pass_output.clone())
+				.add(image.clone())?
+				.build()?
+		);
+
+		let mut dynamic_state = DynamicState::none();
+		dynamic_state.viewports = Some(vec![Viewport {
+			origin: [0.0, 0.0],
+			dimensions: [resolution[0] as f32, resolution[1] as f32],
+			depth_range: 0.0..1.0,
+		}]);
+
+		Ok(Self { image, framebuffer, dynamic_state })
+	}
+}
+
+/// A copy of an image into CPU memory, which can be read once the command buffer that made it has finished.
+pub(crate) struct Readback {
+	buffer: Arc<CpuAccessibleBuffer<[u8]>>,
+	dimensions: [u32; 2],
+	format: Format,
+}
+
+impl Readback {
+	/// Whether images of this format can be read back. Anything 8 bits per channel RGBA or BGRA is fine,
+	/// as is the HDR format the intermediate images use.
+	pub(crate) fn supports_format(format: Format) -> bool {
+		matches!(format,
+			Format::R8G8B8A8Unorm | Format::R8G8B8A8Srgb | Format::B8G8R8A8Unorm | Format::B8G8R8A8Srgb
+				| Format::R16G16B16A16Sfloat)
+	}
+
+	/// Copies the `dimensions` sized area of `image` starting at `offset` (from the top left).
+	pub(crate) fn record<I: vulkano::image::ImageAccess + Send + Sync + 'static>(
+		builder: &mut AutoCommandBufferBuilder,
+		device: &Arc<Device>,
+		image: I,
+		offset: [u32; 2],
+		dimensions: [u32; 2],
+		format: Format,
+	) -> Result<Self, RendererError> {
+		let pixel_size = format.size().expect("Readback format has no size");
+		let buffer = CpuAccessibleBuffer::from_iter(
+			device.clone(),
+			BufferUsage::transfer_destination(),
+			false,
+			(0..dimensions[0] as usize * dimensions[1] as usize * pixel_size).map(|_| 0u8),
+		)?;
+		builder.copy_image_to_buffer_dimensions(
+			image, buffer.clone(), [offset[0], offset[1], 0], [dimensions[0], dimensions[1], 1], 0, 1, 0)?;
+		Ok(Self { buffer, dimensions, format })
+	}
+
+	/// Only valid once the copy has actually happened. Alpha is thrown away, since nobody wants transparent screenshots.
+	/// HDR images are clamped to 0-1 and converted to sRGB.
+	pub(crate) fn read(&self) -> ImageData {
+		let buffer = self.buffer.read().expect("Failed to read back image");
+		let mut pixels = if self.format == Format::R16G16B16A16Sfloat {
+			buffer.chunks(2)
+				.map(|half| {
+					let linear = f16::from_bits(u16::from_ne_bytes([half[0], half[1]])).to_f32().clamp(0.0, 1.0);
+					let srgb = if linear <= 0.0031308 { linear * 12.92 } else { 1.055 * linear.powf(1.0 / 2.4) - 0.055 };
+					(srgb * 255.0).round() as u8
+				})
+				.collect()
+		} else {
+			buffer.to_vec()
+		};
+		let bgra = matches!(self.format, Format::B8G8R8A8Unorm | Format::B8G8R8A8Srgb);
+		for pixel in pixels.chunks_mut(4) {
+			if bgra {
+				pixel.swap(0, 2);
+			}
+			pixel[3] = 255;
+		}
+		ImageData {
+			width: self.dimensions[0],
+			height: self.dimensions[1],
+			pixels,
+		}
+	}
+}
 
+impl RenderData {
+	/// Also returns the future for uploading the placeholder atlas, palette and default LUT, which has to finish before the first frame.
+	/// `output_format` is the format of whatever the output pass draws to, normally the swapchain's.
+	pub(crate) fn init(
+		device: &Arc<Device>, queue: &Arc<Queue>, output_format: Format,
+	) -> Result<(Self, Box<dyn GpuFuture>), RendererError> {
 		let sampler_simple_nearest = Sampler::new(
 			device.clone(),
 			Filter::Nearest,
@@ -85,7 +452,22 @@ impl RenderData {
 			1.0,
 			0.0,
 			1.0
-		).expect("Failed to create sampler");
+		)?;
+
+		// Only for things that need interpolating between texels, like the color grading LUT
+		let sampler_simple_linear = Sampler::new(
+			device.clone(),
+			Filter::Linear,
+			Filter::Linear,
+			MipmapMode::Nearest,
+			SamplerAddressMode::ClampToEdge,
+			SamplerAddressMode::ClampToEdge,
+			SamplerAddressMode::ClampToEdge,
+			0.0,
+			1.0,
+			0.0,
+			1.0
+		)?;
 
 		let vertex_buffer_pool_triangle = CpuBufferPool::new(device.clone(), BufferUsage::all());
 		let index_buffer_pool_triangle = CpuBufferPool::new(device.clone(), BufferUsage::all());
@@ -103,17 +485,27 @@ impl RenderData {
 					Vertex2d {position: [-1.0, 3.0]},
 					Vertex2d {position: [3.0, -1.0]},
 				].iter().cloned()
-			).unwrap()
+			)?
 		};
 
+		let vertex_buffer_quad = CpuAccessibleBuffer::from_iter(
+			device.clone(),
+			BufferUsage::vertex_buffer(),
+			false,
+			[
+				Vertex2d {position: [0.0, 0.0]},
+				Vertex2d {position: [1.0, 0.0]},
+				Vertex2d {position: [0.0, 1.0]},
+				Vertex2d {position: [1.0, 1.0]},
+			].iter().cloned()
+		)?;
+		let instance_buffer_pool = CpuBufferPool::vertex_buffer(device.clone());
+
 		// let fragment_uniform_buffer = CpuBufferPool::<fs_output::ty::unf_data>::new(device.clone(), BufferUsage::all());
 
-		let vs = shaders::vs::Shader::load(device.clone()).unwrap();
-		let vs_output = shaders::vs_output::Shader::load(device.clone()).unwrap();
-		let fs_triangle = shaders::fs_triangle::Shader::load(device.clone()).unwrap();
-		let fs_output = shaders::fs_output::Shader::load(device.clone()).unwrap();
+		let shader_modules = ShaderModules::load(device)?;
 
-		let render_pass_main: Arc<RenderPass<_>> = Arc::new(
+		let render_pass_main: Arc<dyn RenderPassAbstract + Send + Sync> = Arc::new(
 			vulkano::single_pass_renderpass!(
 			device.clone(),
 			attachments: {
@@ -122,16 +514,47 @@ impl RenderData {
 					store: Store,
 					format: Format::R16G16B16A16Sfloat,
 					samples: 1,
+				},
+				emissive: {
+					load: Clear,
+					store: Store,
+					format: Format::R8G8Unorm,
+					samples: 1,
+				},
+				occlusion: {
+					load: Clear,
+					store: Store,
+					format: Format::R8Unorm,
+					samples: 1,
+				}
+			},
+			pass: {
+				color: [color, emissive, occlusion],
+				depth_stencil: {}
+			}
+		)?
+		);
+
+		let render_pass_lighting: Arc<dyn RenderPassAbstract + Send + Sync> = Arc::new(
+			vulkano::single_pass_renderpass!(
+			device.clone(),
+			attachments: {
+				color: {
+					// Every pixel gets overwritten
+					load: DontCare,
+					store: Store,
+					format: Format::R16G16B16A16Sfloat,
+					samples: 1,
 				}
 			},
 			pass: {
 				color: [color],
 				depth_stencil: {}
 			}
-		).unwrap()
+		)?
 		);
 
-		let render_pass_output: Arc<RenderPass<_>> = Arc::new(
+		let render_pass_output: Arc<dyn RenderPassAbstract + Send + Sync> = Arc::new(
 			vulkano::single_pass_renderpass!(
 			device.clone(),
 			attachments: {
@@ -141,7 +564,7 @@ impl RenderData {
 					//      will want to implement more first before doing that though.
 					load: Clear,
 					store: Store,
-					format: swapchain_format,
+					format: output_format,
 					// no idea what this does, but the vulkano example uses it
 					samples: 1,
 				}
@@ -150,73 +573,904 @@ impl RenderData {
 				color: [color],
 				depth_stencil: {}
 			}
-		).unwrap()
+		)?
 		);
 
-		let pipeline_main = Arc::new(
-			GraphicsPipeline::start()
-				.vertex_input_single_buffer::<Vertex3d>()
-				.vertex_shader(vs.main_entry_point(), ())
-				.triangle_list()
-				.viewports(vec![Viewport {
-					origin: [0.0, 0.0],
-					dimensions: [RESOLUTION[0] as f32, RESOLUTION[1] as f32],
-					depth_range: 0.0..1.0,
-				}])
-				.fragment_shader(fs_triangle.main_entry_point(), ())
-				.render_pass(Subpass::from(render_pass_main.clone(), 0).unwrap())
-				.build(device.clone())
-				.unwrap()
+		let render_pass_render_texture: Arc<dyn RenderPassAbstract + Send + Sync> = Arc::new(
+			vulkano::single_pass_renderpass!(
+			device.clone(),
+			attachments: {
+				color: {
+					load: Clear,
+					store: Store,
+					format: Format::R16G16B16A16Sfloat,
+					samples: 1,
+				}
+			},
+			pass: {
+				color: [color],
+				depth_stencil: {}
+			}
+		)?
 		);
 
-		let pipeline_output = Arc::new(
-			GraphicsPipeline::start()
-				.vertex_input_single_buffer::<Vertex2d>()
-				.vertex_shader(vs_output.main_entry_point(), ())
-				.triangle_list()
-				.viewports_dynamic_scissors_irrelevant(1)
-				.fragment_shader(fs_output.main_entry_point(), ())
-				.render_pass(Subpass::from(render_pass_output.clone(), 0).unwrap())
-				.build(device.clone())
-				.unwrap()
+		let render_pass_overlay: Arc<dyn RenderPassAbstract + Send + Sync> = Arc::new(
+			vulkano::single_pass_renderpass!(
+			device.clone(),
+			attachments: {
+				color: {
+					// Cleared to transparent, since most of it won't have anything drawn on it
+					load: Clear,
+					store: Store,
+					format: Format::R8G8B8A8Srgb,
+					samples: 1,
+				}
+			},
+			pass: {
+				color: [color],
+				depth_stencil: {}
+			}
+		)?
 		);
 
-		let layout = pipeline_output.layout().descriptor_set_layout(0).expect("Failed to get set layout");
-
-		// Doing this here is janky and will not work for changeable descriptor sets.
-		let descriptor_set_output = Arc::new(
-			PersistentDescriptorSet::start(layout.clone())
-				.add_sampled_image(intermediate_image.clone(), sampler_simple_nearest.clone())
-				.expect("Failed to add sampled image")
-				.build()
-				.expect("Failed to build descriptor set"),
-		);
+		let pipelines_main = Self::create_sprite_pipelines(device, &shader_modules, &render_pass_main)?;
+		let pipelines_instanced = Self::create_instanced_pipelines(device, &shader_modules, &render_pass_main)?;
+		let pipeline_lighting = fullscreen_pipeline!(device, shader_modules, fs_lighting, render_pass_lighting)?;
+		let pipeline_bloom = fullscreen_pipeline!(device, shader_modules, fs_bloom, render_pass_lighting)?;
+		let pipeline_tonemap = fullscreen_pipeline!(device, shader_modules, fs_tonemap, render_pass_lighting)?;
+		let pipeline_color_grade = fullscreen_pipeline!(device, shader_modules, fs_color_grade, render_pass_lighting)?;
+		let pipeline_vignette = fullscreen_pipeline!(device, shader_modules, fs_vignette, render_pass_lighting)?;
+		let pipeline_output = fullscreen_pipeline!(device, shader_modules, fs_output, render_pass_output)?;
+		let pipeline_output_render_texture = fullscreen_pipeline!(device, shader_modules, fs_output, render_pass_render_texture)?;
+		let pipeline_debug_lines = debug_pipeline!(device, shader_modules, line_list, render_pass_overlay)?;
+		let pipeline_debug_triangles = debug_pipeline!(device, shader_modules, triangle_list, render_pass_overlay)?;
+
+		// Something to sample until the game uploads its own atlas
+		let (atlas_image, atlas_upload_future) = upload_image(
+			&AtlasBuilder::new().build().expect("Failed to build placeholder atlas").image, Format::R8G8B8A8Srgb, queue)?;
+		let descriptor_set_main = Self::create_image_descriptor_set(
+			&pipelines_main[&BlendMode::Alpha], atlas_image, &sampler_simple_nearest)?;
+
+		// The LUT is indexed in sRGB, so it has to be read back without conversion
+		let (color_grading_lut, lut_upload_future) = upload_image(
+			&post::identity_lut(DEFAULT_LUT_SIZE), Format::R8G8B8A8Unorm, queue)?;
+
+		// A grayscale ramp, so indexed sprites show up as something until the game uploads its own palettes
+		let mut placeholder_palettes = PaletteSet::new();
+		placeholder_palettes.add(Palette::new((0..=255).map(|i| [i, i, i, 255]).collect()));
+		let (palette_image, palette_upload_future) = upload_image(
+			&placeholder_palettes.build_image(), Format::R8G8B8A8Srgb, queue)?;
 
 		// Need this for dynamically updating the viewport when resizing the window.
-		let mut dynamic_state = DynamicState::none();
+		let dynamic_state = DynamicState::none();
 
-		let framebuffer_main = Arc::new(
-			Framebuffer::start(render_pass_main.clone())
-				.add(intermediate_image.clone())
-				.unwrap()
-				.build()
-				.unwrap()
-		);
+		let light_buffer_pool = CpuBufferPool::uniform_buffer(device.clone());
+		let palette_cycle_pool = CpuBufferPool::uniform_buffer(device.clone());
+		let debug_vertex_pool = CpuBufferPool::vertex_buffer(device.clone());
 
-		Self {
-			intermediate_image,
+		Ok((Self {
+			// Made to fit the views when they're drawn; see update_targets
+			targets: Vec::new(),
 			sampler_simple_nearest,
+			sampler_simple_linear,
 			vertex_buffer_pool: vertex_buffer_pool_triangle,
 			index_buffer_pool: index_buffer_pool_triangle,
 			vertex_buffer_square,
+			vertex_buffer_quad,
+			instance_buffer_pool,
 			render_pass_main,
+			render_pass_lighting,
 			render_pass_output,
-			pipeline_main,
+			render_pass_render_texture,
+			render_pass_overlay,
+			pipelines_main,
+			pipelines_instanced,
+			pipeline_lighting,
+			pipeline_bloom,
+			pipeline_tonemap,
+			pipeline_color_grade,
+			pipeline_vignette,
 			pipeline_output,
-			descriptor_set_output,
+			pipeline_output_render_texture,
+			pipeline_debug_lines,
+			pipeline_debug_triangles,
+			shader_modules,
+			descriptor_set_main,
+			light_buffer_pool,
+			debug_vertex_pool,
+			tilemap_buffers: HashMap::new(),
+			render_textures: HashMap::new(),
+			color_grading_lut,
+			palette_image,
+			palette_cycles: Vec::new(),
+			palette_cycle_pool,
+			post_processing: PostProcessing::default(),
+			debug_settings: DebugSettings::default(),
 			dynamic_state,
-			framebuffer_main,
+		}, atlas_upload_future.join(palette_upload_future).join(lut_upload_future).boxed()))
+	}
+
+	/// One sprite pipeline for each blend mode
+	fn create_sprite_pipelines(
+		device: &Arc<Device>,
+		shader_modules: &ShaderModules,
+		render_pass_main: &Arc<dyn RenderPassAbstract + Send + Sync>,
+	) -> Result<HashMap<BlendMode, Arc<dyn GraphicsPipelineAbstract + Send + Sync>>, GraphicsPipelineCreationError> {
+		BlendMode::ALL.iter()
+			.map(|&blend_mode| {
+				let pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync> = Arc::new(
+					GraphicsPipeline::start()
+						.vertex_input_single_buffer::<VertexSprite>()
+						.vertex_shader(shader_modules.vs_sprite.main_entry_point(), ())
+						.triangle_list()
+						.viewports_dynamic_scissors_irrelevant(1)
+						.fragment_shader(shader_modules.fs_sprite.main_entry_point(), ())
+						.blend_individual(Self::main_pass_blend(blend_mode).iter().cloned())
+						.render_pass(Subpass::from(render_pass_main.clone(), 0).expect("Render pass has no subpass"))
+						.build(device.clone())?
+				);
+				Ok((blend_mode, pipeline))
+			})
+			.collect()
+	}
+
+	/// Same as `create_sprite_pipelines`, but for `InstancedSpriteRenderer`
+	fn create_instanced_pipelines(
+		device: &Arc<Device>,
+		shader_modules: &ShaderModules,
+		render_pass_main: &Arc<dyn RenderPassAbstract + Send + Sync>,
+	) -> Result<HashMap<BlendMode, Arc<dyn GraphicsPipelineAbstract + Send + Sync>>, GraphicsPipelineCreationError> {
+		BlendMode::ALL.iter()
+			.map(|&blend_mode| {
+				let pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync> = Arc::new(
+					GraphicsPipeline::start()
+						.vertex_input(OneVertexOneInstanceDefinition::<Vertex2d, InstanceSprite>::new())
+						.vertex_shader(shader_modules.vs_sprite_instanced.main_entry_point(), ())
+						.triangle_strip()
+						.viewports_dynamic_scissors_irrelevant(1)
+						.fragment_shader(shader_modules.fs_sprite.main_entry_point(), ())
+						.blend_individual(Self::main_pass_blend(blend_mode).iter().cloned())
+						.render_pass(Subpass::from(render_pass_main.clone(), 0).expect("Render pass has no subpass"))
+						.build(device.clone())?
+				);
+				Ok((blend_mode, pipeline))
+			})
+			.collect()
+	}
+
+	/// Recompiles the named shaders from their sources under `root`, and rebuilds every pipeline using one that compiled.
+	/// Shaders that don't compile, or whose interface has changed, keep their last working version, and so do pipelines
+	/// that fail to rebuild. Either way the error is printed.
+	/// Descriptor sets made for the old pipelines still work with the new ones, since the layouts can't change.
+	pub(crate) fn reload_shaders(&mut self, device: &Arc<Device>, root: &Path, names: &[&str]) {
+		let mut reloaded = Vec::new();
+		for &name in names {
+			match self.shader_modules.reload(device, root, name) {
+				Ok(()) => {
+					println!("Reloaded shader {}", name);
+					reloaded.push(name);
+				},
+				Err(e) => println!("Failed to reload shader {}, keeping the last working version:\n{}", name, e),
+			}
+		}
+
+		let uses = |shaders: &[&str]| shaders.iter().any(|shader| reloaded.contains(shader));
+		let modules = &self.shader_modules;
+		if uses(&["vs_sprite", "fs_sprite"]) {
+			replace_pipeline(&mut self.pipelines_main, "sprite",
+				Self::create_sprite_pipelines(device, modules, &self.render_pass_main));
+		}
+		if uses(&["vs_sprite_instanced", "fs_sprite"]) {
+			replace_pipeline(&mut self.pipelines_instanced, "instanced sprite",
+				Self::create_instanced_pipelines(device, modules, &self.render_pass_main));
+		}
+		if uses(&["vs_output", "fs_lighting"]) {
+			replace_pipeline(&mut self.pipeline_lighting, "lighting",
+				fullscreen_pipeline!(device, modules, fs_lighting, self.render_pass_lighting));
+		}
+		if uses(&["vs_output", "fs_bloom"]) {
+			replace_pipeline(&mut self.pipeline_bloom, "bloom",
+				fullscreen_pipeline!(device, modules, fs_bloom, self.render_pass_lighting));
+		}
+		if uses(&["vs_output", "fs_tonemap"]) {
+			replace_pipeline(&mut self.pipeline_tonemap, "tonemap",
+				fullscreen_pipeline!(device, modules, fs_tonemap, self.render_pass_lighting));
+		}
+		if uses(&["vs_output", "fs_color_grade"]) {
+			replace_pipeline(&mut self.pipeline_color_grade, "color grading",
+				fullscreen_pipeline!(device, modules, fs_color_grade, self.render_pass_lighting));
+		}
+		if uses(&["vs_output", "fs_vignette"]) {
+			replace_pipeline(&mut self.pipeline_vignette, "vignette",
+				fullscreen_pipeline!(device, modules, fs_vignette, self.render_pass_lighting));
+		}
+		if uses(&["vs_output", "fs_output"]) {
+			replace_pipeline(&mut self.pipeline_output, "output",
+				fullscreen_pipeline!(device, modules, fs_output, self.render_pass_output));
+			replace_pipeline(&mut self.pipeline_output_render_texture, "render texture output",
+				fullscreen_pipeline!(device, modules, fs_output, self.render_pass_render_texture));
+		}
+		if uses(&["vs_debug", "fs_debug"]) {
+			replace_pipeline(&mut self.pipeline_debug_lines, "debug line",
+				debug_pipeline!(device, modules, line_list, self.render_pass_overlay));
+			replace_pipeline(&mut self.pipeline_debug_triangles, "debug triangle",
+				debug_pipeline!(device, modules, triangle_list, self.render_pass_overlay));
+		}
+	}
+
+	/// Converts the frame's lights into the layout fs_lighting expects.
+	/// Lights that can't reach the screen are skipped, and anything past MAX_LIGHTS is dropped.
+	fn build_light_data(lighting: &Lighting, camera: &Camera) -> shaders::fs_lighting::ty::LightData {
+		let [ambient_r, ambient_g, ambient_b] = lighting.get_ambient();
+		let mut data = shaders::fs_lighting::ty::LightData {
+			ambient: [ambient_r, ambient_g, ambient_b, 0.0],
+			light_pos: [[0.0; 4]; MAX_LIGHTS],
+			light_color: [[0.0; 4]; MAX_LIGHTS],
+			light_cone: [[0.0; 4]; MAX_LIGHTS],
+			light_count: 0,
+		};
+
+		let [width, height] = camera.get_full_resolution();
+		let visible = lighting.get_lights().iter()
+			.map(|(pos, light)| (camera.world_to_pixel(*pos), light))
+			.filter(|(pos, light)|
+				pos.x + light.radius > 0.0 && pos.x - light.radius < width as f32
+					&& pos.y + light.radius > 0.0 && pos.y - light.radius < height as f32)
+			.take(MAX_LIGHTS);
+		for (i, (pos, light)) in visible.enumerate() {
+			data.light_pos[i] = [pos.x, pos.y, light.radius, light.intensity];
+			data.light_color[i] = [light.color[0], light.color[1], light.color[2], 0.0];
+			if let Some(cone) = light.cone {
+				// Pixel coordinates are Y+ down, so flip the direction
+				data.light_cone[i] = [cone.direction.cos(), -cone.direction.sin(), cone.half_angle.cos(), 1.0];
+			}
+			data.light_count = i as i32 + 1;
+		}
+		data
+	}
+
+	/// Set 1 for the sprite pipelines: the palettes, and how far each palette cycle has got by `time`.
+	/// Cycles past MAX_PALETTE_CYCLES are dropped.
+	fn create_palette_descriptor_set(&self, time: f32) -> Result<Arc<dyn DescriptorSet + Send + Sync>, RendererError> {
+		let mut data = shaders::fs_sprite::ty::PaletteCycles {
+			cycles: [[0; 4]; MAX_PALETTE_CYCLES],
+			cycle_count: 0,
+		};
+		let time_ms = (time as f64 * 1000.0) as u64;
+		for (i, &(palette, cycle)) in self.palette_cycles.iter().take(MAX_PALETTE_CYCLES).enumerate() {
+			let steps = time_ms / cycle.step_ms.max(1) as u64;
+			let offset = if cycle.length == 0 { 0 } else { steps % cycle.length as u64 };
+			data.cycles[i] = [palette as i32, cycle.start as i32, cycle.length as i32, offset as i32];
+			data.cycle_count = i as i32 + 1;
 		}
+
+		let cycle_buf = self.palette_cycle_pool.next(data)?;
+		Ok(Arc::new(
+			PersistentDescriptorSet::start(
+				self.pipelines_main[&BlendMode::Alpha].descriptor_set_layout(1).expect("Failed to get set layout").clone())
+				.add_sampled_image(self.palette_image.clone(), self.sampler_simple_nearest.clone())?
+				.add_buffer(cycle_buf)?
+				.build()?,
+		))
+	}
+
+	/// Device features the pipelines need
+	pub(crate) fn required_features() -> Features {
+		Features {
+			// For main_pass_blend
+			independent_blend: true,
+			..Features::none()
+		}
+	}
+
+	/// Blending for the color, emissive and occlusion attachments. Only color blends with `blend_mode`:
+	/// emissive is always blended like alpha, and occlusion takes the max so a sprite can only ever add to it.
+	fn main_pass_blend(blend_mode: BlendMode) -> [AttachmentBlend; 3] {
+		let occlusion = AttachmentBlend {
+			color_op: BlendOp::Max,
+			alpha_op: BlendOp::Max,
+			..Self::attachment_blend(BlendMode::Alpha)
+		};
+		[Self::attachment_blend(blend_mode), Self::attachment_blend(BlendMode::Alpha), occlusion]
+	}
+
+	/// fs_sprite outputs premultiplied alpha, which is what makes multiply possible with fixed-function blending.
+	fn attachment_blend(blend_mode: BlendMode) -> AttachmentBlend {
+		// Additive and multiply leave the destination alpha alone
+		let (color_source, color_destination, alpha_source, alpha_destination) = match blend_mode {
+			BlendMode::Alpha => (BlendFactor::One, BlendFactor::OneMinusSrcAlpha, BlendFactor::One, BlendFactor::OneMinusSrcAlpha),
+			BlendMode::Additive => (BlendFactor::One, BlendFactor::One, BlendFactor::Zero, BlendFactor::One),
+			BlendMode::Multiply => (BlendFactor::DstColor, BlendFactor::OneMinusSrcAlpha, BlendFactor::Zero, BlendFactor::One),
+		};
+		AttachmentBlend {
+			enabled: true,
+			color_op: BlendOp::Add,
+			color_source,
+			color_destination,
+			alpha_op: BlendOp::Add,
+			alpha_source,
+			alpha_destination,
+			mask_red: true,
+			mask_green: true,
+			mask_blue: true,
+			mask_alpha: true,
+		}
+	}
+
+	/// Set 0 with a single sampled image at binding 0, which is all most of the pipelines need.
+	fn create_image_descriptor_set<I: vulkano::image::ImageViewAccess + Send + Sync + 'static>(
+		pipeline: &Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+		image: I,
+		sampler: &Arc<Sampler>,
+	) -> Result<Arc<dyn DescriptorSet + Send + Sync>, RendererError> {
+		let layout = pipeline.descriptor_set_layout(0).expect("Failed to get set layout");
+		Ok(Arc::new(
+			PersistentDescriptorSet::start(layout.clone())
+				.add_sampled_image(image, sampler.clone())?
+				.build()?,
+		))
+	}
+
+	/// Records one pass of the post-processing chain, reading `source` and writing `framebuffer`.
+	fn draw_post_effect(
+		&self,
+		builder: &mut AutoCommandBufferBuilder,
+		effect: &PostEffect,
+		source: &Arc<AttachmentImage>,
+		framebuffer: &Arc<dyn FramebufferAbstract + Send + Sync>,
+		dynamic_state: &DynamicState,
+	) -> Result<(), RendererError> {
+		builder
+			.begin_render_pass(framebuffer.clone(), false, vec![ClearValue::None])?;
+		let vertex_buffer = vec![self.vertex_buffer_square.clone()];
+		match *effect {
+			PostEffect::Bloom { threshold, intensity, radius } => {
+				let push_constants = shaders::fs_bloom::ty::PushConstants {
+					threshold,
+					intensity,
+					radius: radius.min(MAX_BLOOM_RADIUS) as i32,
+				};
+				let descriptor_set = Self::create_image_descriptor_set(
+					&self.pipeline_bloom, source.clone(), &self.sampler_simple_nearest)?;
+				builder
+					.draw(self.pipeline_bloom.clone(), dynamic_state, vertex_buffer, descriptor_set, push_constants)?;
+			}
+			PostEffect::Tonemap { exposure, operator } => {
+				let push_constants = shaders::fs_tonemap::ty::PushConstants {
+					exposure,
+					tonemap_operator: match operator {
+						TonemapOperator::Reinhard => 0,
+						TonemapOperator::Aces => 1,
+					},
+				};
+				let descriptor_set = Self::create_image_descriptor_set(
+					&self.pipeline_tonemap, source.clone(), &self.sampler_simple_nearest)?;
+				builder
+					.draw(self.pipeline_tonemap.clone(), dynamic_state, vertex_buffer, descriptor_set, push_constants)?;
+			}
+			PostEffect::ColorGrading { intensity } => {
+				let push_constants = shaders::fs_color_grade::ty::PushConstants { intensity };
+				let layout = self.pipeline_color_grade.descriptor_set_layout(0).expect("Failed to get set layout");
+				let descriptor_set = Arc::new(
+					PersistentDescriptorSet::start(layout.clone())
+						.add_sampled_image(source.clone(), self.sampler_simple_nearest.clone())?
+						.add_sampled_image(self.color_grading_lut.clone(), self.sampler_simple_linear.clone())?
+						.build()?,
+				);
+				builder
+					.draw(self.pipeline_color_grade.clone(), dynamic_state, vertex_buffer, descriptor_set, push_constants)?;
+			}
+			PostEffect::Vignette { color, intensity, radius, softness } => {
+				let push_constants = shaders::fs_vignette::ty::PushConstants {
+					color: [color[0], color[1], color[2], 1.0],
+					intensity,
+					radius,
+					softness,
+				};
+				let descriptor_set = Self::create_image_descriptor_set(
+					&self.pipeline_vignette, source.clone(), &self.sampler_simple_nearest)?;
+				builder
+					.draw(self.pipeline_vignette.clone(), dynamic_state, vertex_buffer, descriptor_set, push_constants)?;
+			}
+			PostEffect::Crt { .. } => unreachable!("CRT is applied in the output pass"),
+		}
+		builder
+			.end_render_pass()?;
+		Ok(())
+	}
+
+	/// Replaces the texture used for drawing sprites. The returned future has to finish before the next frame.
+	pub(crate) fn set_atlas(&mut self, atlas: &Atlas, queue: &Arc<Queue>) -> Result<Box<dyn GpuFuture>, RendererError> {
+		let (atlas_image, upload_future) = upload_image(&atlas.image, Format::R8G8B8A8Srgb, queue)?;
+		self.descriptor_set_main = Self::create_image_descriptor_set(
+			&self.pipelines_main[&BlendMode::Alpha], atlas_image, &self.sampler_simple_nearest)?;
+		Ok(upload_future)
+	}
+
+	/// Replaces every palette, including their cycles. The returned future has to finish before the next frame.
+	pub(crate) fn set_palettes(&mut self, palettes: &PaletteSet, queue: &Arc<Queue>) -> Result<Box<dyn GpuFuture>, RendererError> {
+		let (palette_image, upload_future) = upload_image(&palettes.build_image(), Format::R8G8B8A8Srgb, queue)?;
+		self.palette_image = palette_image;
+		self.palette_cycles = palettes.cycles();
+		Ok(upload_future)
+	}
+
+	/// The returned future has to finish before the next frame.
+	pub(crate) fn set_color_grading_lut(&mut self, lut: &ImageData, queue: &Arc<Queue>) -> Result<Box<dyn GpuFuture>, RendererError> {
+		if lut.height < 2 || lut.width != lut.height * lut.height {
+			return Err(RendererError::InvalidLut([lut.width, lut.height]));
+		}
+		let (lut_image, upload_future) = upload_image(lut, Format::R8G8B8A8Unorm, queue)?;
+		self.color_grading_lut = lut_image;
+		Ok(upload_future)
+	}
+
+	/// Makes sure there's a set of targets for each view at that view's resolution, recreating any that have changed
+	/// and dropping any that are no longer needed. Frames still in flight keep the old images alive.
+	fn update_targets(&mut self, device: &Arc<Device>, resolutions: &[[u32; 2]]) -> Result<(), RendererError> {
+		self.targets.truncate(resolutions.len());
+		for (i, &resolution) in resolutions.iter().enumerate() {
+			if self.targets.get(i).is_some_and(|targets| targets.resolution == resolution) {
+				continue;
+			}
+			let targets = IntermediateTargets::new(
+				device,
+				resolution,
+				self.render_pass_main.clone(),
+				self.render_pass_lighting.clone(),
+				self.render_pass_overlay.clone(),
+				&self.pipeline_lighting,
+				&self.sampler_simple_nearest,
+			)?;
+			if i < self.targets.len() {
+				self.targets[i] = targets;
+			} else {
+				self.targets.push(targets);
+			}
+		}
+		Ok(())
+	}
+
+	/// Makes images for any render textures that views are about to draw into for the first time,
+	/// and frees ones whose handles have all been dropped.
+	fn update_render_textures(&mut self, device: &Arc<Device>, views: &[View]) -> Result<(), RendererError> {
+		self.render_textures.retain(|_, target| target.texture.strong_count() > 0);
+
+		for view in views {
+			let ViewTarget::Texture(texture) = &view.target else { continue };
+			if self.render_textures.contains_key(&texture.get_id()) {
+				continue;
+			}
+			let resolution = texture.get_resolution();
+			// HDR, since it holds the view's image before post-processing; see record_views
+			let image = AttachmentImage::with_usage(
+				device.clone(),
+				resolution,
+				Format::R16G16B16A16Sfloat,
+				ImageUsage {
+					color_attachment: true,
+					sampled: true,
+					..ImageUsage::none()
+				}
+			)?;
+			let framebuffer = IntermediateTargets::create_single_framebuffer(self.render_pass_render_texture.clone(), &image)?;
+			let mut dynamic_state = DynamicState::none();
+			dynamic_state.viewports = Some(vec![Viewport {
+				origin: [0.0, 0.0],
+				dimensions: [resolution[0] as f32, resolution[1] as f32],
+				depth_range: 0.0..1.0,
+			}]);
+			let descriptor_set = Self::create_image_descriptor_set(
+				&self.pipelines_main[&BlendMode::Alpha], image, &self.sampler_simple_nearest)?;
+			self.render_textures.insert(texture.get_id(), RenderTextureTarget {
+				texture: texture.downgrade(),
+				framebuffer,
+				dynamic_state,
+				descriptor_set,
+			});
+		}
+		Ok(())
+	}
+
+	/// Records everything up to the output pass for each view, each in its own set of targets.
+	/// Views drawing into render textures are finished off here; the rest are returned for `record_output`.
+	/// Render textures get the lit image from before post-processing. Sprites draw them unlit, so they go through
+	/// lighting and post-processing once each, like everything else on the screen.
+	pub(crate) fn record_views(
+		&mut self,
+		builder: &mut AutoCommandBufferBuilder,
+		device: &Arc<Device>,
+		mut views: Vec<View>,
+	) -> Result<Vec<ViewOutput>, RendererError> {
+		// Render textures go first, so views on the screen can draw sprites with them. Stable, so the order is otherwise kept.
+		views.sort_by_key(|view| matches!(view.target, ViewTarget::Screen(_)));
+		let resolutions: Vec<_> = views.iter().map(|view| view.camera.get_resolution()).collect();
+		self.update_targets(device, &resolutions)?;
+		self.update_render_textures(device, &views)?;
+
+		let mut outputs = Vec::new();
+		for (i, mut view) in views.into_iter().enumerate() {
+			let own_texture = match &view.target {
+				ViewTarget::Texture(texture) => Some(texture.get_id()),
+				ViewTarget::Screen(_) => None,
+			};
+			let source = self.record_scene(builder, &mut view.frame, view.camera, view.layers, own_texture, i)?;
+			let overlay = self.targets[i].overlay_image.clone();
+			let resolution = view.camera.get_resolution();
+			let mut push_constants = self.output_push_constants(view.camera, view.frame.get_time());
+			match view.target {
+				ViewTarget::Screen(rect) => outputs.push(ViewOutput { source, resolution, overlay, rect, push_constants }),
+				ViewTarget::Texture(texture) => {
+					// The CRT effect is for the screen, not things drawn on it
+					push_constants.scanline_intensity = 0.0;
+					push_constants.curvature = 0.0;
+					let texture_resolution = texture.get_resolution();
+					let output = ViewOutput { source, resolution, overlay, rect: ScreenRect::full(texture_resolution), push_constants };
+					let target = &self.render_textures[&texture.get_id()];
+					self.record_output_with(
+						builder, &self.pipeline_output_render_texture, &[output], texture_resolution,
+						&target.framebuffer, &target.dynamic_state, [0.0, 0.0, 0.0])?;
+				},
+			}
+		}
+		Ok(outputs)
+	}
+
+	/// Records the sprite, lighting, post-processing and debug overlay passes into the targets at `target_index`,
+	/// leaving out anything outside `layers`, and any sprites drawn with `own_texture` (the render texture
+	/// the view is drawing into, if any). Views drawing into a render texture skip post-processing.
+	/// Returns the image that should be shown, which `record_output` then scales up.
+	fn record_scene(
+		&mut self,
+		builder: &mut AutoCommandBufferBuilder,
+		frame: &mut FrameBuilder,
+		camera: &Camera,
+		layers: LayerMask,
+		own_texture: Option<u64>,
+		target_index: usize,
+	) -> Result<Arc<AttachmentImage>, RendererError> {
+		let time = frame.get_time();
+
+		let tilemap_chunks: Vec<_> = frame.take_tilemap_chunks().into_iter()
+			.filter(|chunk| layers.contains(chunk.order.layer))
+			.collect();
+		self.upload_tilemap_chunks(builder, &tilemap_chunks)?;
+		let mut instance_batches = frame.get_instanced_renderer().take_batches();
+		instance_batches.retain(|batch| layers.contains(batch.order.layer));
+		let main_pass_draws = self.build_main_pass_draws(&tilemap_chunks, instance_batches)?;
+		let main_pass_orders: Vec<_> = main_pass_draws.iter().map(|(order, _)| *order).collect();
+		let sprites = frame.get_sprite_renderer().build_buffers(&main_pass_orders);
+		let light_data = Self::build_light_data(frame.get_lighting(), camera);
+		let palette_set = self.create_palette_descriptor_set(time)?;
+		let targets = &self.targets[target_index];
+
+		let transformation_matrix = camera.get_sprite_matrix();
+
+		let push_constants = shaders::vs_sprite::ty::PushConstants {
+			time,
+			_dummy0: [0u8; 12],
+			transform: transformation_matrix.into(),
+		};
+
+		let clear_values_main = vec![
+			[0.0, 0.0, 0.0, 1.0].into(),
+			[0.0].into(),
+			[0.0].into(),
+		];
+
+		builder
+			.begin_render_pass(targets.framebuffer_main.clone(), false, clear_values_main)?;
+
+		// Tilemaps and instances go before any sprites with the same draw order, so things standing on tiles are on top.
+		// build_buffers has already split batches so none of them straddle one.
+		let mut main_pass_draws = main_pass_draws.iter().peekable();
+
+		// Nothing to upload if nothing was drawn
+		if !sprites.batches.is_empty() {
+			let vert_buf = Arc::new(self.vertex_buffer_pool.chunk(sprites.vertices)?);
+			let ind_buf = Arc::new(self.index_buffer_pool.chunk(sprites.indices)?);
+
+			for batch in sprites.batches.into_iter().filter(|batch| layers.contains(batch.order.layer)) {
+				while let Some((_, draw)) = main_pass_draws.next_if(|(order, _)| *order <= batch.order) {
+					self.draw_main_pass_draw(builder, draw, &targets.dynamic_state, &palette_set, push_constants)?;
+				}
+				// Render textures nothing has drawn into yet have no image to sample
+				let descriptor_set = match batch.texture {
+					SpriteTexture::Atlas => self.descriptor_set_main.clone(),
+					SpriteTexture::Render(id) if Some(id) != own_texture => match self.render_textures.get(&id) {
+						Some(target) => target.descriptor_set.clone(),
+						None => continue,
+					},
+					SpriteTexture::Render(_) => continue,
+				};
+				let ind_slice = BufferSlice::from_typed_buffer_access(ind_buf.clone())
+					.slice(batch.indices)
+					.expect("Sprite batch is outside the index buffer");
+				builder
+					.draw_indexed(
+						self.pipelines_main[&batch.blend_mode].clone(),
+						&targets.dynamic_state,
+						vec![vert_buf.clone()],
+						ind_slice,
+						(descriptor_set, palette_set.clone()),
+						push_constants
+					)?;
+			}
+		}
+		for (_, draw) in main_pass_draws {
+			self.draw_main_pass_draw(builder, draw, &targets.dynamic_state, &palette_set, push_constants)?;
+		}
+
+		builder
+			.end_render_pass()?;
+
+		let light_buf = self.light_buffer_pool.next(light_data)?;
+		let descriptor_set_lights = Arc::new(
+			PersistentDescriptorSet::start(
+				self.pipeline_lighting.descriptor_set_layout(1).expect("Failed to get set layout").clone())
+				.add_buffer(light_buf)?
+				.build()?,
+		);
+
+		builder
+			.begin_render_pass(targets.framebuffer_lighting.clone(), false, vec![ClearValue::None])?
+			.draw(
+				self.pipeline_lighting.clone(),
+				&targets.dynamic_state,
+				vec![self.vertex_buffer_square.clone()],
+				(targets.descriptor_set_lighting.clone(), descriptor_set_lights),
+				()
+			)?
+			.end_render_pass()?;
+
+		let mut source = targets.lit_image.clone();
+		let effects = self.post_processing.enabled()
+			.filter(|effect| own_texture.is_none() && !matches!(effect, PostEffect::Crt { .. }));
+		for (i, effect) in effects.enumerate() {
+			self.draw_post_effect(builder, effect, &source, &targets.framebuffers_post[i % 2], &targets.dynamic_state)?;
+			source = targets.post_images[i % 2].clone();
+		}
+
+		self.record_debug_overlay(builder, frame, camera, targets)?;
+		Ok(source)
+	}
+
+	/// Copies any tilemap chunks that haven't been seen before into device-local buffers,
+	/// and forgets about ones whose tilemap has since replaced or dropped them.
+	fn upload_tilemap_chunks(
+		&mut self,
+		builder: &mut AutoCommandBufferBuilder,
+		chunks: &[TilemapChunkDraw],
+	) -> Result<(), RendererError> {
+		self.tilemap_buffers.retain(|_, buffers| buffers.mesh.strong_count() > 0);
+
+		let device = self.vertex_buffer_pool.device().clone();
+		for chunk in chunks {
+			if self.tilemap_buffers.contains_key(&chunk.mesh.id) {
+				continue;
+			}
+			let mesh = &chunk.mesh;
+			// The pools are just as good for staging as anything else, and are already set up
+			let vertex_staging = self.vertex_buffer_pool.chunk(mesh.vertices.iter().cloned())?;
+			let index_staging = self.index_buffer_pool.chunk(mesh.indices.iter().cloned())?;
+			let vertices = DeviceLocalBuffer::array(
+				device.clone(),
+				mesh.vertices.len(),
+				BufferUsage { vertex_buffer: true, transfer_destination: true, ..BufferUsage::none() },
+				device.active_queue_families(),
+			)?;
+			let indices = DeviceLocalBuffer::array(
+				device.clone(),
+				mesh.indices.len(),
+				BufferUsage { index_buffer: true, transfer_destination: true, ..BufferUsage::none() },
+				device.active_queue_families(),
+			)?;
+			builder
+				.copy_buffer(vertex_staging, vertices.clone())?
+				.copy_buffer(index_staging, indices.clone())?;
+			self.tilemap_buffers.insert(mesh.id, TilemapChunkBuffers {
+				mesh: Arc::downgrade(mesh),
+				vertices,
+				indices,
+			});
+		}
+		Ok(())
+	}
+
+	/// Uploads all the frame's instances in one go, and sorts everything else for the main pass by draw order.
+	/// Tilemap chunks go under instances with the same draw order.
+	fn build_main_pass_draws(
+		&self,
+		tilemap_chunks: &[TilemapChunkDraw],
+		instance_batches: Vec<InstanceBatch>,
+	) -> Result<Vec<(DrawOrder, MainPassDraw)>, RendererError> {
+		let mut draws: Vec<_> = tilemap_chunks.iter()
+			.map(|chunk| (chunk.order, MainPassDraw::TilemapChunk(chunk.mesh.id)))
+			.collect();
+
+		if !instance_batches.is_empty() {
+			let instances: Vec<InstanceSprite> = instance_batches.iter()
+				.flat_map(|batch| batch.instances.iter().copied())
+				.collect();
+			let instance_buf = Arc::new(self.instance_buffer_pool.chunk(instances)?);
+			let mut start = 0;
+			for batch in instance_batches {
+				let end = start + batch.instances.len();
+				let instances = BufferSlice::from_typed_buffer_access(instance_buf.clone())
+					.slice(start..end)
+					.expect("Instance batch is outside the instance buffer");
+				draws.push((batch.order, MainPassDraw::Instances {
+					blend_mode: batch.blend_mode,
+					instances: Arc::new(instances),
+				}));
+				start = end;
+			}
+		}
+
+		// Stable, so ties keep the order above
+		draws.sort_by_key(|(order, _)| *order);
+		Ok(draws)
+	}
+
+	/// Tilemap chunks always use alpha blending, like sprites do by default.
+	fn draw_main_pass_draw(
+		&self,
+		builder: &mut AutoCommandBufferBuilder,
+		draw: &MainPassDraw,
+		dynamic_state: &DynamicState,
+		palette_set: &Arc<dyn DescriptorSet + Send + Sync>,
+		push_constants: shaders::vs_sprite::ty::PushConstants,
+	) -> Result<(), RendererError> {
+		match draw {
+			MainPassDraw::TilemapChunk(mesh_id) => {
+				let buffers = &self.tilemap_buffers[mesh_id];
+				builder
+					.draw_indexed(
+						self.pipelines_main[&BlendMode::Alpha].clone(),
+						dynamic_state,
+						vec![buffers.vertices.clone()],
+						buffers.indices.clone(),
+						(self.descriptor_set_main.clone(), palette_set.clone()),
+						push_constants
+					)?;
+			},
+			MainPassDraw::Instances { blend_mode, instances } => {
+				builder
+					.draw(
+						self.pipelines_instanced[blend_mode].clone(),
+						dynamic_state,
+						vec![self.vertex_buffer_quad.clone(), instances.clone()],
+						(self.descriptor_set_main.clone(), palette_set.clone()),
+						push_constants
+					)?;
+			},
+		}
+		Ok(())
+	}
+
+	/// Draws the frame's debug shapes into the overlay image, or just clears it if there aren't any.
+	fn record_debug_overlay(
+		&self,
+		builder: &mut AutoCommandBufferBuilder,
+		frame: &mut FrameBuilder,
+		camera: &Camera,
+		targets: &IntermediateTargets,
+	) -> Result<(), RendererError> {
+		let vertices = frame.get_debug_draw().build_vertices(camera, &self.debug_settings);
+
+		builder
+			.begin_render_pass(targets.framebuffer_overlay.clone(), false, vec![[0.0, 0.0, 0.0, 0.0].into()])?;
+		let batches = [(&self.pipeline_debug_lines, vertices.lines), (&self.pipeline_debug_triangles, vertices.triangles)];
+		for (pipeline, vertices) in batches {
+			if vertices.is_empty() {
+				continue;
+			}
+			let vertex_buffer = Arc::new(self.debug_vertex_pool.chunk(vertices)?);
+			builder
+				.draw(pipeline.clone(), &targets.dynamic_state, vec![vertex_buffer], (), ())?;
+		}
+		builder
+			.end_render_pass()?;
+		Ok(())
+	}
+
+	fn output_push_constants(&self, camera: &Camera, time: f32) -> shaders::fs_output::ty::PushConstants {
+		// CRT can't go through the chain like everything else, so just pick out the last enabled one
+		let (scanline_intensity, curvature) = self.post_processing.enabled()
+			.filter_map(|effect| match *effect {
+				PostEffect::Crt { scanline_intensity, curvature } => Some((scanline_intensity, curvature)),
+				_ => None,
+			})
+			.last()
+			.unwrap_or((0.0, 0.0));
+		// Only the visible part of the intermediate image gets shown, leaving out the margin
+		let [offset_x, offset_y] = camera.get_visible_offset();
+		let [width, height] = camera.get_resolution();
+		let [full_width, full_height] = camera.get_full_resolution();
+		shaders::fs_output::ty::PushConstants {
+			uv_offset: [offset_x / full_width as f32, offset_y / full_height as f32],
+			uv_scale: [width as f32 / full_width as f32, height as f32 / full_height as f32],
+			time,
+			scanline_intensity,
+			curvature,
+		}
+	}
+
+	/// Records the output pass, which draws the visible part of each view's image into its rect on the screen.
+	/// `dynamic_state` has the viewport the whole screen, `screen_resolution` in size, is scaled to in `framebuffer`.
+	/// Anything no view covers is filled with `clear_color`.
+	pub(crate) fn record_output(
+		&self,
+		builder: &mut AutoCommandBufferBuilder,
+		views: &[ViewOutput],
+		screen_resolution: [u32; 2],
+		framebuffer: &Arc<dyn FramebufferAbstract + Send + Sync>,
+		dynamic_state: &DynamicState,
+		clear_color: [f32; 3],
+	) -> Result<(), RendererError> {
+		self.record_output_with(
+			builder, &self.pipeline_output, views, screen_resolution, framebuffer, dynamic_state, clear_color)
+	}
+
+	/// `record_output` with a given output pipeline, which has to match `framebuffer`'s render pass.
+	#[allow(clippy::too_many_arguments)]
+	fn record_output_with(
+		&self,
+		builder: &mut AutoCommandBufferBuilder,
+		pipeline: &Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+		views: &[ViewOutput],
+		screen_resolution: [u32; 2],
+		framebuffer: &Arc<dyn FramebufferAbstract + Send + Sync>,
+		dynamic_state: &DynamicState,
+		clear_color: [f32; 3],
+	) -> Result<(), RendererError> {
+		let [clear_r, clear_g, clear_b] = clear_color;
+		let clear_values = vec![[clear_r, clear_g, clear_b, 1.0].into()];
+
+		let screen = dynamic_state.viewports.as_ref()
+			.and_then(|viewports| viewports.first())
+			.expect("Output pass needs a viewport");
+		let scale = [
+			screen.dimensions[0] / screen_resolution[0] as f32,
+			screen.dimensions[1] / screen_resolution[1] as f32,
+		];
+
+		builder
+			.begin_render_pass(framebuffer.clone(), false, clear_values)?;
+		// Vulkan doesn't allow empty viewports
+		for view in views.iter().filter(|view| view.rect.size[0] > 0 && view.rect.size[1] > 0) {
+			let layout = pipeline.descriptor_set_layout(0).expect("Failed to get set layout");
+			let descriptor_set_output = Arc::new(
+				PersistentDescriptorSet::start(layout.clone())
+					.add_sampled_image(view.source.clone(), self.sampler_simple_nearest.clone())?
+					.add_sampled_image(view.overlay.clone(), self.sampler_simple_nearest.clone())?
+					.build()?,
+			);
+
+			// Screen rects are Y+ up, but viewports are Y+ down
+			let top = screen_resolution[1] as i32 - view.rect.pos[1] - view.rect.size[1] as i32;
+			let mut view_state = DynamicState::none();
+			view_state.viewports = Some(vec![Viewport {
+				origin: [
+					screen.origin[0] + view.rect.pos[0] as f32 * scale[0],
+					screen.origin[1] + top as f32 * scale[1],
+				],
+				dimensions: [view.rect.size[0] as f32 * scale[0], view.rect.size[1] as f32 * scale[1]],
+				depth_range: 0.0..1.0,
+			}]);
+
+			builder
+				.draw(
+					pipeline.clone(),
+					&view_state,
+					vec![self.vertex_buffer_square.clone()],
+					descriptor_set_output,
+					view.push_constants
+				)?;
+		}
+		builder
+			.end_render_pass()?;
+		Ok(())
 	}
 }
 
@@ -238,7 +1492,23 @@ pub struct Renderer {
 
 	data: RenderData,
 
+	video_settings: VideoSettings,
 	framebuffers_output: Vec<Arc<dyn FramebufferAbstract + Send + Sync>>,
+	scaling_mode: ScalingMode,
+	/// Fills whatever part of the window the scaled image doesn't
+	bar_color: [f32; 3],
+	/// Size of the screen views are laid out on, before it's scaled up to the window
+	screen_resolution: [u32; 2],
+
+	/// Whether swapchain images can be copied out of, for `CaptureTarget::Output`
+	output_capture_supported: bool,
+	/// Captures to take during the next frame
+	capture_requests: Vec<CaptureTarget>,
+	/// Captures from the last frame, waiting for `take_captures`
+	captures: Vec<(CaptureTarget, ImageData)>,
+
+	/// Set by `watch_shaders`
+	shader_watcher: Option<ShaderWatcher>,
 
 	previous_frame_end: Option<Box<dyn GpuFuture>>,
 	// TODO `on_resize` method instead of this - we'll need to handle other things like scaling anyway
@@ -246,31 +1516,60 @@ pub struct Renderer {
 }
 
 impl Renderer {
-	pub fn init(events_loop: &EventLoop<()>) -> Self {
+	pub fn init(events_loop: &EventLoop<()>, video_settings: VideoSettings) -> Result<Self, RendererError> {
 		let instance = {
-			let extensions = vulkano_win::required_extensions();
-			Instance::new(None, &extensions, None)
-				.expect("Failed to create Vulkan instance.")
+			// Needed for exclusive fullscreen where it's supported
+			let optional_extensions = InstanceExtensions {
+				khr_get_physical_device_properties2: true,
+				khr_get_surface_capabilities2: true,
+				..InstanceExtensions::none()
+			};
+			let extensions = vulkano_win::required_extensions().union(
+				&InstanceExtensions::supported_by_core()
+					.unwrap_or_else(|_| InstanceExtensions::none())
+					.intersection(&optional_extensions));
+			Instance::new(None, &extensions, None)?
 		};
-		let physical = select_physical_device(&instance);
-		let physical_device_index = physical.index();
 
-		let surface = Self::create_window(&instance, &events_loop);
+		// The window has to come first so we can check which devices are able to present to it
+		let surface = Self::create_window(&instance, events_loop, &video_settings.window)?;
 
-		let (device, queue) = Self::get_device_and_queue(physical);
+		let requirements = DeviceRequirements {
+			extensions: DeviceExtensions {
+				khr_swapchain: true,
+				..DeviceExtensions::none()
+			},
+			optional_extensions: DeviceExtensions {
+				ext_full_screen_exclusive: true,
+				..DeviceExtensions::none()
+			},
+			features: RenderData::required_features(),
+			surface: Some(&surface),
+		};
+		let physical = device::select_physical_device(&instance, &requirements, video_settings.device.as_deref())?;
+		let physical_device_index = physical.index();
+		let (device, queue) = device::create_device(physical, &requirements)?;
 
 		let (swapchain, swapchain_images) =
-			Self::create_swapchain(physical, &device, &surface, &queue);
+			Self::create_swapchain(physical, &device, &surface, &queue, &video_settings, None)?;
 
-		let mut render_data = RenderData::init(&device, swapchain.format());
+		let (mut render_data, atlas_upload_future) = RenderData::init(&device, &queue, swapchain.format())?;
 
+		let output_capture_supported = surface.capabilities(physical)?.supported_usage_flags.transfer_source
+			&& Readback::supports_format(swapchain.format());
+		if !output_capture_supported {
+			println!("Swapchain images can't be read back; output captures are disabled");
+		}
+
+		let scaling_mode = ScalingMode::default();
 		let framebuffers_output = Self::window_size_dependent_setup(
-			&swapchain_images, render_data.render_pass_output.clone(), &mut render_data.dynamic_state);
+			&swapchain_images, render_data.render_pass_output.clone(), &mut render_data.dynamic_state,
+			scaling_mode, DEFAULT_RESOLUTION)?;
 
 		// I'm not clear on what exactly this does, but it sounds important for freeing memory that's no longer needed
-		let previous_frame_end = Some(sync::now(device.clone()).boxed());
+		let previous_frame_end = Some(sync::now(device.clone()).join(atlas_upload_future).boxed());
 
-		Self {
+		Ok(Self {
 			instance,
 			surface,
 			physical_device_index,
@@ -281,229 +1580,455 @@ impl Renderer {
 
 			data: render_data,
 
+			video_settings,
 			framebuffers_output,
+			scaling_mode,
+			bar_color: [0.0, 0.0, 0.0],
+			screen_resolution: DEFAULT_RESOLUTION,
+
+			output_capture_supported,
+			capture_requests: Vec::new(),
+			captures: Vec::new(),
+
+			shader_watcher: None,
 
 			previous_frame_end,
 			recreate_swapchain: false,
+		})
+	}
+
+	/// Replaces the texture used for drawing sprites.
+	/// Regions from a previously uploaded atlas are meaningless after this.
+	pub fn upload_atlas(&mut self, atlas: &Atlas) -> Result<(), RendererError> {
+		let upload_future = self.data.set_atlas(atlas, &self.graphics_queue)?;
+		self.join_previous_frame(upload_future);
+		Ok(())
+	}
+
+	/// Replaces the palettes that indexed sprites are drawn with. See `SpriteEffects` for picking one.
+	pub fn upload_palettes(&mut self, palettes: &PaletteSet) -> Result<(), RendererError> {
+		let upload_future = self.data.set_palettes(palettes, &self.graphics_queue)?;
+		self.join_previous_frame(upload_future);
+		Ok(())
+	}
+
+	/// Recompiles shaders whenever their sources change, for tweaking them while the game is running.
+	/// `root` is where the shaders crate's sources are, normally `shaders::SOURCE_ROOT`. A shader that fails
+	/// to compile, or changes its interface, keeps its last working version, with the error printed. Meant for development only;
+	/// see `ShaderWatcher` for what can and can't be changed this way.
+	pub fn watch_shaders(&mut self, root: &Path) {
+		self.shader_watcher = Some(ShaderWatcher::new(root));
+	}
+
+	/// Goes back to leaving shaders alone. Any that were reloaded stay as they are.
+	pub fn stop_watching_shaders(&mut self) {
+		self.shader_watcher = None;
+	}
+
+	/// Applies new video settings. A different present mode or window mode recreates the swapchain before the next frame.
+	/// The device can't be changed without restarting, and the target FPS is up to whatever runs the main loop.
+	pub fn set_video_settings(&mut self, video_settings: VideoSettings) {
+		if video_settings.window != self.video_settings.window {
+			Self::apply_window_settings(self.surface.window(), &video_settings.window);
+			self.recreate_swapchain = true;
+		}
+		if video_settings.present_mode != self.video_settings.present_mode {
+			self.recreate_swapchain = true;
 		}
+		self.video_settings = video_settings;
+	}
+
+	pub fn get_video_settings(&self) -> &VideoSettings { &self.video_settings }
+
+	/// The present mode actually in use, after falling back from the preferred one if it isn't supported
+	pub fn get_present_mode(&self) -> PresentMode { self.swapchain.present_mode() }
+
+	pub fn set_scaling_mode(&mut self, scaling_mode: ScalingMode) {
+		self.scaling_mode = scaling_mode;
+		Self::update_output_viewport(
+			self.swapchain.dimensions(), self.screen_resolution, &mut self.data.dynamic_state, scaling_mode);
+	}
+
+	pub fn get_scaling_mode(&self) -> ScalingMode { self.scaling_mode }
+
+	pub fn set_bar_color(&mut self, bar_color: [f32; 3]) {
+		self.bar_color = bar_color;
+	}
+
+	pub fn get_bar_color(&self) -> [f32; 3] { self.bar_color }
+
+	/// Captures the next frame drawn. The result is available from `take_captures` straight after `draw_frame`.
+	/// Capturing makes `draw_frame` wait for the GPU to finish, so it's slower than usual.
+	pub fn request_capture(&mut self, target: CaptureTarget) {
+		if target == CaptureTarget::Output && !self.output_capture_supported {
+			println!("Output capture isn't supported on this device");
+			return;
+		}
+		if !self.capture_requests.contains(&target) {
+			self.capture_requests.push(target);
+		}
+	}
+
+	/// Takes the captures from the last frame drawn, if any were requested.
+	/// Requests made before a frame that didn't get drawn (e.g. while the window is minimised) carry over to the next one.
+	pub fn take_captures(&mut self) -> Vec<(CaptureTarget, ImageData)> {
+		std::mem::take(&mut self.captures)
 	}
 
-	fn create_window(instance: &Arc<Instance>, events_loop: &EventLoop<()>) -> Arc<Surface<Window>> {
-		WindowBuilder::new()
+	/// Replaces the LUT used by `PostEffect::ColorGrading`. See `post::identity_lut` for the layout, which it has to match.
+	pub fn set_color_grading_lut(&mut self, lut: &ImageData) -> Result<(), RendererError> {
+		let upload_future = self.data.set_color_grading_lut(lut, &self.graphics_queue)?;
+		self.join_previous_frame(upload_future);
+		Ok(())
+	}
+
+	/// The post-processing chain, for changing at runtime. Changes apply from the next frame.
+	pub fn get_post_processing(&mut self) -> &mut PostProcessing {
+		&mut self.data.post_processing
+	}
+
+	/// Which debug shapes get drawn, for every `DebugDraw`. Changes apply from the next frame.
+	pub fn get_debug_settings(&mut self) -> &mut DebugSettings {
+		&mut self.data.debug_settings
+	}
+
+	fn create_window(instance: &Arc<Instance>, events_loop: &EventLoop<()>, settings: &WindowSettings)
+			-> Result<Arc<Surface<Window>>, RendererError> {
+		let surface = WindowBuilder::new()
 			.with_title("Vulkan")
-			.build_vk_surface(events_loop, instance.clone())
-			.unwrap()
+			.with_inner_size(PhysicalSize::new(settings.size[0], settings.size[1]))
+			.build_vk_surface(events_loop, instance.clone())?;
+		Self::apply_window_settings(surface.window(), settings);
+		Ok(surface)
 	}
 
-	fn get_device_and_queue(physical: PhysicalDevice) -> (Arc<Device>, Arc<Queue>) {
-		let queue_family = physical.queue_families()
-			.find(|&q| q.supports_graphics())
-			.expect("Failed to find a graphical queue family");
+	/// Puts the window in the right mode, on the right monitor, and in the right place if windowed.
+	fn apply_window_settings(window: &Window, settings: &WindowSettings) {
+		let monitor = settings.monitor
+			.and_then(|index| window.available_monitors().nth(index))
+			.unwrap_or_else(|| window.current_monitor());
+		match settings.mode {
+			WindowMode::Windowed => {
+				window.set_fullscreen(None);
+				window.set_inner_size(PhysicalSize::new(settings.size[0], settings.size[1]));
+				if let Some([x, y]) = settings.position {
+					window.set_outer_position(PhysicalPosition::new(x, y));
+				}
+			},
+			WindowMode::Borderless => window.set_fullscreen(Some(Fullscreen::Borderless(monitor))),
+			WindowMode::Exclusive => {
+				let video_mode = monitor.video_modes()
+					.max_by_key(|mode| (mode.size().width * mode.size().height, mode.refresh_rate(), mode.bit_depth()));
+				match video_mode {
+					Some(video_mode) => window.set_fullscreen(Some(Fullscreen::Exclusive(video_mode))),
+					None => {
+						println!("No video modes available for exclusive fullscreen; using borderless instead");
+						window.set_fullscreen(Some(Fullscreen::Borderless(monitor)));
+					},
+				}
+			},
+		}
+	}
 
-		println!("Selected queue family: {:?}", queue_family);
+	/// Records the window's current size and position in the video settings, so they can be saved.
+	/// Call this whenever the window is moved or resized. Does nothing while fullscreen.
+	pub fn remember_window_placement(&mut self) {
+		let window = self.surface.window();
+		if self.video_settings.window.mode != WindowMode::Windowed || window.fullscreen().is_some() {
+			return;
+		}
+		let size = window.inner_size();
+		self.video_settings.window.size = [size.width, size.height];
+		if let Ok(position) = window.outer_position() {
+			self.video_settings.window.position = Some([position.x, position.y]);
+		}
+	}
 
-		let device_ext = DeviceExtensions {
-			khr_swapchain: true,
-			..DeviceExtensions::none()
-		};
-		let (device, mut queues) = {
-			Device::new(physical, &Features::none(), &device_ext,
-						[(queue_family, 0.5)].iter().cloned()).expect("Failed to create device")
-		};
+	pub fn set_title(&self, title: &str) {
+		self.surface.window().set_title(title);
+	}
 
-		// We only have one queue
-		// TODO use multiple queues?
-		let queue = queues.next().unwrap();
-		(device, queue)
+	/// Sets the icon shown in the title bar and taskbar (on platforms that have those), or goes back to the default.
+	pub fn set_icon(&self, icon: Option<&ImageData>) {
+		let icon = icon.and_then(|icon| {
+			Icon::from_rgba(icon.pixels.clone(), icon.width, icon.height)
+				.map_err(|e| println!("Invalid window icon: {}", e))
+				.ok()
+		});
+		self.surface.window().set_window_icon(icon);
 	}
 
-	fn create_swapchain(physical: PhysicalDevice, device: &Arc<Device>, surface: &Arc<Surface<Window>>, queue: &Arc<Queue>)
-						-> (Arc<Swapchain<Window>>, Vec<Arc<SwapchainImage<Window>>>) {
-		let caps = surface.capabilities(physical).unwrap();
+	/// Creates a swapchain to fit the window. Pass in the old swapchain if there is one, since a surface
+	/// can only have one swapchain at a time; this is also how the present mode gets changed.
+	fn create_swapchain(
+		physical: PhysicalDevice,
+		device: &Arc<Device>,
+		surface: &Arc<Surface<Window>>,
+		queue: &Arc<Queue>,
+		video_settings: &VideoSettings,
+		old_swapchain: Option<Arc<Swapchain<Window>>>,
+	) -> Result<SwapchainWithImages, RendererError> {
+		let caps = surface.capabilities(physical)?;
 		// TODO we probably want to actually pick this properly?
 		//      Seems to normally be opaque, but shouldn't rely on that.
-		let alpha = caps.supported_composite_alpha.iter().next().unwrap();
+		let alpha = caps.supported_composite_alpha.iter().next()
+			.ok_or(RendererError::UnsupportedSurface("composite alpha modes"))?;
 		println!("Using alpha mode {:?}", alpha);
 		// TODO formats?
-		let format = caps.supported_formats[0].0;
+		let format = caps.supported_formats.first()
+			.ok_or(RendererError::UnsupportedSurface("image formats"))?.0;
 		println!("Using format {:?}", format);
 
+		let present_mode = video_settings.present_mode.choose(caps.present_modes);
+		let fullscreen_exclusive = video_settings.window.mode.fullscreen_exclusive();
+		println!("Using present mode {:?}", present_mode);
+
 		let dimensions: [u32; 2] = surface.window().inner_size().into();
+		let usage = ImageUsage {
+			color_attachment: true,
+			// For screenshots
+			transfer_source: caps.supported_usage_flags.transfer_source,
+			..ImageUsage::none()
+		};
 
-		Swapchain::new(
-			device.clone(),
-			surface.clone(),
-			caps.min_image_count,
-			format,
-			dimensions,
-			1,
-			ImageUsage::color_attachment(),
-			queue,
-			SurfaceTransform::Identity,
-			alpha,
-			// TODO actually have a setting for present mode somewhere instead of changing it here
-			PresentMode::Immediate,
-			FullscreenExclusive::Default,
-			true,
-			ColorSpace::SrgbNonLinear
-		).unwrap()
+		let swapchain = match old_swapchain {
+			Some(old_swapchain) => Swapchain::with_old_swapchain(
+				device.clone(),
+				surface.clone(),
+				caps.min_image_count,
+				format,
+				dimensions,
+				1,
+				usage,
+				queue,
+				SurfaceTransform::Identity,
+				alpha,
+				present_mode,
+				fullscreen_exclusive,
+				true,
+				ColorSpace::SrgbNonLinear,
+				old_swapchain,
+			),
+			None => Swapchain::new(
+				device.clone(),
+				surface.clone(),
+				caps.min_image_count,
+				format,
+				dimensions,
+				1,
+				usage,
+				queue,
+				SurfaceTransform::Identity,
+				alpha,
+				present_mode,
+				fullscreen_exclusive,
+				true,
+				ColorSpace::SrgbNonLinear,
+			),
+		};
+		Ok(swapchain?)
 	}
 
 	fn window_size_dependent_setup(
 		images: &[Arc<SwapchainImage<Window>>],
 		render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
-		dynamic_state: &mut DynamicState
-	) -> Vec<Arc<dyn FramebufferAbstract + Send + Sync>> {
-		let dimensions = images[0].dimensions();
-
-		let viewport = Viewport {
-			origin: [0.0, 0.0],
-			dimensions: [dimensions[0] as f32, dimensions[1] as f32],
-			depth_range: 0.0..1.0,
-		};
-		dynamic_state.viewports = Some(vec![viewport]);
+		dynamic_state: &mut DynamicState,
+		scaling_mode: ScalingMode,
+		resolution: [u32; 2],
+	) -> Result<Vec<Arc<dyn FramebufferAbstract + Send + Sync>>, FramebufferCreationError> {
+		Self::update_output_viewport(images[0].dimensions(), resolution, dynamic_state, scaling_mode);
 
 		images
 			.iter()
 			.map(|image| {
-				Arc::new(
+				Ok(Arc::new(
 					Framebuffer::start(render_pass.clone())
-						.add(image.clone())
-						.unwrap()
-						.build()
-						.unwrap(),
-				) as Arc<dyn FramebufferAbstract + Send + Sync>
+						.add(image.clone())?
+						.build()?,
+				) as Arc<dyn FramebufferAbstract + Send + Sync>)
 			})
-			.collect::<Vec<_>>()
+			.collect()
+	}
+
+	/// Points the output pass's viewport at the part of the window the screen gets scaled to.
+	/// `resolution` is the screen resolution, i.e. the visible part of the intermediate image when there's just one view.
+	fn update_output_viewport(
+		window_dimensions: [u32; 2],
+		resolution: [u32; 2],
+		dynamic_state: &mut DynamicState,
+		scaling_mode: ScalingMode,
+	) {
+		let rect = scaling_mode.output_rect(resolution, window_dimensions);
+		let viewport = Viewport {
+			origin: rect.origin,
+			dimensions: rect.dimensions,
+			depth_range: 0.0..1.0,
+		};
+		dynamic_state.viewports = Some(vec![viewport]);
 	}
 
-	fn rebuild_swapchain(&mut self) {
-		let dimensions: [u32; 2] = self.surface.window().inner_size().into();
+	/// The future the next submission has to wait for. There's only none left if a frame failed part way through.
+	fn take_previous_frame_end(&mut self) -> Box<dyn GpuFuture> {
+		self.previous_frame_end.take().unwrap_or_else(|| sync::now(self.device.clone()).boxed())
+	}
+
+	/// Makes the next frame wait for `future` as well, e.g. for an upload.
+	fn join_previous_frame(&mut self, future: Box<dyn GpuFuture>) {
+		self.previous_frame_end = Some(self.take_previous_frame_end().join(future).boxed());
+	}
+
+	fn rebuild_swapchain(&mut self) -> Result<(), RendererError> {
+		let physical = PhysicalDevice::from_index(&self.instance, self.physical_device_index).expect("Physical device went missing");
 		let (new_swapchain, new_images) =
-			match self.swapchain.recreate_with_dimensions(dimensions) {
+			match Self::create_swapchain(
+				physical,
+				&self.device,
+				&self.surface,
+				&self.graphics_queue,
+				&self.video_settings,
+				Some(self.swapchain.clone()),
+			) {
 				Ok(r) => r,
 				// This tends to happen while the user is resizing the window, apparently
-				Err(SwapchainCreationError::UnsupportedDimensions) => return,
-				Err(e) => panic!("Failed to recreate swapchain: {:?}", e)
+				Err(RendererError::SwapchainCreation(SwapchainCreationError::UnsupportedDimensions)) => return Ok(()),
+				Err(e) => return Err(e),
 			};
 
 		self.swapchain = new_swapchain;
+		self.swapchain_images = new_images.clone();
 		self.framebuffers_output = Self::window_size_dependent_setup(
 			&new_images,
 			self.data.render_pass_output.clone(),
 			&mut self.data.dynamic_state,
-		);
+			self.scaling_mode,
+			self.screen_resolution,
+		)?;
 		self.recreate_swapchain = false;
+		Ok(())
 	}
 
-	fn build_command_buffer(&mut self, mut frame: FrameBuilder, camera: &Camera, image_num: usize)
-			-> AutoCommandBuffer<StandardCommandPoolAlloc> {
-		let time = frame.get_time();
-
-		let (vert, ind) = frame.get_sprite_renderer().get_buffers();
-
-		// TODO don't unwrap these
-		let vert_buf = self.data.vertex_buffer_pool.chunk(vert.into_iter().cloned()).unwrap();
-		let ind_buf = self.data.index_buffer_pool.chunk(ind.into_iter().cloned()).unwrap();
-
-		let transformation_matrix = camera.get_sprite_matrix();
-
-		let push_constants = shaders::vs::ty::PushConstants {
-			time,
-			_dummy0: [0u8; 12],
-			transform: transformation_matrix.into(),
-		};
-
-		let push_constants_output = shaders::fs_output::ty::PushConstants {
-			time
-		};
-
-		let clear_values = vec![[0.0, 0.0, 0.0, 1.0].into()];
-
+	/// Also returns readbacks for any captures that were requested.
+	fn build_command_buffer(&mut self, views: Vec<View>, image_num: usize)
+			-> Result<FrameCommands, RendererError> {
 		let mut builder = AutoCommandBufferBuilder::primary_one_time_submit(
 			self.device.clone(),
 			self.graphics_queue.family(),
-		).unwrap();
-
-		builder
-			.begin_render_pass(self.data.framebuffer_main.clone(), false, clear_values.clone())
-			.unwrap()
-			.draw_indexed(
-				self.data.pipeline_main.clone(),
-				&DynamicState::none(),
-				vec![Arc::new(vert_buf)],
-				ind_buf,
-				(),
-				push_constants
-			)
-			.unwrap()
-			.end_render_pass()
-			.unwrap();
+		)?;
+
+		let view_outputs = self.data.record_views(&mut builder, &self.device, views)?;
+		let mut readbacks = Vec::new();
+
+		if self.capture_requests.contains(&CaptureTarget::Intermediate) {
+			// Straight from the end of the post-processing chain, leaving out the margin
+			if let Some(view) = view_outputs.first() {
+				readbacks.push((
+					CaptureTarget::Intermediate,
+					Readback::record(
+						&mut builder, &self.device, view.source.clone(), PIXEL_OFFSET, view.resolution, Format::R16G16B16A16Sfloat)?,
+				));
+			}
+		}
 
 		// TODO we probably need a pipeline barrier or whatever it's called here?
 
-		builder
-			.begin_render_pass(self.framebuffers_output[image_num].clone(), false, clear_values)
-			.unwrap()
-			.draw(
-				self.data.pipeline_output.clone(),
-				&self.data.dynamic_state,
-				vec![self.data.vertex_buffer_square.clone()],
-				self.data.descriptor_set_output.clone(),
-				push_constants
-			)
-			.unwrap()
-			.end_render_pass()
-			.unwrap();
+		self.data.record_output(
+			&mut builder,
+			&view_outputs,
+			self.screen_resolution,
+			&self.framebuffers_output[image_num],
+			&self.data.dynamic_state,
+			self.bar_color,
+		)?;
+
+		if self.capture_requests.contains(&CaptureTarget::Output) {
+			let image = self.swapchain_images[image_num].clone();
+			readbacks.push((
+				CaptureTarget::Output,
+				Readback::record(&mut builder, &self.device, image, [0, 0], self.swapchain.dimensions(), self.swapchain.format())?,
+			));
+		}
+		self.capture_requests.clear();
+
+		Ok((builder.build()?, readbacks))
+	}
 
-		builder.build().unwrap()
+	/// Errors from here mean the device has been lost or something similarly unrecoverable,
+	/// so there's not much to do other than report it and quit.
+	pub fn draw_frame(&mut self, frame: FrameBuilder, camera: &Camera) -> Result<(), RendererError> {
+		self.draw_views(camera.get_resolution(), vec![View::new(frame, camera)])
 	}
 
-	pub fn draw_frame(&mut self, mut frame: FrameBuilder, camera: &Camera) {
+	/// Draws several cameras' views at once, e.g. for split screen or a minimap. They're laid out on a screen
+	/// of `screen_resolution`, which is scaled up to the window the same way a single camera's view is.
+	/// See `View` for the details. Nothing is drawn if `screen_resolution` is empty.
+	pub fn draw_views(&mut self, screen_resolution: [u32; 2], views: Vec<View>) -> Result<(), RendererError> {
+		// Nothing to scale up, so skip the frame like when the window is minimised
+		if screen_resolution.contains(&0) {
+			return Ok(());
+		}
 		// Free resources that are no longer needed? :shrug:
-		self.previous_frame_end.as_mut().unwrap().cleanup_finished();
+		if let Some(previous_frame_end) = &mut self.previous_frame_end {
+			previous_frame_end.cleanup_finished();
+		}
 
 		if self.recreate_swapchain {
-			self.rebuild_swapchain();
+			self.rebuild_swapchain()?;
+		}
+
+		if screen_resolution != self.screen_resolution {
+			self.screen_resolution = screen_resolution;
+			Self::update_output_viewport(
+				self.swapchain.dimensions(), screen_resolution, &mut self.data.dynamic_state, self.scaling_mode);
+		}
+
+		if let Some(watcher) = &mut self.shader_watcher {
+			let changed = watcher.poll();
+			if !changed.is_empty() {
+				self.data.reload_shaders(&self.device, watcher.get_root(), &changed);
+			}
 		}
 
 		let (image_num, suboptimal, acquire_future) =
 			match swapchain::acquire_next_image(self.swapchain.clone(), None) {
 				Ok(r) => r,
-				Err(AcquireError::OutOfDate) => {
+				// Exclusive fullscreen can be taken away at any time, e.g. by alt-tabbing
+				Err(AcquireError::OutOfDate) | Err(AcquireError::FullscreenExclusiveLost) => {
 					self.recreate_swapchain = true;
-					return;
+					return Ok(());
 				}
-				Err(e) => panic!("Failed to acquire next swapchain image: {:?}", e)
+				Err(e) => return Err(e.into()),
 			};
 
 		if suboptimal {
 			self.recreate_swapchain = true;
 		}
 
-		let command_buffer = self.build_command_buffer(frame, camera, image_num);
+		let (command_buffer, readbacks) = self.build_command_buffer(views, image_num)?;
 
-		let future = self.previous_frame_end
-			.take()
-			.unwrap()
+		let future = self.take_previous_frame_end()
 			.join(acquire_future)
-			.then_execute(self.graphics_queue.clone(), command_buffer)
-			.unwrap()
+			.then_execute(self.graphics_queue.clone(), command_buffer)?
 			.then_swapchain_present(self.graphics_queue.clone(), self.swapchain.clone(), image_num)
 			.then_signal_fence_and_flush();
 
 		match future {
 			Ok(future) => {
+				if !readbacks.is_empty() {
+					// Readbacks are only valid once the GPU's done with this frame
+					future.wait(None)?;
+					self.captures.extend(readbacks.iter().map(|(target, readback)| (*target, readback.read())));
+				}
 				self.previous_frame_end = Some(future.boxed());
 			},
-			Err(FlushError::OutOfDate) => {
+			Err(FlushError::OutOfDate) | Err(FlushError::FullscreenExclusiveLost) => {
 				self.recreate_swapchain = true;
 				self.previous_frame_end = Some(sync::now(self.device.clone()).boxed());
 			},
 			Err(e) => {
-				println!("Failed to flush future: {:?}", e);
 				self.previous_frame_end = Some(sync::now(self.device.clone()).boxed());
+				return Err(e.into());
 			}
 		}
+		Ok(())
 	}
 }
@@ -0,0 +1,93 @@
+/// How the intermediate image is scaled up to fill the window.
+/// Any part of the window it doesn't cover is filled with the bar color.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ScalingMode {
+	/// Largest whole-number scale that fits, so every pixel is the same size.
+	/// Falls back to `Fit` if the window is smaller than the intermediate image.
+	#[default]
+	Integer,
+	/// As large as possible while keeping the aspect ratio. Pixels can end up slightly uneven.
+	Fit,
+	/// Fills the whole window, even if that means pixels aren't square.
+	Stretch,
+}
+
+/// Where the intermediate image ends up in the window, in window pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutputRect {
+	pub origin: [f32; 2],
+	pub dimensions: [f32; 2],
+}
+
+impl ScalingMode {
+	/// Works out where an image of size `source` goes in a window of size `target`.
+	/// Always centered, and always at whole-pixel positions.
+	pub fn output_rect(self, source: [u32; 2], target: [u32; 2]) -> OutputRect {
+		let scale = match self {
+			ScalingMode::Stretch => return OutputRect {
+				origin: [0.0, 0.0],
+				dimensions: [target[0] as f32, target[1] as f32],
+			},
+			ScalingMode::Integer => {
+				let scale = (target[0] / source[0]).min(target[1] / source[1]);
+				if scale == 0 {
+					return ScalingMode::Fit.output_rect(source, target);
+				}
+				scale as f32
+			},
+			ScalingMode::Fit => (target[0] as f32 / source[0] as f32).min(target[1] as f32 / source[1] as f32),
+		};
+		let width = (source[0] as f32 * scale).round();
+		let height = (source[1] as f32 * scale).round();
+		OutputRect {
+			origin: [
+				((target[0] as f32 - width) / 2.0).floor(),
+				((target[1] as f32 - height) / 2.0).floor(),
+			],
+			dimensions: [width, height],
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn rect(origin: [f32; 2], dimensions: [f32; 2]) -> OutputRect {
+		OutputRect { origin, dimensions }
+	}
+
+	#[test]
+	fn integer() {
+		// Exactly 4x
+		assert_eq!(ScalingMode::Integer.output_rect([320, 180], [1280, 720]), rect([0.0, 0.0], [1280.0, 720.0]));
+		// Not quite 5x, so 4x with the rest split evenly either side
+		assert_eq!(ScalingMode::Integer.output_rect([320, 180], [1366, 768]), rect([43.0, 24.0], [1280.0, 720.0]));
+		// Limited by the narrower axis, with bars left and right
+		assert_eq!(ScalingMode::Integer.output_rect([320, 180], [1000, 800]), rect([20.0, 130.0], [960.0, 540.0]));
+	}
+
+	#[test]
+	fn integer_falls_back_to_fit() {
+		let small_window = [300, 200];
+		assert_eq!(
+			ScalingMode::Integer.output_rect([320, 180], small_window),
+			ScalingMode::Fit.output_rect([320, 180], small_window));
+		// Too short, even though it's wide enough
+		assert_eq!(ScalingMode::Integer.output_rect([320, 180], [1280, 90]), rect([560.0, 0.0], [160.0, 90.0]));
+	}
+
+	#[test]
+	fn fit_letterboxes() {
+		// Wider window: bars left and right
+		assert_eq!(ScalingMode::Fit.output_rect([320, 180], [1000, 450]), rect([100.0, 0.0], [800.0, 450.0]));
+		// Taller window: bars top and bottom, rounded down to a whole pixel
+		assert_eq!(ScalingMode::Fit.output_rect([320, 180], [480, 301]), rect([0.0, 15.0], [480.0, 270.0]));
+		assert_eq!(ScalingMode::Fit.output_rect([320, 180], [300, 200]), rect([0.0, 15.0], [300.0, 169.0]));
+	}
+
+	#[test]
+	fn stretch() {
+		assert_eq!(ScalingMode::Stretch.output_rect([320, 180], [1000, 800]), rect([0.0, 0.0], [1000.0, 800.0]));
+	}
+}
@@ -0,0 +1,537 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use vulkano::descriptor::descriptor::{
+	DescriptorBufferDesc, DescriptorDescTy, DescriptorImageDesc, DescriptorImageDescArray, DescriptorImageDescDimensions,
+};
+use vulkano::descriptor::pipeline_layout::PipelineLayoutDesc;
+use vulkano::format::Format;
+use vulkano::pipeline::shader::ShaderInterfaceDef;
+
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+const HEADER_LENGTH: usize = 5;
+
+const OP_ENTRY_POINT: u32 = 15;
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_MATRIX: u32 = 24;
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLER: u32 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u32 = 27;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u32 = 29;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const OP_MEMBER_DECORATE: u32 = 72;
+
+const DECORATION_BUILT_IN: u32 = 11;
+const DECORATION_LOCATION: u32 = 30;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+const DECORATION_OFFSET: u32 = 35;
+
+const STORAGE_INPUT: u32 = 1;
+const STORAGE_OUTPUT: u32 = 3;
+const STORAGE_PUSH_CONSTANT: u32 = 9;
+const STORAGE_STORAGE_BUFFER: u32 = 12;
+
+const DIM_1D: u32 = 0;
+const DIM_2D: u32 = 1;
+const DIM_3D: u32 = 2;
+const DIM_CUBE: u32 = 3;
+const DIM_BUFFER: u32 = 5;
+const DIM_SUBPASS_DATA: u32 = 6;
+
+/// Everything about a shader that pipelines are built from: the inputs, outputs, descriptors and push constants.
+/// Pipelines get these from the code vulkano_shaders generated at compile time, so a hot reloaded shader
+/// has to have exactly the same ones as the version the game was built with.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ShaderInterface {
+	/// Locations and format of each input, in order of location
+	inputs: Vec<(Range<u32>, Format)>,
+	outputs: Vec<(Range<u32>, Format)>,
+	/// Set, binding, type and array count of each descriptor, in order of set and binding
+	descriptors: Vec<(u32, u32, DescriptorDescTy, u32)>,
+	/// In bytes, 0 if there aren't any
+	push_constants_size: usize,
+}
+
+impl ShaderInterface {
+	/// The interface vulkano_shaders generated for a built-in shader, from its `MainInput`, `MainOutput` and `Layout`.
+	pub fn generated(input: impl ShaderInterfaceDef, output: impl ShaderInterfaceDef, layout: impl PipelineLayoutDesc) -> Self {
+		let mut descriptors = Vec::new();
+		for set in 0..layout.num_sets() {
+			for binding in 0..layout.num_bindings_in_set(set).unwrap_or(0) {
+				if let Some(descriptor) = layout.descriptor(set, binding) {
+					descriptors.push((set as u32, binding as u32, descriptor.ty, descriptor.array_count));
+				}
+			}
+		}
+		Self {
+			inputs: sorted_by_location(input.elements().map(|element| (element.location, element.format)).collect()),
+			outputs: sorted_by_location(output.elements().map(|element| (element.location, element.format)).collect()),
+			descriptors,
+			push_constants_size: layout.push_constants_range(0).map_or(0, |range| range.size),
+		}
+	}
+
+	/// Reads the interface of the first entry point in a SPIR-V module, working it out the same way vulkano_shaders does.
+	pub fn parse(words: &[u32]) -> Result<Self, String> {
+		let module = Module::parse(words)?;
+		let mut inputs = Vec::new();
+		let mut outputs = Vec::new();
+		for &id in &module.entry_point_interface {
+			let &(pointer, storage) = module.variables.get(&id).ok_or("Entry point uses a missing variable")?;
+			let elements = match storage {
+				STORAGE_INPUT => &mut inputs,
+				STORAGE_OUTPUT => &mut outputs,
+				_ => continue,
+			};
+			if module.is_builtin(id) {
+				continue;
+			}
+			let location = module.decoration(id, DECORATION_LOCATION)
+				.ok_or_else(|| format!("Input or output {} has no location", id))?;
+			let (format, length) = module.format(module.pointee(pointer)?)?;
+			elements.push((location..location + length, format));
+		}
+		let mut descriptors = Vec::new();
+		for (&id, &(pointer, storage)) in &module.variables {
+			let Some(set) = module.decoration(id, DECORATION_DESCRIPTOR_SET) else { continue };
+			let binding = module.decoration(id, DECORATION_BINDING)
+				.ok_or_else(|| format!("Descriptor {} has no binding", id))?;
+			let (ty, array_count) = module.descriptor(module.pointee(pointer)?, storage, false)?;
+			descriptors.push((set, binding, ty, array_count));
+		}
+		descriptors.sort_by_key(|&(set, binding, _, _)| (set, binding));
+
+		let mut push_constants_size = 0;
+		for ty in module.types.values() {
+			if let Type::Pointer { storage: STORAGE_PUSH_CONSTANT, pointee } = *ty {
+				push_constants_size = push_constants_size.max(module.size(pointee)?);
+			}
+		}
+
+		Ok(Self {
+			inputs: sorted_by_location(inputs),
+			outputs: sorted_by_location(outputs),
+			descriptors,
+			push_constants_size,
+		})
+	}
+
+	/// Describes how `self` differs from `expected`, for error messages. `None` if they're the same.
+	pub fn difference(&self, expected: &ShaderInterface) -> Option<String> {
+		let mut differences = Vec::new();
+		if self.inputs != expected.inputs {
+			differences.push(format!("inputs {:?} should be {:?}", self.inputs, expected.inputs));
+		}
+		if self.outputs != expected.outputs {
+			differences.push(format!("outputs {:?} should be {:?}", self.outputs, expected.outputs));
+		}
+		if self.descriptors != expected.descriptors {
+			differences.push(format!("descriptors {:?} should be {:?}", self.descriptors, expected.descriptors));
+		}
+		if self.push_constants_size != expected.push_constants_size {
+			differences.push(format!("push constants are {} bytes but should be {}",
+				self.push_constants_size, expected.push_constants_size));
+		}
+		if differences.is_empty() { None } else { Some(differences.join("\n")) }
+	}
+}
+
+fn sorted_by_location(mut elements: Vec<(Range<u32>, Format)>) -> Vec<(Range<u32>, Format)> {
+	elements.sort_by_key(|(location, _)| location.start);
+	elements
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Type {
+	Int { width: u32, signed: bool },
+	Float { width: u32 },
+	Vector { component: u32, count: u32 },
+	Matrix { column: u32, count: u32 },
+	Image { dim: u32, arrayed: bool, multisampled: bool, sampled: u32 },
+	Sampler,
+	SampledImage { image: u32 },
+	Array { element: u32, length: u32 },
+	RuntimeArray { element: u32 },
+	Struct,
+	Pointer { storage: u32, pointee: u32 },
+}
+
+/// Just enough of a SPIR-V module to work out its interface.
+struct Module {
+	types: HashMap<u32, Type>,
+	struct_members: HashMap<u32, Vec<u32>>,
+	/// Only the low word, which is all array lengths need
+	constants: HashMap<u32, u32>,
+	/// Pointer type and storage class of each global variable
+	variables: HashMap<u32, (u32, u32)>,
+	/// The first operand of each decoration, or 0 for ones without any, by target and decoration
+	decorations: HashMap<(u32, u32), u32>,
+	/// Same as `decorations`, by struct, member and decoration
+	member_decorations: HashMap<(u32, u32, u32), u32>,
+	entry_point_interface: Vec<u32>,
+}
+
+impl Module {
+	fn parse(words: &[u32]) -> Result<Self, String> {
+		if words.len() < HEADER_LENGTH || words[0] != SPIRV_MAGIC {
+			return Err("Not a SPIR-V module".to_string());
+		}
+		let mut module = Module {
+			types: HashMap::new(),
+			struct_members: HashMap::new(),
+			constants: HashMap::new(),
+			variables: HashMap::new(),
+			decorations: HashMap::new(),
+			member_decorations: HashMap::new(),
+			entry_point_interface: Vec::new(),
+		};
+		let mut found_entry_point = false;
+
+		let mut rest = &words[HEADER_LENGTH..];
+		while let Some(&first) = rest.first() {
+			let (opcode, length) = (first & 0xffff, (first >> 16) as usize);
+			if length == 0 || length > rest.len() {
+				return Err("Truncated SPIR-V module".to_string());
+			}
+			let operands = &rest[1..length];
+			rest = &rest[length..];
+			let operand = |i: usize| operands.get(i).copied().ok_or_else(|| format!("Truncated instruction {}", opcode));
+
+			let ty = match opcode {
+				OP_ENTRY_POINT if !found_entry_point => {
+					// Execution model, function, then the name as a nul-terminated string padded to whole words
+					let name_words = operands.iter().skip(2).position(|word| word >> 24 == 0)
+						.ok_or("Truncated entry point")? + 1;
+					module.entry_point_interface = operands[2 + name_words..].to_vec();
+					found_entry_point = true;
+					None
+				},
+				OP_TYPE_INT => Some(Type::Int { width: operand(1)?, signed: operand(2)? == 1 }),
+				OP_TYPE_FLOAT => Some(Type::Float { width: operand(1)? }),
+				OP_TYPE_VECTOR => Some(Type::Vector { component: operand(1)?, count: operand(2)? }),
+				OP_TYPE_MATRIX => Some(Type::Matrix { column: operand(1)?, count: operand(2)? }),
+				OP_TYPE_IMAGE => Some(Type::Image {
+					dim: operand(2)?,
+					arrayed: operand(4)? == 1,
+					multisampled: operand(5)? == 1,
+					sampled: operand(6)?,
+				}),
+				OP_TYPE_SAMPLER => Some(Type::Sampler),
+				OP_TYPE_SAMPLED_IMAGE => Some(Type::SampledImage { image: operand(1)? }),
+				OP_TYPE_ARRAY => Some(Type::Array { element: operand(1)?, length: operand(2)? }),
+				OP_TYPE_RUNTIME_ARRAY => Some(Type::RuntimeArray { element: operand(1)? }),
+				OP_TYPE_STRUCT => {
+					module.struct_members.insert(operand(0)?, operands[1..].to_vec());
+					Some(Type::Struct)
+				},
+				OP_TYPE_POINTER => Some(Type::Pointer { storage: operand(1)?, pointee: operand(2)? }),
+				OP_CONSTANT => {
+					module.constants.insert(operand(1)?, operand(2)?);
+					None
+				},
+				OP_VARIABLE => {
+					module.variables.insert(operand(1)?, (operand(0)?, operand(2)?));
+					None
+				},
+				OP_DECORATE => {
+					module.decorations.insert((operand(0)?, operand(1)?), operands.get(2).copied().unwrap_or(0));
+					None
+				},
+				OP_MEMBER_DECORATE => {
+					module.member_decorations.insert(
+						(operand(0)?, operand(1)?, operand(2)?), operands.get(3).copied().unwrap_or(0));
+					None
+				},
+				_ => None,
+			};
+			if let Some(ty) = ty {
+				module.types.insert(operand(0)?, ty);
+			}
+		}
+
+		if !found_entry_point {
+			return Err("SPIR-V module has no entry point".to_string());
+		}
+		Ok(module)
+	}
+
+	fn decoration(&self, id: u32, decoration: u32) -> Option<u32> {
+		self.decorations.get(&(id, decoration)).copied()
+	}
+
+	fn ty(&self, id: u32) -> Result<Type, String> {
+		self.types.get(&id).copied().ok_or_else(|| format!("Missing type {}", id))
+	}
+
+	fn pointee(&self, pointer: u32) -> Result<u32, String> {
+		match self.ty(pointer)? {
+			Type::Pointer { pointee, .. } => Ok(pointee),
+			_ => Err(format!("Type {} isn't a pointer", pointer)),
+		}
+	}
+
+	fn constant(&self, id: u32) -> Result<u32, String> {
+		self.constants.get(&id).copied().ok_or_else(|| format!("Missing constant {}", id))
+	}
+
+	/// Whether a variable or type is built in, or contains something that is, like `gl_PerVertex`.
+	fn is_builtin(&self, id: u32) -> bool {
+		if self.decoration(id, DECORATION_BUILT_IN).is_some() {
+			return true;
+		}
+		match self.ty(id) {
+			Ok(Type::Pointer { pointee: inner, .. } | Type::Array { element: inner, .. } | Type::RuntimeArray { element: inner }) =>
+				self.is_builtin(inner),
+			Ok(Type::Struct) => {
+				let members = &self.struct_members[&id];
+				(0..members.len() as u32).any(|member| self.member_decorations.contains_key(&(id, member, DECORATION_BUILT_IN)))
+					|| members.iter().any(|&member| self.is_builtin(member))
+			},
+			Ok(_) => false,
+			// Variables aren't types, so go by their pointer type instead
+			Err(_) => self.variables.get(&id).is_some_and(|&(pointer, _)| self.is_builtin(pointer)),
+		}
+	}
+
+	/// Format of each location an input or output takes up, and how many locations that is.
+	fn format(&self, id: u32) -> Result<(Format, u32), String> {
+		match self.ty(id)? {
+			Type::Int { width: 32, signed: true } => Ok((Format::R32Sint, 1)),
+			Type::Int { width: 32, signed: false } => Ok((Format::R32Uint, 1)),
+			Type::Float { width: 32 } => Ok((Format::R32Sfloat, 1)),
+			Type::Float { width: 64 } => Ok((Format::R64Sfloat, 1)),
+			Type::Vector { component, count } => {
+				let (component, _) = self.format(component)?;
+				let format = match (component, count) {
+					(Format::R32Sfloat, 2) => Format::R32G32Sfloat,
+					(Format::R32Sfloat, 3) => Format::R32G32B32Sfloat,
+					(Format::R32Sfloat, 4) => Format::R32G32B32A32Sfloat,
+					(Format::R32Sint, 2) => Format::R32G32Sint,
+					(Format::R32Sint, 3) => Format::R32G32B32Sint,
+					(Format::R32Sint, 4) => Format::R32G32B32A32Sint,
+					(Format::R32Uint, 2) => Format::R32G32Uint,
+					(Format::R32Uint, 3) => Format::R32G32B32Uint,
+					(Format::R32Uint, 4) => Format::R32G32B32A32Uint,
+					_ => return Err(format!("Unsupported vector type {}", id)),
+				};
+				Ok((format, 1))
+			},
+			Type::Matrix { column, count } => {
+				let (format, length) = self.format(column)?;
+				Ok((format, length * count))
+			},
+			Type::Array { element, length } => {
+				let (format, element_length) = self.format(element)?;
+				Ok((format, element_length * self.constant(length)?))
+			},
+			_ => Err(format!("Unsupported input or output type {}", id)),
+		}
+	}
+
+	/// Descriptor type and array count, the same way vulkano_shaders decides them.
+	fn descriptor(&self, id: u32, storage: u32, combined_sampler: bool) -> Result<(DescriptorDescTy, u32), String> {
+		let ty = match self.ty(id)? {
+			Type::Struct => DescriptorDescTy::Buffer(DescriptorBufferDesc {
+				dynamic: Some(false),
+				storage: storage == STORAGE_STORAGE_BUFFER,
+			}),
+			Type::Image { dim, arrayed, multisampled, sampled } => {
+				let sampled = sampled == 1;
+				let array_layers = if arrayed {
+					DescriptorImageDescArray::Arrayed { max_layers: None }
+				} else {
+					DescriptorImageDescArray::NonArrayed
+				};
+				let dimensions = match dim {
+					DIM_SUBPASS_DATA => return Ok((DescriptorDescTy::InputAttachment { multisampled, array_layers }, 1)),
+					DIM_BUFFER => return Ok((DescriptorDescTy::TexelBuffer { storage: !sampled, format: None }, 1)),
+					DIM_1D => DescriptorImageDescDimensions::OneDimensional,
+					DIM_2D => DescriptorImageDescDimensions::TwoDimensional,
+					DIM_3D => DescriptorImageDescDimensions::ThreeDimensional,
+					DIM_CUBE => DescriptorImageDescDimensions::Cube,
+					_ => return Err(format!("Unsupported image type {}", id)),
+				};
+				let image = DescriptorImageDesc { sampled, dimensions, format: None, multisampled, array_layers };
+				if combined_sampler {
+					DescriptorDescTy::CombinedImageSampler(image)
+				} else {
+					DescriptorDescTy::Image(image)
+				}
+			},
+			Type::SampledImage { image } => return self.descriptor(image, storage, true),
+			Type::Sampler => DescriptorDescTy::Sampler,
+			Type::Array { element, length } => {
+				let (ty, _) = self.descriptor(element, storage, false)?;
+				return Ok((ty, self.constant(length)?));
+			},
+			_ => return Err(format!("Unsupported descriptor type {}", id)),
+		};
+		Ok((ty, 1))
+	}
+
+	/// Size in bytes of a type in a push constant block, laid out by its offsets.
+	fn size(&self, id: u32) -> Result<usize, String> {
+		match self.ty(id)? {
+			Type::Int { width, .. } | Type::Float { width } => Ok(width as usize / 8),
+			Type::Vector { component: inner, count } | Type::Matrix { column: inner, count } =>
+				Ok(self.size(inner)? * count as usize),
+			Type::Array { element, length } => Ok(self.size(element)? * self.constant(length)? as usize),
+			Type::Struct => {
+				let mut size = 0;
+				let mut alignment = 1;
+				for (i, &member) in self.struct_members[&id].iter().enumerate() {
+					let offset = self.member_decorations.get(&(id, i as u32, DECORATION_OFFSET))
+						.ok_or_else(|| format!("Member {} of struct {} has no offset", i, id))?;
+					size = size.max(*offset as usize + self.size(member)?);
+					alignment = alignment.max(self.alignment(member)?);
+				}
+				Ok(size.div_ceil(alignment) * alignment)
+			},
+			_ => Err(format!("Unsupported push constant type {}", id)),
+		}
+	}
+
+	fn alignment(&self, id: u32) -> Result<usize, String> {
+		match self.ty(id)? {
+			Type::Int { width, .. } | Type::Float { width } => Ok(width as usize / 8),
+			Type::Vector { component: inner, .. } | Type::Matrix { column: inner, .. } | Type::Array { element: inner, .. } =>
+				self.alignment(inner),
+			Type::Struct => {
+				let mut alignment = 1;
+				for &member in &self.struct_members[&id] {
+					alignment = alignment.max(self.alignment(member)?);
+				}
+				Ok(alignment)
+			},
+			_ => Err(format!("Unsupported push constant type {}", id)),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::fs;
+	use std::path::Path;
+	use std::sync::atomic::{AtomicUsize, Ordering};
+
+	use vulkano::descriptor::descriptor::ShaderStages;
+
+	use super::*;
+
+	/// Name, source path and generated interface of each built-in shader
+	macro_rules! built_in {
+		($($name:ident),* $(,)?) => {
+			vec![$((
+				stringify!($name),
+				shaders::$name::SOURCE_PATH,
+				ShaderInterface::generated(
+					shaders::$name::MainInput, shaders::$name::MainOutput, shaders::$name::Layout(ShaderStages::none())),
+			),)*]
+		};
+	}
+
+	fn built_in_shaders() -> Vec<(&'static str, &'static str, ShaderInterface)> {
+		built_in!(
+			vs_sprite,
+			vs_sprite_instanced,
+			vs_output,
+			fs_sprite,
+			fs_lighting,
+			fs_bloom,
+			fs_tonemap,
+			fs_color_grade,
+			fs_vignette,
+			fs_output,
+			vs_debug,
+			fs_debug,
+		)
+	}
+
+	fn source(path: &str) -> String {
+		fs::read_to_string(Path::new(shaders::SOURCE_ROOT).join(path)).unwrap()
+	}
+
+	/// Compiles an edited copy of a built-in shader, and returns how its interface differs from the original's
+	fn difference_after_edit(name: &str, edit: impl Fn(String) -> String) -> Option<String> {
+		let (_, path, expected) = built_in_shaders().into_iter().find(|&(n, _, _)| n == name).unwrap();
+		let edited = edit(source(path));
+		let extension = Path::new(path).extension().unwrap().to_str().unwrap();
+		// Tests run in parallel, so each edit needs its own file
+		static NEXT_FILE: AtomicUsize = AtomicUsize::new(0);
+		let edited_path = std::env::temp_dir().join(format!(
+			"shader_interface_test_{}_{}.{}", std::process::id(), NEXT_FILE.fetch_add(1, Ordering::Relaxed), extension));
+		fs::write(&edited_path, edited).unwrap();
+		let words = shaders::compile(&edited_path).unwrap();
+		ShaderInterface::parse(&words).unwrap().difference(&expected)
+	}
+
+	fn replace(from: &'static str, to: &'static str) -> impl Fn(String) -> String {
+		move |source| {
+			assert!(source.contains(from), "Shader doesn't contain {:?}", from);
+			source.replace(from, to)
+		}
+	}
+
+	#[test]
+	fn built_in_shaders_match() {
+		for (name, path, expected) in built_in_shaders() {
+			let words = shaders::compile(&Path::new(shaders::SOURCE_ROOT).join(path)).unwrap();
+			let parsed = ShaderInterface::parse(&words).unwrap_or_else(|e| panic!("{}: {}", name, e));
+			assert_eq!(parsed.difference(&expected), None, "{}", name);
+		}
+	}
+
+	#[test]
+	fn unchanged_interface_accepted() {
+		let difference = difference_after_edit("fs_bloom", replace("float sigma = max(float(radius) / 2.0, 0.5);",
+			"float sigma = max(float(radius) / 3.0, 0.5);"));
+		assert_eq!(difference, None);
+	}
+
+	#[test]
+	fn extra_uniform_rejected() {
+		let difference = difference_after_edit("fs_sprite", |source| {
+			let source = replace("layout(set = 0, binding = 0) uniform sampler2D atlas;",
+				"layout(set = 0, binding = 0) uniform sampler2D atlas;\nlayout(set = 0, binding = 1) uniform sampler2D extra;")(source);
+			replace("vec4 texel = texture(atlas, fragTexCoord);",
+				"vec4 texel = texture(atlas, fragTexCoord) * texture(extra, fragTexCoord);")(source)
+		});
+		assert!(difference.unwrap().starts_with("descriptors"));
+	}
+
+	#[test]
+	fn moved_output_rejected() {
+		let difference = difference_after_edit("fs_sprite",
+			replace("layout(location = 2) out vec4 f_occlusion;", "layout(location = 3) out vec4 f_occlusion;"));
+		assert!(difference.unwrap().starts_with("outputs"));
+	}
+
+	#[test]
+	fn changed_input_rejected() {
+		let difference = difference_after_edit("fs_sprite",
+			replace("layout(location = 1) in vec4 fragTint;", "layout(location = 1) in vec3 fragTint3;\nvec4 fragTint = vec4(fragTint3, 1.0);"));
+		assert!(difference.unwrap().starts_with("inputs"));
+	}
+
+	#[test]
+	fn push_constants_rejected() {
+		let difference = difference_after_edit("fs_bloom", replace("\tint radius;\n", "\tint radius;\n\tfloat extra;\n"));
+		assert_eq!(difference.unwrap(), "push constants are 16 bytes but should be 12");
+	}
+
+	#[test]
+	fn not_spirv() {
+		assert!(ShaderInterface::parse(&[]).is_err());
+		assert!(ShaderInterface::parse(&[0x1234_5678, 0, 0, 0, 0]).is_err());
+		// A valid header, then an instruction that claims to be longer than the module
+		assert!(ShaderInterface::parse(&[SPIRV_MAGIC, 0x0001_0000, 0, 1, 0, 10 << 16 | OP_DECORATE]).is_err());
+	}
+}
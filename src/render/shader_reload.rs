@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use vulkano::descriptor::descriptor::ShaderStages;
+use vulkano::device::Device;
+use vulkano::OomError;
+
+use crate::render::shader_interface::ShaderInterface;
+
+/// How often `ShaderWatcher` looks at the source files. Often enough to feel instant, rarely enough to not matter.
+const CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+macro_rules! shader_modules {
+	($($name:ident),* $(,)?) => {
+		/// Every shader the renderer uses, built in to start with, and replaced as they're hot reloaded.
+		pub(crate) struct ShaderModules {
+			$(pub $name: shaders::$name::Shader,)*
+		}
+
+		/// Every shader's name and source path, relative to `shaders::SOURCE_ROOT`
+		pub(crate) const SHADER_SOURCES: &[(&str, &str)] = &[$((stringify!($name), shaders::$name::SOURCE_PATH),)*];
+
+		impl ShaderModules {
+			/// The versions that were compiled into the game.
+			pub fn load(device: &Arc<Device>) -> Result<Self, OomError> {
+				Ok(Self {
+					$($name: shaders::$name::Shader::load(device.clone())?,)*
+				})
+			}
+
+			/// Compiles `name`'s source under `root` and swaps it in. On failure the current version is kept,
+			/// and the compiler's output is returned. So is a new version with a different interface to the
+			/// built-in one, since the pipelines would still be built for the old interface.
+			pub fn reload(&mut self, device: &Arc<Device>, root: &Path, name: &str) -> Result<(), String> {
+				match name {
+					$(stringify!($name) => {
+						let words = shaders::compile(&root.join(shaders::$name::SOURCE_PATH))?;
+						let expected = ShaderInterface::generated(
+							shaders::$name::MainInput, shaders::$name::MainOutput, shaders::$name::Layout(ShaderStages::none()));
+						if let Some(difference) = ShaderInterface::parse(&words)?.difference(&expected) {
+							return Err(format!("The interface has changed, which needs a rebuild:\n{}", difference));
+						}
+						// Safety: the interface was just checked against the built-in version's
+						self.$name = unsafe { shaders::$name::Shader::from_words(device.clone(), &words) }
+							.map_err(|e| e.to_string())?;
+					},)*
+					_ => return Err(format!("Unknown shader {}", name)),
+				}
+				Ok(())
+			}
+		}
+	};
+}
+
+shader_modules!(
+	vs_sprite,
+	vs_sprite_instanced,
+	vs_output,
+	fs_sprite,
+	fs_lighting,
+	fs_bloom,
+	fs_tonemap,
+	fs_color_grade,
+	fs_vignette,
+	fs_output,
+	vs_debug,
+	fs_debug,
+);
+
+/// Watches shader sources for changes, by checking modification times every so often.
+///
+/// Reloaded shaders have to keep the same interface as the version the game was built with: inputs, outputs,
+/// descriptors and push constants all come from the generated code, so changing them still needs a rebuild.
+/// Versions that change them are rejected, keeping the last working version.
+/// Everything else (the code in `main`, constants, helper functions) can change while the game is running.
+pub(crate) struct ShaderWatcher {
+	root: PathBuf,
+	/// When each source was last seen to change. Missing files are left out until they turn up.
+	modified: HashMap<&'static str, SystemTime>,
+	last_check: Instant,
+}
+
+impl ShaderWatcher {
+	/// Starts from the sources as they are now, so only later edits count as changes.
+	pub fn new(root: &Path) -> Self {
+		let mut watcher = Self {
+			root: root.to_path_buf(),
+			modified: HashMap::new(),
+			last_check: Instant::now(),
+		};
+		watcher.check();
+		watcher
+	}
+
+	pub fn get_root(&self) -> &Path { &self.root }
+
+	/// Names of shaders whose sources have changed since last time. Empty if it's been less than `CHECK_INTERVAL`.
+	pub fn poll(&mut self) -> Vec<&'static str> {
+		if self.last_check.elapsed() < CHECK_INTERVAL {
+			return Vec::new();
+		}
+		self.last_check = Instant::now();
+		self.check()
+	}
+
+	fn check(&mut self) -> Vec<&'static str> {
+		let mut changed = Vec::new();
+		for &(name, path) in SHADER_SOURCES {
+			// Editors often save by replacing the file, so it can be briefly missing; that's not a change
+			let Ok(modified) = self.root.join(path).metadata().and_then(|metadata| metadata.modified()) else { continue };
+			if self.modified.insert(name, modified).is_some_and(|previous| previous != modified) {
+				changed.push(name);
+			}
+		}
+		changed
+	}
+}
@@ -0,0 +1,358 @@
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::render::texture::ImageData;
+use crate::util::asset::{self, AssetError};
+
+/// Aseprite's default frame duration
+pub const DEFAULT_FRAME_DURATION_MS: u32 = 100;
+
+/// One named rectangle within a sprite sheet's image, in pixels.
+#[derive(Debug, Clone)]
+pub struct SpriteFrame {
+	pub name: String,
+	pub x: u32,
+	pub y: u32,
+	pub width: u32,
+	pub height: u32,
+	/// How long this frame shows for when it's part of an animation
+	pub duration_ms: u32,
+	/// Where the frame goes within `source_size`, in pixels from the bottom left (Y+ up, like the world).
+	/// Only nonzero for frames that had empty space trimmed off when they were exported.
+	pub offset: [i32; 2],
+	/// Size of the frame before it was trimmed. The same as `width` and `height` for untrimmed frames.
+	pub source_size: [u32; 2],
+}
+
+impl SpriteFrame {
+	/// An untrimmed frame
+	pub fn new(name: String, x: u32, y: u32, width: u32, height: u32, duration_ms: u32) -> Self {
+		Self {
+			name,
+			x,
+			y,
+			width,
+			height,
+			duration_ms,
+			offset: [0, 0],
+			source_size: [width, height],
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnimationDirection {
+	Forward,
+	Reverse,
+	/// Forward then back again, without repeating the frames at either end
+	PingPong,
+	/// Like `PingPong`, but starting from the end
+	PingPongReverse,
+}
+
+/// A named range of frames in a sheet, i.e. one animation. Matches Aseprite's frame tags.
+#[derive(Debug, Clone)]
+pub struct FrameTag {
+	pub name: String,
+	/// Index of the first frame, inclusive
+	pub from: usize,
+	/// Index of the last frame, inclusive
+	pub to: usize,
+	pub direction: AnimationDirection,
+	/// How many times to play before finishing. 0 loops forever.
+	pub repeat: u32,
+}
+
+/// An image cut up into frames. Doesn't do anything on its own; add it to an `AtlasBuilder`.
+#[derive(Debug, Clone)]
+pub struct SpriteSheet {
+	pub image: ImageData,
+	pub frames: Vec<SpriteFrame>,
+	/// Animations in this sheet. Empty for grid sheets unless added by hand.
+	pub tags: Vec<FrameTag>,
+}
+
+impl SpriteSheet {
+	/// Slices the image into a grid of `cell_width` x `cell_height` frames, named by their index
+	/// in row-major order ("0", "1", ...). Leftover pixels on the right and bottom are ignored.
+	pub fn from_grid(image: ImageData, cell_width: u32, cell_height: u32) -> Result<Self, AssetError> {
+		if cell_width == 0 || cell_height == 0 {
+			return Err(AssetError::Invalid(format!("Invalid sprite sheet cell size {}x{}", cell_width, cell_height)));
+		}
+		let columns = image.width / cell_width;
+		let rows = image.height / cell_height;
+		let frames = (0..rows*columns)
+			.map(|i| SpriteFrame::new(
+				i.to_string(),
+				(i % columns) * cell_width,
+				(i / columns) * cell_height,
+				cell_width,
+				cell_height,
+				DEFAULT_FRAME_DURATION_MS,
+			))
+			.collect();
+		Ok(Self {
+			image,
+			frames,
+			tags: Vec::new(),
+		})
+	}
+
+	/// Loads a sheet from the JSON written by Aseprite's "Export Sprite Sheet" or TexturePacker's
+	/// generic JSON exporter. Both the hash and array variants of `frames` are supported.
+	/// The image is loaded from `meta.image`, relative to the JSON file.
+	/// Frame durations and `meta.frameTags` are read if present (Aseprite writes them, TexturePacker doesn't).
+	/// Trimmed frames keep where they were in the untrimmed frame, so they're drawn in the right place.
+	///
+	/// Only exported JSON is supported, not `.aseprite` files themselves; export with something like
+	/// `aseprite -b file.aseprite --sheet file.png --data file.json --format json-array --list-tags`.
+	pub fn load_json(path: &Path) -> Result<Self, AssetError> {
+		let json: SheetJson = asset::load_json(path)?;
+		let image_path = path.parent().unwrap_or_else(|| Path::new("")).join(&json.meta.image);
+		let image = ImageData::load_png(&image_path)?;
+
+		let frames = json.frames.into_named()?
+			.into_iter()
+			.map(|(name, frame)| {
+				if frame.rotated {
+					return Err(AssetError::Invalid(
+						format!("{:?}: frame {} is rotated, which isn't supported", path, name)));
+				}
+				let rect = frame.frame;
+				if rect.x + rect.w > image.width || rect.y + rect.h > image.height {
+					return Err(AssetError::Invalid(
+						format!("{:?}: frame {} lies outside the image", path, name)));
+				}
+				let mut sprite_frame = SpriteFrame::new(
+					name, rect.x, rect.y, rect.w, rect.h, frame.duration.unwrap_or(DEFAULT_FRAME_DURATION_MS));
+				if let (Some(trimmed), Some(source)) = (&frame.sprite_source_size, &frame.source_size) {
+					if trimmed.x + trimmed.w > source.w || trimmed.y + trimmed.h > source.h {
+						return Err(AssetError::Invalid(
+							format!("{:?}: frame {} is trimmed to outside its source size", path, sprite_frame.name)));
+					}
+					// The JSON is Y+ down from the top left
+					sprite_frame.offset = [trimmed.x as i32, (source.h - trimmed.y - trimmed.h) as i32];
+					sprite_frame.source_size = [source.w, source.h];
+				}
+				Ok(sprite_frame)
+			})
+			.collect::<Result<Vec<_>, _>>()?;
+
+		let tags = json.meta.frame_tags.into_iter()
+			.map(|tag| {
+				if tag.from > tag.to || tag.to >= frames.len() {
+					return Err(AssetError::Invalid(
+						format!("{:?}: tag {} has invalid frame range {}..={}", path, tag.name, tag.from, tag.to)));
+				}
+				let direction = match tag.direction.as_str() {
+					"forward" => AnimationDirection::Forward,
+					"reverse" => AnimationDirection::Reverse,
+					"pingpong" => AnimationDirection::PingPong,
+					"pingpong_reverse" => AnimationDirection::PingPongReverse,
+					other => return Err(AssetError::Invalid(
+						format!("{:?}: tag {} has unknown direction {}", path, tag.name, other))),
+				};
+				// Aseprite writes this as a string, for some reason
+				let repeat = match &tag.repeat {
+					Some(repeat) => repeat.parse().map_err(|_| AssetError::Invalid(
+						format!("{:?}: tag {} has invalid repeat count {}", path, tag.name, repeat)))?,
+					None => 0,
+				};
+				Ok(FrameTag {
+					name: tag.name,
+					from: tag.from,
+					to: tag.to,
+					direction,
+					repeat,
+				})
+			})
+			.collect::<Result<Vec<_>, _>>()?;
+
+		Ok(Self { image, frames, tags })
+	}
+}
+
+#[derive(Deserialize)]
+pub(crate) struct SheetJson {
+	pub frames: FramesJson,
+	pub meta: MetaJson,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub(crate) enum FramesJson {
+	// Needs serde_json's preserve_order feature so frames stay in file order
+	Hash(serde_json::Map<String, serde_json::Value>),
+	Array(Vec<FrameJson>),
+}
+
+impl FramesJson {
+	pub fn into_named(self) -> Result<Vec<(String, FrameJson)>, AssetError> {
+		match self {
+			FramesJson::Hash(map) => map.into_iter()
+				.map(|(name, value)| Ok((name, serde_json::from_value(value)?)))
+				.collect(),
+			FramesJson::Array(frames) => frames.into_iter()
+				.map(|frame| {
+					let name = frame.filename.clone()
+						.ok_or_else(|| AssetError::Invalid("frame in array is missing filename".to_string()))?;
+					Ok((name, frame))
+				})
+				.collect(),
+		}
+	}
+}
+
+#[derive(Deserialize)]
+pub(crate) struct FrameJson {
+	pub filename: Option<String>,
+	pub frame: RectJson,
+	#[serde(default)]
+	pub rotated: bool,
+	pub duration: Option<u32>,
+	/// Where the trimmed frame was in the original, if it was trimmed
+	#[serde(rename = "spriteSourceSize")]
+	pub sprite_source_size: Option<RectJson>,
+	#[serde(rename = "sourceSize")]
+	pub source_size: Option<SizeJson>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct RectJson {
+	pub x: u32,
+	pub y: u32,
+	pub w: u32,
+	pub h: u32,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct SizeJson {
+	pub w: u32,
+	pub h: u32,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct MetaJson {
+	pub image: String,
+	#[serde(default, rename = "frameTags")]
+	pub frame_tags: Vec<FrameTagJson>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct FrameTagJson {
+	pub name: String,
+	pub from: usize,
+	pub to: usize,
+	#[serde(default = "default_direction")]
+	pub direction: String,
+	pub repeat: Option<String>,
+}
+
+fn default_direction() -> String { "forward".to_string() }
+
+#[cfg(test)]
+mod tests {
+	use std::fs;
+	use std::path::PathBuf;
+
+	use super::*;
+
+	/// Writes `json` and a 16x8 image for it to load into a fresh directory, and returns the JSON's path
+	fn write_fixture(name: &str, json: &str) -> PathBuf {
+		let dir = std::env::temp_dir().join(format!("sprite_sheet_test_{}_{}", std::process::id(), name));
+		fs::create_dir_all(&dir).unwrap();
+		ImageData::new(16, 8).save_png(&dir.join("sheet.png")).unwrap();
+		let path = dir.join("sheet.json");
+		fs::write(&path, json).unwrap();
+		path
+	}
+
+	#[test]
+	fn aseprite_hash_with_trimmed_frame() {
+		let path = write_fixture("hash", r#"{
+			"frames": {
+				"idle 0": { "frame": { "x": 0, "y": 0, "w": 8, "h": 8 }, "duration": 120 },
+				"idle 1": {
+					"frame": { "x": 8, "y": 0, "w": 4, "h": 6 },
+					"trimmed": true,
+					"spriteSourceSize": { "x": 2, "y": 1, "w": 4, "h": 6 },
+					"sourceSize": { "w": 8, "h": 8 }
+				}
+			},
+			"meta": {
+				"image": "sheet.png",
+				"frameTags": [{ "name": "idle", "from": 0, "to": 1, "direction": "pingpong", "repeat": "3" }]
+			}
+		}"#);
+		let sheet = SpriteSheet::load_json(&path).unwrap();
+
+		let names: Vec<_> = sheet.frames.iter().map(|frame| frame.name.as_str()).collect();
+		assert_eq!(names, ["idle 0", "idle 1"]);
+		let untrimmed = &sheet.frames[0];
+		assert_eq!(untrimmed.duration_ms, 120);
+		assert_eq!(untrimmed.offset, [0, 0]);
+		assert_eq!(untrimmed.source_size, [8, 8]);
+
+		let trimmed = &sheet.frames[1];
+		assert_eq!([trimmed.x, trimmed.y, trimmed.width, trimmed.height], [8, 0, 4, 6]);
+		assert_eq!(trimmed.duration_ms, DEFAULT_FRAME_DURATION_MS);
+		// 1 pixel trimmed off the top and 8 - 1 - 6 = 1 off the bottom, which is what the offset is measured from
+		assert_eq!(trimmed.offset, [2, 1]);
+		assert_eq!(trimmed.source_size, [8, 8]);
+
+		let tag = &sheet.tags[0];
+		assert_eq!((tag.name.as_str(), tag.from, tag.to), ("idle", 0, 1));
+		assert_eq!(tag.direction, AnimationDirection::PingPong);
+		assert_eq!(tag.repeat, 3);
+	}
+
+	#[test]
+	fn texture_packer_array() {
+		let path = write_fixture("array", r#"{
+			"frames": [
+				{ "filename": "b", "frame": { "x": 4, "y": 0, "w": 4, "h": 4 } },
+				{ "filename": "a", "frame": { "x": 0, "y": 0, "w": 4, "h": 4 } }
+			],
+			"meta": { "image": "sheet.png" }
+		}"#);
+		let sheet = SpriteSheet::load_json(&path).unwrap();
+		let names: Vec<_> = sheet.frames.iter().map(|frame| frame.name.as_str()).collect();
+		assert_eq!(names, ["b", "a"]);
+		assert!(sheet.tags.is_empty());
+	}
+
+	#[test]
+	fn invalid_frames() {
+		let outside = write_fixture("outside", r#"{
+			"frames": [{ "filename": "a", "frame": { "x": 12, "y": 0, "w": 8, "h": 8 } }],
+			"meta": { "image": "sheet.png" }
+		}"#);
+		assert!(SpriteSheet::load_json(&outside).is_err());
+
+		let bad_trim = write_fixture("bad_trim", r#"{
+			"frames": [{
+				"filename": "a",
+				"frame": { "x": 0, "y": 0, "w": 4, "h": 4 },
+				"spriteSourceSize": { "x": 6, "y": 0, "w": 4, "h": 4 },
+				"sourceSize": { "w": 8, "h": 8 }
+			}],
+			"meta": { "image": "sheet.png" }
+		}"#);
+		assert!(SpriteSheet::load_json(&bad_trim).is_err());
+
+		let bad_tag = write_fixture("bad_tag", r#"{
+			"frames": [{ "filename": "a", "frame": { "x": 0, "y": 0, "w": 4, "h": 4 } }],
+			"meta": { "image": "sheet.png", "frameTags": [{ "name": "walk", "from": 0, "to": 1 }] }
+		}"#);
+		assert!(SpriteSheet::load_json(&bad_tag).is_err());
+	}
+
+	#[test]
+	fn grid() {
+		let sheet = SpriteSheet::from_grid(ImageData::new(10, 8), 4, 4).unwrap();
+		let cells: Vec<_> = sheet.frames.iter().map(|frame| (frame.name.as_str(), frame.x, frame.y)).collect();
+		assert_eq!(cells, [("0", 0, 0), ("1", 4, 0), ("2", 0, 4), ("3", 4, 4)]);
+		assert!(SpriteSheet::from_grid(ImageData::new(10, 8), 0, 4).is_err());
+	}
+}
@@ -0,0 +1,115 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use crate::util::asset::AssetError;
+
+/// An RGBA8 image living on the CPU side, e.g. something loaded from a PNG that hasn't been
+/// packed into an atlas and uploaded yet.
+#[derive(Debug, Clone)]
+pub struct ImageData {
+	pub width: u32,
+	pub height: u32,
+	/// Row-major, top row first, 4 bytes per pixel.
+	pub pixels: Vec<u8>,
+}
+
+impl ImageData {
+	/// Creates a fully transparent image.
+	pub fn new(width: u32, height: u32) -> Self {
+		Self {
+			width,
+			height,
+			pixels: vec![0; (width * height * 4) as usize],
+		}
+	}
+
+	pub fn from_fn<F: Fn(u32, u32) -> [u8; 4]>(width: u32, height: u32, f: F) -> Self {
+		let mut image = Self::new(width, height);
+		for y in 0..height {
+			for x in 0..width {
+				image.set_pixel(x, y, f(x, y));
+			}
+		}
+		image
+	}
+
+	pub fn load_png(path: &Path) -> Result<Self, AssetError> {
+		let mut decoder = png::Decoder::new(File::open(path)?);
+		// Expand palettes and low bit depths, and throw away the extra precision of 16-bit images,
+		// so we only have to deal with 8 bits per channel below.
+		decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+		let (info, mut reader) = decoder.read_info()?;
+		let mut buf = vec![0; info.buffer_size()];
+		reader.next_frame(&mut buf)?;
+
+		let (color_type, _) = reader.output_color_type();
+		let pixels = match color_type {
+			png::ColorType::RGBA => buf,
+			png::ColorType::RGB => buf.chunks(3)
+				.flat_map(|p| vec![p[0], p[1], p[2], 255])
+				.collect(),
+			png::ColorType::GrayscaleAlpha => buf.chunks(2)
+				.flat_map(|p| vec![p[0], p[0], p[0], p[1]])
+				.collect(),
+			png::ColorType::Grayscale => buf.iter()
+				.flat_map(|&p| vec![p, p, p, 255])
+				.collect(),
+			png::ColorType::Indexed => return Err(AssetError::Invalid(
+				format!("{:?}: indexed PNG was not expanded", path))),
+		};
+
+		Ok(Self {
+			width: info.width,
+			height: info.height,
+			pixels,
+		})
+	}
+
+	pub fn save_png(&self, path: &Path) -> Result<(), AssetError> {
+		let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), self.width, self.height);
+		encoder.set_color(png::ColorType::RGBA);
+		encoder.set_depth(png::BitDepth::Eight);
+		encoder.write_header()?.write_image_data(&self.pixels)?;
+		Ok(())
+	}
+
+	fn index(&self, x: u32, y: u32) -> usize {
+		((y * self.width + x) * 4) as usize
+	}
+
+	pub fn get_pixel(&self, x: u32, y: u32) -> [u8; 4] {
+		let i = self.index(x, y);
+		[self.pixels[i], self.pixels[i+1], self.pixels[i+2], self.pixels[i+3]]
+	}
+
+	pub fn set_pixel(&mut self, x: u32, y: u32, color: [u8; 4]) {
+		let i = self.index(x, y);
+		self.pixels[i..i+4].copy_from_slice(&color);
+	}
+
+	/// Copies the whole of `src` into this image with its top-left corner at `x, y`.
+	/// Panics if it doesn't fit.
+	pub fn blit(&mut self, src: &ImageData, x: u32, y: u32) {
+		assert!(x + src.width <= self.width && y + src.height <= self.height,
+			"blit of {}x{} image at {}, {} out of bounds", src.width, src.height, x, y);
+		let row_len = (src.width * 4) as usize;
+		for row in 0..src.height {
+			let from = src.index(0, row);
+			let to = self.index(x, y + row);
+			self.pixels[to..to+row_len].copy_from_slice(&src.pixels[from..from+row_len]);
+		}
+	}
+
+	/// Copies out the rectangle with top-left corner `x, y` and the given size.
+	pub fn sub_image(&self, x: u32, y: u32, width: u32, height: u32) -> ImageData {
+		let mut out = ImageData::new(width, height);
+		let row_len = (width * 4) as usize;
+		for row in 0..height {
+			let from = self.index(x, y + row);
+			let to = out.index(0, row);
+			out.pixels[to..to+row_len].copy_from_slice(&self.pixels[from..from+row_len]);
+		}
+		out
+	}
+}
@@ -0,0 +1,315 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::render::animation::{self, Animation, AnimationFrame};
+use crate::render::atlas::AtlasRegion;
+use crate::render::camera::{Camera, PIXEL_OFFSET};
+use crate::render::display::{self, DrawOrder, FrameBuilder, RenderLayer};
+use crate::render::lighting::SpriteLighting;
+use crate::render::palette::SpriteEffects;
+use crate::render::vert::VertexSprite;
+
+/// Width and height of a chunk, in tiles
+pub const CHUNK_SIZE: u32 = 16;
+
+/// Index of a tile in its `Tileset`
+pub type TileId = u16;
+
+/// What a tile looks like.
+#[derive(Debug, Clone)]
+pub struct TileDef {
+	/// A single frame for a static tile. Tiles with more than one frame loop through them,
+	/// and get drawn as sprites every frame instead of going into a chunk's vertex buffer.
+	pub frames: Vec<AnimationFrame>,
+	pub lighting: SpriteLighting,
+}
+
+impl TileDef {
+	pub fn new(region: AtlasRegion) -> Self {
+		Self {
+			frames: vec![AnimationFrame { region, ticks: 1 }],
+			lighting: SpriteLighting::default(),
+		}
+	}
+
+	/// Loops forever, whatever the animation's repeat count says.
+	pub fn animated(animation: &Animation) -> Self {
+		assert!(!animation.frames.is_empty(), "Animated tile has no frames");
+		Self {
+			frames: animation.frames.clone(),
+			lighting: SpriteLighting::default(),
+		}
+	}
+
+	pub fn with_lighting(mut self, lighting: SpriteLighting) -> Self {
+		self.lighting = lighting;
+		self
+	}
+
+	pub fn is_animated(&self) -> bool { self.frames.len() > 1 }
+}
+
+/// The kinds of tile a `Tilemap` can use. Tiles are drawn at the size of their regions, which should be `tile_size`
+/// (or taller, for tiles that stick up into the one above).
+#[derive(Debug, Clone)]
+pub struct Tileset {
+	tile_size: [u32; 2],
+	tiles: Vec<TileDef>,
+}
+
+impl Tileset {
+	pub fn new(tile_size: [u32; 2]) -> Self {
+		Self {
+			tile_size,
+			tiles: Vec::new(),
+		}
+	}
+
+	pub fn add(&mut self, tile: TileDef) -> TileId {
+		self.tiles.push(tile);
+		(self.tiles.len() - 1) as TileId
+	}
+
+	pub fn get(&self, id: TileId) -> Option<&TileDef> {
+		self.tiles.get(id as usize)
+	}
+
+	pub fn get_tile_size(&self) -> [u32; 2] { self.tile_size }
+}
+
+static NEXT_MESH_ID: AtomicU64 = AtomicU64::new(0);
+
+/// The static tiles of one chunk, in world coordinates. Never changes once built; changing a tile builds
+/// a new one, so the renderer can keep a device-local copy for as long as the mesh is alive.
+pub(crate) struct ChunkMesh {
+	/// Unique for the life of the program, for the renderer to look up its copy by
+	pub id: u64,
+	pub vertices: Vec<VertexSprite>,
+	pub indices: Vec<u32>,
+}
+
+/// A chunk's mesh, to be drawn at the given draw order. See `FrameBuilder::draw_tilemap_chunk`.
+pub(crate) struct TilemapChunkDraw {
+	pub order: DrawOrder,
+	pub mesh: Arc<ChunkMesh>,
+}
+
+struct Chunk {
+	/// `None` if there aren't any static tiles in it
+	mesh: Option<Arc<ChunkMesh>>,
+	/// Tile coordinates and kinds of the animated tiles in the chunk
+	animated: Vec<([u32; 2], TileId)>,
+	dirty: bool,
+}
+
+/// A grid of tiles, drawn in `CHUNK_SIZE` chunks.
+/// Static tiles are uploaded to the GPU once per chunk and only rebuilt when a tile in that chunk changes,
+/// so a big level costs next to nothing to draw each frame. Chunks outside the camera's view are skipped.
+///
+/// Tile (0, 0) is the bottom left, with its corner at `origin` in the world.
+pub struct Tilemap {
+	tileset: Arc<Tileset>,
+	origin: [i32; 2],
+	/// In tiles
+	size: [u32; 2],
+	tiles: Vec<Option<TileId>>,
+	/// Row-major, bottom row first, like `tiles`
+	chunks: Vec<Chunk>,
+	chunk_counts: [u32; 2],
+	order: DrawOrder,
+	/// Ticks since the map was created, for animated tiles
+	ticks: u32,
+}
+
+impl Tilemap {
+	/// An empty map, `size` tiles across. Drawn in the background layer unless changed with `set_draw_order`.
+	pub fn new(tileset: Arc<Tileset>, size: [u32; 2], origin: [i32; 2]) -> Self {
+		let chunk_counts = [size[0].div_ceil(CHUNK_SIZE), size[1].div_ceil(CHUNK_SIZE)];
+		let chunks = (0..chunk_counts[0] * chunk_counts[1])
+			.map(|_| Chunk {
+				mesh: None,
+				animated: Vec::new(),
+				dirty: false,
+			})
+			.collect();
+		Self {
+			tileset,
+			origin,
+			size,
+			tiles: vec![None; (size[0] * size[1]) as usize],
+			chunks,
+			chunk_counts,
+			order: DrawOrder::new(RenderLayer::Background, 0),
+			ticks: 0,
+		}
+	}
+
+	/// Moving the map into or out of the UI layer rebuilds every chunk on the next `rebuild_chunks`,
+	/// since tiles there are unlit.
+	pub fn set_draw_order(&mut self, order: DrawOrder) {
+		if (order.layer == RenderLayer::Ui) != (self.order.layer == RenderLayer::Ui) {
+			for chunk in &mut self.chunks {
+				chunk.dirty = true;
+			}
+		}
+		self.order = order;
+	}
+
+	pub fn get_draw_order(&self) -> DrawOrder { self.order }
+
+	pub fn get_tileset(&self) -> &Arc<Tileset> { &self.tileset }
+
+	pub fn get_size(&self) -> [u32; 2] { self.size }
+
+	pub fn get_origin(&self) -> [i32; 2] { self.origin }
+
+	/// `None` for an empty tile, or one outside the map
+	pub fn get_tile(&self, x: u32, y: u32) -> Option<TileId> {
+		if x >= self.size[0] || y >= self.size[1] {
+			return None;
+		}
+		self.tiles[(y * self.size[0] + x) as usize]
+	}
+
+	/// Changes a tile. Its chunk gets rebuilt by the next `rebuild_chunks`.
+	pub fn set_tile(&mut self, x: u32, y: u32, tile: Option<TileId>) {
+		assert!(x < self.size[0] && y < self.size[1], "Tile ({}, {}) is outside the map", x, y);
+		let index = (y * self.size[0] + x) as usize;
+		if self.tiles[index] != tile {
+			self.tiles[index] = tile;
+			let chunk = self.chunk_index(x / CHUNK_SIZE, y / CHUNK_SIZE);
+			self.chunks[chunk].dirty = true;
+		}
+	}
+
+	/// Sets every tile in `min..max` (in tiles, max exclusive) at once.
+	pub fn fill(&mut self, min: [u32; 2], max: [u32; 2], tile: Option<TileId>) {
+		for y in min[1]..max[1].min(self.size[1]) {
+			for x in min[0]..max[0].min(self.size[0]) {
+				self.set_tile(x, y, tile);
+			}
+		}
+	}
+
+	/// Which tile a world position is in, if any.
+	pub fn world_to_tile(&self, x: i32, y: i32) -> Option<[u32; 2]> {
+		let [tile_width, tile_height] = self.tileset.tile_size;
+		let tile_x = (x - self.origin[0]).div_euclid(tile_width as i32);
+		let tile_y = (y - self.origin[1]).div_euclid(tile_height as i32);
+		if tile_x < 0 || tile_y < 0 || tile_x as u32 >= self.size[0] || tile_y as u32 >= self.size[1] {
+			return None;
+		}
+		Some([tile_x as u32, tile_y as u32])
+	}
+
+	/// Advances animated tiles by one tick, and rebuilds anything changed since last tick.
+	pub fn tick(&mut self) {
+		self.ticks = self.ticks.wrapping_add(1);
+		self.rebuild_chunks();
+	}
+
+	/// Rebuilds the chunks with tiles changed since they were last built.
+	/// `tick` does this anyway; call it directly if tiles change between the tick and drawing.
+	pub fn rebuild_chunks(&mut self) {
+		for chunk_y in 0..self.chunk_counts[1] {
+			for chunk_x in 0..self.chunk_counts[0] {
+				let index = self.chunk_index(chunk_x, chunk_y);
+				if self.chunks[index].dirty {
+					self.chunks[index] = self.build_chunk(chunk_x, chunk_y);
+				}
+			}
+		}
+	}
+
+	fn chunk_index(&self, chunk_x: u32, chunk_y: u32) -> usize {
+		(chunk_y * self.chunk_counts[0] + chunk_x) as usize
+	}
+
+	fn tile_pos(&self, x: u32, y: u32) -> [i32; 2] {
+		let [tile_width, tile_height] = self.tileset.tile_size;
+		[self.origin[0] + (x * tile_width) as i32, self.origin[1] + (y * tile_height) as i32]
+	}
+
+	fn build_chunk(&self, chunk_x: u32, chunk_y: u32) -> Chunk {
+		let mut vertices = Vec::new();
+		let mut indices = Vec::new();
+		let mut animated = Vec::new();
+		let min = [chunk_x * CHUNK_SIZE, chunk_y * CHUNK_SIZE];
+		let max = [(min[0] + CHUNK_SIZE).min(self.size[0]), (min[1] + CHUNK_SIZE).min(self.size[1])];
+		for y in min[1]..max[1] {
+			for x in min[0]..max[0] {
+				let Some(id) = self.get_tile(x, y) else { continue };
+				let tile = self.tileset.get(id).unwrap_or_else(|| panic!("Tile {} isn't in the tileset", id));
+				if tile.is_animated() {
+					animated.push(([x, y], id));
+					continue;
+				}
+				let [pos_x, pos_y] = self.tile_pos(x, y);
+				let region = &tile.frames[0].region;
+				let params = display::sprite_params(tile.lighting, SpriteEffects::default(), region.indexed, self.order.layer);
+				indices.extend_from_slice(&display::quad_indices(vertices.len() as u32));
+				vertices.extend_from_slice(&display::quad_vertices(
+					pos_x + region.offset[0], pos_y + region.offset[1], [region.width, region.height], [region.uv_min, region.uv_max], [255, 255, 255, 255], params));
+			}
+		}
+		let mesh = if vertices.is_empty() {
+			None
+		} else {
+			Some(Arc::new(ChunkMesh {
+				id: NEXT_MESH_ID.fetch_add(1, Ordering::Relaxed),
+				vertices,
+				indices,
+			}))
+		};
+		Chunk { mesh, animated, dirty: false }
+	}
+
+	/// Draws the chunks that are at least partly in view.
+	pub fn draw(&self, frame: &mut FrameBuilder, camera: &Camera) {
+		// Everything in the intermediate image, including the margin, since it can end up on screen
+		let [view_x, view_y] = camera.get_view_origin();
+		let view_min = [view_x - PIXEL_OFFSET[0] as i32, view_y - PIXEL_OFFSET[1] as i32];
+		let [full_width, full_height] = camera.get_full_resolution();
+		let view_max = [view_min[0] + full_width as i32, view_min[1] + full_height as i32];
+
+		let [tile_width, tile_height] = self.tileset.tile_size;
+		let chunk_size = [(CHUNK_SIZE * tile_width) as i32, (CHUNK_SIZE * tile_height) as i32];
+		let chunk_range = |axis: usize, count: u32| {
+			let first = (view_min[axis] - self.origin[axis]).div_euclid(chunk_size[axis]).max(0);
+			let last = (view_max[axis] - 1 - self.origin[axis]).div_euclid(chunk_size[axis]).min(count as i32 - 1);
+			first..=last
+		};
+
+		let sprite_renderer = frame.get_sprite_renderer();
+		let (previous_order, previous_lighting) = (sprite_renderer.get_draw_order(), sprite_renderer.get_sprite_lighting());
+		// Chunk meshes are built once, so tiles always use the first palette, and animated ones have to match
+		let previous_effects = sprite_renderer.get_sprite_effects();
+		sprite_renderer.set_sprite_effects(SpriteEffects::default());
+		for chunk_y in chunk_range(1, self.chunk_counts[1]) {
+			for chunk_x in chunk_range(0, self.chunk_counts[0]) {
+				let chunk = &self.chunks[self.chunk_index(chunk_x as u32, chunk_y as u32)];
+				if let Some(mesh) = &chunk.mesh {
+					frame.draw_tilemap_chunk(TilemapChunkDraw {
+						order: self.order,
+						mesh: mesh.clone(),
+					});
+				}
+
+				let sprite_renderer = frame.get_sprite_renderer();
+				sprite_renderer.set_draw_order(self.order);
+				for &([x, y], id) in &chunk.animated {
+					let tile = &self.tileset.tiles[id as usize];
+					let [pos_x, pos_y] = self.tile_pos(x, y);
+					sprite_renderer.set_sprite_lighting(tile.lighting);
+					// All tiles of the same kind animate in sync
+					let frame = animation::looping_frame_at(&tile.frames, self.ticks);
+					sprite_renderer.draw_sprite(&frame.region, pos_x, pos_y);
+				}
+			}
+		}
+		let sprite_renderer = frame.get_sprite_renderer();
+		sprite_renderer.set_draw_order(previous_order);
+		sprite_renderer.set_sprite_lighting(previous_lighting);
+		sprite_renderer.set_sprite_effects(previous_effects);
+	}
+}
@@ -15,7 +15,30 @@ pub struct VertexSprite {
 	pub position: [f32; 3], // 12 bytes
 	pub uv: [f32; 2], // 12 + 8 = 20 bytes
 	pub tint: [u8; 4], // 20 + 4 = 24 bytes
-	// TODO other properties like emissivity, light occlusion and such
-	// will probably want to compress UVs so that the entire vert fits in 32 bytes in that case
+	pub params: [u8; 4], // emissive, flags, palette, flash; 24 + 4 = 28 bytes
+	// will probably want to compress UVs if we need more than 32 bytes
 }
-vulkano::impl_vertex!(VertexSprite, position, uv, tint);
+vulkano::impl_vertex!(VertexSprite, position, uv, tint, params);
+
+/// One sprite drawn through `InstancedSpriteRenderer`, expanded into a quad by vs_sprite_instanced.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct InstanceSprite {
+	/// World position of the bottom left corner
+	pub origin: [f32; 2], // 8 bytes
+	/// In pixels
+	pub size: [f32; 2], // 16 bytes
+	pub uv_min: [f32; 2], // 24 bytes
+	pub uv_max: [f32; 2], // 32 bytes
+	pub tint: [u8; 4], // 36 bytes
+	/// Same as `VertexSprite::params`
+	pub params: [u8; 4], // 40 bytes
+}
+vulkano::impl_vertex!(InstanceSprite, origin, size, uv_min, uv_max, tint, params);
+
+/// For the debug overlay. Positions are already in normalized device coordinates.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct VertexDebug {
+	pub position: [f32; 2],
+	pub color: [u8; 4],
+}
+vulkano::impl_vertex!(VertexDebug, position, color);
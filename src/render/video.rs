@@ -0,0 +1,126 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use vulkano::swapchain::{FullscreenExclusive, PresentMode, SupportedPresentModes};
+
+use crate::util::asset::{self, AssetError};
+
+/// Where video settings are saved between runs, relative to the working directory
+pub const VIDEO_SETTINGS_PATH: &str = "video_settings.json";
+
+/// How frames are handed to the screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum PresentModePreference {
+	/// Waits for the monitor's refresh. No tearing, and caps the framerate at the refresh rate.
+	/// Always supported.
+	#[default]
+	Vsync,
+	/// No tearing, but doesn't wait: newer frames replace ones still waiting to be shown.
+	/// Lower latency than vsync, at the cost of drawing frames that never get seen.
+	/// Falls back to vsync if unsupported.
+	Mailbox,
+	/// Shows frames as soon as they're done, which can tear.
+	/// Falls back to mailbox, then vsync, if unsupported.
+	Immediate,
+}
+
+impl PresentModePreference {
+	/// The closest present mode to this one that's actually supported.
+	pub fn choose(self, supported: SupportedPresentModes) -> PresentMode {
+		let candidates: &[PresentMode] = match self {
+			PresentModePreference::Vsync => &[],
+			PresentModePreference::Mailbox => &[PresentMode::Mailbox],
+			PresentModePreference::Immediate => &[PresentMode::Immediate, PresentMode::Mailbox],
+		};
+		candidates.iter()
+			.copied()
+			.find(|&mode| supported.supports(mode))
+			// FIFO is the only mode that's guaranteed to be there
+			.unwrap_or(PresentMode::Fifo)
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum WindowMode {
+	#[default]
+	Windowed,
+	/// A borderless window covering the whole monitor. Switches in and out quickly,
+	/// and plays nicely with alt-tab.
+	Borderless,
+	/// Takes over the monitor at its highest resolution and refresh rate.
+	/// Can be faster than borderless on some platforms, but switching is slow.
+	Exclusive,
+}
+
+impl WindowMode {
+	/// What Alt+Enter switches to: fullscreen from windowed, and windowed from either fullscreen mode.
+	pub fn toggled(self) -> Self {
+		match self {
+			WindowMode::Windowed => WindowMode::Borderless,
+			WindowMode::Borderless | WindowMode::Exclusive => WindowMode::Windowed,
+		}
+	}
+
+	/// Only has an effect if the driver supports `VK_EXT_full_screen_exclusive`, which is Windows-only.
+	pub(crate) fn fullscreen_exclusive(self) -> FullscreenExclusive {
+		match self {
+			WindowMode::Exclusive => FullscreenExclusive::Allowed,
+			WindowMode::Windowed | WindowMode::Borderless => FullscreenExclusive::Default,
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WindowSettings {
+	pub mode: WindowMode,
+	/// Which monitor to go fullscreen on, as an index into the list of monitors.
+	/// `None` means whichever one the window is currently on.
+	pub monitor: Option<usize>,
+	/// Size and position when windowed, in physical pixels. Kept up to date as the window is moved and resized,
+	/// so it comes back in the same place next run.
+	pub size: [u32; 2],
+	/// `None` leaves it up to the OS
+	pub position: Option<[i32; 2]>,
+}
+
+impl Default for WindowSettings {
+	fn default() -> Self {
+		Self {
+			mode: WindowMode::Windowed,
+			monitor: None,
+			size: [1280, 720],
+			position: None,
+		}
+	}
+}
+
+/// Settings that affect how (and where) frames get drawn, as opposed to what's in them.
+/// Saved as JSON, and anything missing from the file is left at its default.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VideoSettings {
+	/// Device index or part of a device name to use instead of the best one available.
+	/// See `device::select_physical_device`.
+	pub device: Option<String>,
+	pub present_mode: PresentModePreference,
+	/// Frames per second to aim for, or `None` for as fast as possible.
+	/// With vsync a cap above the refresh rate just burns CPU time, so it's usually best left unlimited.
+	pub target_fps: Option<f64>,
+	pub window: WindowSettings,
+}
+
+impl VideoSettings {
+	pub fn load(path: &Path) -> Result<Self, AssetError> {
+		asset::load_json(path)
+	}
+
+	pub fn save(&self, path: &Path) -> Result<(), AssetError> {
+		asset::save_json(path, self)
+	}
+
+	/// The target FPS as a rate to pass to `spin_sleep::LoopHelper`, where infinity means unlimited.
+	pub fn target_rate(&self) -> f64 {
+		self.target_fps.unwrap_or(f64::INFINITY)
+	}
+}
@@ -0,0 +1,160 @@
+use std::sync::{Arc, Weak};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::render::camera::Camera;
+use crate::render::display::{FrameBuilder, RenderLayer};
+
+/// A set of render layers, for picking which ones a view draws.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LayerMask(u32);
+
+impl LayerMask {
+	pub const ALL: LayerMask = LayerMask(!0);
+	pub const NONE: LayerMask = LayerMask(0);
+
+	pub fn only(layer: RenderLayer) -> Self {
+		Self::NONE.with(layer)
+	}
+
+	pub fn with(self, layer: RenderLayer) -> Self {
+		LayerMask(self.0 | Self::bit(layer))
+	}
+
+	pub fn without(self, layer: RenderLayer) -> Self {
+		LayerMask(self.0 & !Self::bit(layer))
+	}
+
+	pub fn contains(self, layer: RenderLayer) -> bool {
+		self.0 & Self::bit(layer) != 0
+	}
+
+	fn bit(layer: RenderLayer) -> u32 {
+		1 << layer as u32
+	}
+}
+
+impl Default for LayerMask {
+	fn default() -> Self { Self::ALL }
+}
+
+/// Part of the screen, in screen pixels from the bottom left (Y+ up, like `DebugSpace::Screen`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScreenRect {
+	pub pos: [i32; 2],
+	pub size: [u32; 2],
+}
+
+impl ScreenRect {
+	pub fn new(pos: [i32; 2], size: [u32; 2]) -> Self {
+		Self { pos, size }
+	}
+
+	/// The whole of a screen of this size
+	pub fn full(screen_resolution: [u32; 2]) -> Self {
+		Self::new([0, 0], screen_resolution)
+	}
+
+	/// Splits a screen into a grid, e.g. 2x1 for side by side split screen. In reading order, so
+	/// the top left cell comes first. Cells are whole pixels, so any leftover pixels are left uncovered.
+	pub fn split(screen_resolution: [u32; 2], columns: u32, rows: u32) -> Vec<Self> {
+		assert!(columns > 0 && rows > 0, "Can't split a screen into {}x{} cells", columns, rows);
+		let size = [screen_resolution[0] / columns, screen_resolution[1] / rows];
+		(0..rows).rev()
+			.flat_map(|row| (0..columns).map(move |column|
+				Self::new([(column * size[0]) as i32, (row * size[1]) as i32], size)))
+			.collect()
+	}
+}
+
+static NEXT_RENDER_TEXTURE_ID: AtomicU64 = AtomicU64::new(0);
+
+/// An offscreen image that views can draw into, and sprites can then be drawn with, for things like minimaps,
+/// reflections, portals and UI previews. See `View::to_texture` and `SpriteRenderer::draw_render_texture`.
+///
+/// The renderer makes the image the first time a view draws into it, and frees it once every clone of this
+/// has been dropped. It keeps whatever was last drawn into it until then, so something that rarely changes
+/// only needs drawing again when it does.
+#[derive(Debug, Clone)]
+pub struct RenderTexture(Arc<RenderTextureInfo>);
+
+#[derive(Debug)]
+pub(crate) struct RenderTextureInfo {
+	/// Unique for the life of the program, for the renderer to look up the image by
+	pub id: u64,
+	pub resolution: [u32; 2],
+}
+
+impl RenderTexture {
+	pub fn new(resolution: [u32; 2]) -> Self {
+		assert!(resolution[0] > 0 && resolution[1] > 0, "Invalid render texture resolution {:?}", resolution);
+		Self(Arc::new(RenderTextureInfo {
+			id: NEXT_RENDER_TEXTURE_ID.fetch_add(1, Ordering::Relaxed),
+			resolution,
+		}))
+	}
+
+	pub fn get_resolution(&self) -> [u32; 2] { self.0.resolution }
+
+	pub(crate) fn get_id(&self) -> u64 { self.0.id }
+
+	pub(crate) fn downgrade(&self) -> Weak<RenderTextureInfo> {
+		Arc::downgrade(&self.0)
+	}
+}
+
+/// Where a view draws to.
+#[derive(Debug, Clone)]
+pub enum ViewTarget {
+	/// Part of the screen
+	Screen(ScreenRect),
+	/// All of a render texture. These views are drawn before any on the screen, so they can use the texture straight away.
+	Texture(RenderTexture),
+}
+
+/// One camera's picture of the world, and where on the screen it goes. Each view gets its own frame,
+/// since anything that depends on the camera (parallax, tilemap culling, HUDs) can differ between them.
+///
+/// Views are composited in the order given, so later ones go on top, e.g. for picture-in-picture.
+/// A view's camera can have a different resolution to its target, in which case it's scaled to fit;
+/// a minimap might show a large area in a small corner of the screen.
+pub struct View<'a> {
+	pub frame: FrameBuilder,
+	pub camera: &'a Camera,
+	pub target: ViewTarget,
+	/// Anything drawn in a layer outside this is skipped, e.g. so a minimap doesn't show the HUD
+	pub layers: LayerMask,
+}
+
+impl<'a> View<'a> {
+	/// Fills a screen the size of the camera's resolution with every layer, which is what `Renderer::draw_frame` does.
+	pub fn new(frame: FrameBuilder, camera: &'a Camera) -> Self {
+		Self {
+			frame,
+			camera,
+			target: ViewTarget::Screen(ScreenRect::full(camera.get_resolution())),
+			layers: LayerMask::ALL,
+		}
+	}
+
+	/// Draws into a render texture instead of onto the screen. The texture gets the lit image without any
+	/// post-processing, which is applied once the texture is drawn, by the view drawing it. A view can't draw
+	/// sprites with its own texture; those are skipped.
+	pub fn to_texture(frame: FrameBuilder, camera: &'a Camera, texture: &RenderTexture) -> Self {
+		Self {
+			frame,
+			camera,
+			target: ViewTarget::Texture(texture.clone()),
+			layers: LayerMask::ALL,
+		}
+	}
+
+	pub fn with_rect(mut self, rect: ScreenRect) -> Self {
+		self.target = ViewTarget::Screen(rect);
+		self
+	}
+
+	pub fn with_layers(mut self, layers: LayerMask) -> Self {
+		self.layers = layers;
+		self
+	}
+}
@@ -0,0 +1,64 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Something went wrong while loading an asset from disk (or occasionally saving one).
+#[derive(Debug)]
+pub enum AssetError {
+	Io(io::Error),
+	Png(png::DecodingError),
+	PngEncoding(png::EncodingError),
+	GifEncoding(gif::EncodingError),
+	Json(serde_json::Error),
+	/// The file was read fine, but its contents don't make sense to us.
+	Invalid(String),
+}
+
+impl fmt::Display for AssetError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			AssetError::Io(e) => write!(f, "IO error: {}", e),
+			AssetError::Png(e) => write!(f, "PNG decoding error: {}", e),
+			AssetError::PngEncoding(e) => write!(f, "PNG encoding error: {}", e),
+			AssetError::GifEncoding(e) => write!(f, "GIF encoding error: {}", e),
+			AssetError::Json(e) => write!(f, "JSON error: {}", e),
+			AssetError::Invalid(msg) => write!(f, "Invalid asset: {}", msg),
+		}
+	}
+}
+
+impl std::error::Error for AssetError {}
+
+impl From<io::Error> for AssetError {
+	fn from(e: io::Error) -> Self { AssetError::Io(e) }
+}
+
+impl From<png::DecodingError> for AssetError {
+	fn from(e: png::DecodingError) -> Self { AssetError::Png(e) }
+}
+
+impl From<png::EncodingError> for AssetError {
+	fn from(e: png::EncodingError) -> Self { AssetError::PngEncoding(e) }
+}
+
+impl From<gif::EncodingError> for AssetError {
+	fn from(e: gif::EncodingError) -> Self { AssetError::GifEncoding(e) }
+}
+
+impl From<serde_json::Error> for AssetError {
+	fn from(e: serde_json::Error) -> Self { AssetError::Json(e) }
+}
+
+/// Reads a JSON file and deserializes it into `T`.
+pub fn load_json<T: serde::de::DeserializeOwned>(path: &Path) -> Result<T, AssetError> {
+	let text = fs::read_to_string(path)?;
+	Ok(serde_json::from_str(&text)?)
+}
+
+/// Serializes `value` to a JSON file, pretty-printed so it can be edited by hand.
+pub fn save_json<T: serde::Serialize>(path: &Path, value: &T) -> Result<(), AssetError> {
+	let text = serde_json::to_string_pretty(value)?;
+	fs::write(path, text)?;
+	Ok(())
+}
@@ -1,2 +1,4 @@
 pub mod timing;
 pub mod input;
+pub mod asset;
+pub mod random;
@@ -0,0 +1,36 @@
+/// A small seeded random number generator (xorshift64*), for anything that has to come out the same
+/// every run given the same seed, like particles. Not remotely suitable for anything security related.
+#[derive(Debug, Clone)]
+pub struct Rng {
+	state: u64,
+}
+
+impl Rng {
+	pub fn new(seed: u64) -> Self {
+		// xorshift gets stuck on zero, and similar seeds give similar first numbers,
+		// so scramble the seed with a round of splitmix64 first
+		let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+		z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+		z ^= z >> 31;
+		Self { state: if z == 0 { 1 } else { z } }
+	}
+
+	pub fn next_u32(&mut self) -> u32 {
+		self.state ^= self.state >> 12;
+		self.state ^= self.state << 25;
+		self.state ^= self.state >> 27;
+		(self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 32) as u32
+	}
+
+	/// Uniform in `0.0..1.0`
+	pub fn next_f32(&mut self) -> f32 {
+		// 24 bits is all an f32 can hold exactly
+		(self.next_u32() >> 8) as f32 / (1 << 24) as f32
+	}
+
+	/// Uniform in `min..max`. Fine to call with `min == max`.
+	pub fn range(&mut self, min: f32, max: f32) -> f32 {
+		min + (max - min) * self.next_f32()
+	}
+}
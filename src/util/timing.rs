@@ -1,5 +1,15 @@
 use std::time::Duration;
 
+/// Game ticks are fixed length; anything that needs to be deterministic (animations etc.) counts ticks, not time.
+pub const TICKS_PER_SECOND: u32 = 60;
+
+/// Converts a duration in milliseconds to a whole number of ticks, rounding to the nearest tick (minimum 1).
+pub fn ms_to_ticks(ms: u32) -> u32 {
+	// In u64, since ms * TICKS_PER_SECOND overflows u32 for anything over about 19 hours
+	let ticks = (ms as u64 * TICKS_PER_SECOND as u64 + 500) / 1000;
+	(ticks as u32).max(1)
+}
+
 pub struct TickTiming {
 	tick_duration: f64,
 	partial_ticks: f64,
@@ -35,3 +45,18 @@ impl TickTiming {
 		self.partial_ticks
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn ms_to_ticks_rounds_to_nearest() {
+		assert_eq!(ms_to_ticks(1000), 60);
+		assert_eq!(ms_to_ticks(25), 2);
+		assert_eq!(ms_to_ticks(24), 1);
+		assert_eq!(ms_to_ticks(0), 1);
+		// Overflows u32 if it isn't widened first
+		assert_eq!(ms_to_ticks(u32::MAX), 257_698_038);
+	}
+}